@@ -0,0 +1,44 @@
+// Times update_camera_system over many cameras. Compare the serial and rayon paths with
+// `cargo bench --bench camera_system` with and without `--features parallel_systems`
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use cgmath::Point3;
+use fps_engine::{update_camera_system, Camera, CameraController, Input, Transform};
+use hecs::World;
+
+const CAMERA_COUNT: usize = 64;
+const WARMUP_UPDATES: u32 = 100;
+const UPDATES: u32 = 10_000;
+
+fn main() {
+    let mut world = World::new();
+    for i in 0..CAMERA_COUNT {
+        let transform = Transform {
+            position: Point3::new(i as f32, 0.0, 0.0),
+            ..Default::default()
+        };
+        world.spawn((transform, Camera::default(), CameraController::default()));
+    }
+    let input = Input::default();
+    let dt = Duration::from_secs(1) / 60;
+
+    for _ in 0..WARMUP_UPDATES {
+        update_camera_system(&mut world, &input, dt);
+    }
+    let start = Instant::now();
+    for _ in 0..UPDATES {
+        update_camera_system(black_box(&mut world), &input, dt);
+    }
+    let elapsed = start.elapsed();
+
+    let mode = if cfg!(feature = "parallel_systems") {
+        "parallel"
+    } else {
+        "serial"
+    };
+    println!(
+        "update_camera_system ({mode}, {CAMERA_COUNT} cameras): {:?} per update",
+        elapsed / UPDATES
+    );
+}
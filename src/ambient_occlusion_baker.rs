@@ -0,0 +1,122 @@
+use crate::vertex::Vertex;
+use crate::{MeshBvh, Ray3};
+use cgmath::{InnerSpace, Point3, Vector3};
+use std::f32::consts::PI;
+
+// Occluders further away than this fraction of the mesh's bounds diagonal don't darken
+const MAX_DISTANCE_FRACTION: f32 = 0.25;
+// Rays start this fraction of the diagonal off the surface so they miss their own triangles
+const RAY_BIAS_FRACTION: f32 = 1e-4;
+
+/// Bakes the open fraction of the hemisphere above each vertex, 1 where nothing in the
+/// mesh blocks the sky and 0 where it's fully enclosed. Rays are cast against the
+/// mesh's `MeshBvh`, built from the same vertices
+pub struct AmbientOcclusionBaker;
+
+impl AmbientOcclusionBaker {
+    pub fn bake(vertices: &[Vertex], bvh: &MeshBvh, rays: u32) -> Vec<f32> {
+        let Some(root) = bvh.nodes.first() else {
+            return vec![1.0; vertices.len()];
+        };
+        if rays == 0 {
+            return vec![1.0; vertices.len()];
+        }
+
+        let diagonal = (root.max - root.min).magnitude();
+        let max_distance = diagonal * MAX_DISTANCE_FRACTION;
+        let bias = diagonal * RAY_BIAS_FRACTION;
+
+        vertices
+            .iter()
+            .map(|vertex| {
+                let normal = Vector3::from(vertex.normal);
+                if normal.magnitude2() <= f32::EPSILON {
+                    return 1.0;
+                }
+                let normal = normal.normalize();
+                let origin = Point3::from(vertex.position) + normal * bias;
+                let hits = (0..rays)
+                    .filter(|&i| {
+                        let ray = Ray3::new(origin, hemisphere_direction(normal, i, rays));
+                        bvh.intersect(ray)
+                            .is_some_and(|hit| hit.distance <= max_distance)
+                    })
+                    .count();
+                1.0 - hits as f32 / rays as f32
+            })
+            .collect()
+    }
+}
+
+/// Cosine weighted direction `i` of `count` around `normal`, from a Hammersley sequence
+/// so every vertex gets the same evenly spread rays
+fn hemisphere_direction(normal: Vector3<f32>, i: u32, count: u32) -> Vector3<f32> {
+    let u = (i as f32 + 0.5) / count as f32;
+    let v = i.reverse_bits() as f32 / 2f32.powi(32);
+    let radius = u.sqrt();
+    let phi = 2.0 * PI * v;
+
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let tangent = normal.cross(helper).normalize();
+    let bitangent = normal.cross(tangent);
+    tangent * (radius * phi.cos()) + bitangent * (radius * phi.sin()) + normal * (1.0 - u).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::AmbientOcclusionBaker;
+    use crate::vertex::Vertex;
+    use crate::MeshBvh;
+
+    fn vertex(position: [f32; 3], normal: [f32; 3]) -> Vertex {
+        Vertex {
+            position,
+            tex_uv: [0.0, 0.0],
+            normal,
+            ao: 1.0,
+        }
+    }
+
+    #[test]
+    pub fn test_only_covered_vertices_are_occluded() {
+        // A floor quad with a small roof right above its first corner
+        let vertices = [
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            vertex([4.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            vertex([4.0, 0.0, 4.0], [0.0, 1.0, 0.0]),
+            vertex([0.0, 0.0, 4.0], [0.0, 1.0, 0.0]),
+            vertex([-1.0, 0.2, -1.0], [0.0, -1.0, 0.0]),
+            vertex([1.0, 0.2, -1.0], [0.0, -1.0, 0.0]),
+            vertex([1.0, 0.2, 1.0], [0.0, -1.0, 0.0]),
+            vertex([-1.0, 0.2, 1.0], [0.0, -1.0, 0.0]),
+        ];
+        let indices = [0, 1, 2, 0, 2, 3, 4, 6, 5, 4, 7, 6];
+        let bvh = MeshBvh::build(&vertices, &indices);
+        let ao = AmbientOcclusionBaker::bake(&vertices, &bvh, 32);
+        assert!(ao[0] < 0.5);
+        assert_eq!(ao[2], 1.0);
+    }
+
+    #[test]
+    pub fn test_roof_occludes_only_the_vertex_below() {
+        // A roof facing down over two probes that aren't part of any triangle, one
+        // right under it and one on top of it
+        let vertices = [
+            vertex([-4.0, 0.2, -4.0], [0.0, -1.0, 0.0]),
+            vertex([4.0, 0.2, -4.0], [0.0, -1.0, 0.0]),
+            vertex([4.0, 0.2, 4.0], [0.0, -1.0, 0.0]),
+            vertex([-4.0, 0.2, 4.0], [0.0, -1.0, 0.0]),
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            vertex([0.0, 0.2, 0.0], [0.0, 1.0, 0.0]),
+        ];
+        let indices = [0, 2, 1, 0, 3, 2];
+        let bvh = MeshBvh::build(&vertices, &indices);
+        let ao = AmbientOcclusionBaker::bake(&vertices, &bvh, 32);
+        assert_eq!(ao[4], 0.0);
+        assert_eq!(ao[5], 1.0);
+    }
+}
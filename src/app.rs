@@ -1,25 +1,27 @@
-use cgmath::{EuclideanSpace, Point3, SquareMatrix};
+use cgmath::{InnerSpace, SquareMatrix};
 // app.rs
 use hecs::World;
-use winit::event::Event;
-use winit::keyboard::Key;
-use winit::keyboard::NamedKey;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use winit::application::ApplicationHandler;
 use winit::dpi::Size;
-use winit::event::MouseScrollDelta::*;
+use winit::event::Event;
 use winit::event::WindowEvent;
-use winit::event::{DeviceEvent, DeviceId};
-use winit::event_loop::ActiveEventLoop;
-use winit::keyboard::PhysicalKey;
+use winit::event::{DeviceEvent, DeviceId, MouseButton};
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+use winit::keyboard::Key;
+use winit::keyboard::NamedKey;
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window, WindowId};
 
 use crate::input::Input;
 use crate::wgpu_ctx::WgpuCtx;
 use crate::*;
 
-#[derive(Default)]
+// Distance a teleport stops in front of the clicked surface
+const TELEPORT_STANDOFF: f32 = 1.0;
+
 pub struct App<'window> {
     window: Option<Arc<Window>>,
     wgpu_ctx: Option<WgpuCtx<'window>>,
@@ -27,18 +29,149 @@ pub struct App<'window> {
     world: World,
     camera_entity: Option<hecs::Entity>,
     last_frame_time: Option<Instant>,
+    ui_state: UiState,
+    is_minimized: bool, // Zero sized surface, rendering is suspended
+    chunk_streaming: ChunkStreamingSystem,
+    voxel_editor: VoxelEditorSystem,
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<GilrsBackend>, // None if no gamepad backend is available
+}
+
+/// App state the settings window edits while drawing
+pub struct UiState {
+    pub time_of_day: TimeOfDay,
+    pub water_surface: WaterSurface,
+    pub snow_accumulation: SnowAccumulation,
+    pub dynamic_resolution: DynamicResolutionScaler,
+    pub scene_files: SceneFiles,
+    pub target_frame_time: Duration, // Zero disables the frame limiter
+    pub low_power_mode: bool,        // Sleep instead of rendering while unfocused or hidden
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            time_of_day: TimeOfDay::default(),
+            water_surface: WaterSurface::default(),
+            snow_accumulation: SnowAccumulation::default(),
+            dynamic_resolution: DynamicResolutionScaler::default(),
+            scene_files: SceneFiles::default(),
+            target_frame_time: Duration::from_secs(1) / 60,
+            low_power_mode: false,
+        }
+    }
+}
+
+impl<'window> Default for App<'window> {
+    fn default() -> Self {
+        Self {
+            window: None,
+            wgpu_ctx: None,
+            input_system: Input::default(),
+            world: World::default(),
+            camera_entity: None,
+            last_frame_time: None,
+            ui_state: UiState::default(),
+            is_minimized: false,
+            chunk_streaming: ChunkStreamingSystem::new(),
+            voxel_editor: VoxelEditorSystem::new(),
+            #[cfg(feature = "gamepad")]
+            gamepad: GilrsBackend::new(),
+        }
+    }
+}
+
+impl<'window> App<'window> {
+    fn save_scene(&mut self) {
+        let Some(wgpu_ctx) = &self.wgpu_ctx else {
+            return;
+        };
+        let path = self.ui_state.scene_files.path.clone();
+        match SceneSerializer::save(&self.world, wgpu_ctx.voxel_settings(), &path) {
+            Ok(()) => {
+                tracing::info!(path = %path.display(), "Saved scene");
+                self.ui_state.scene_files.push_recent(&path);
+            }
+            Err(err) => tracing::error!(path = %path.display(), %err, "Failed to save scene"),
+        }
+    }
+
+    fn teleport_camera_to_cursor(&mut self) {
+        let (Some(wgpu_ctx), Some(camera_entity)) = (&self.wgpu_ctx, self.camera_entity) else {
+            return;
+        };
+        let (x, y) = self.input_system.mouse_position();
+        let Some(target) = wgpu_ctx.world_position_at_pixel(x as u32, y as u32) else {
+            return;
+        };
+        if let Ok(transform) = self.world.query_one_mut::<&mut Transform>(camera_entity) {
+            let back = transform.position - target;
+            // Clicked the camera's own position, there is no direction to stop short along
+            if back.magnitude2() < f32::EPSILON {
+                return;
+            }
+            transform.position =
+                target + back.normalize() * back.magnitude().min(TELEPORT_STANDOFF);
+        }
+    }
+
+    fn load_scene(&mut self, path: &Path) {
+        let (mut scene, voxel_settings) = match SceneSerializer::load(path) {
+            Ok(scene) => scene,
+            Err(err) => {
+                tracing::error!(path = %path.display(), %err, "Failed to load scene");
+                return;
+            }
+        };
+        self.ui_state.scene_files.path = path.to_path_buf();
+
+        if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+            wgpu_ctx.set_voxel_settings(voxel_settings);
+        }
+        // Otherwise the next frame would overwrite the loaded water and snow heights
+        self.ui_state.water_surface.base_water_height = voxel_settings.water_height;
+        self.ui_state.snow_accumulation.start_height = voxel_settings.snow_height;
+        self.ui_state.snow_accumulation.restart();
+
+        // Only one camera is rendered, it takes over the first saved one
+        let saved = scene
+            .query_mut::<(&Transform, &Camera, Option<&CameraController>)>()
+            .into_iter()
+            .next()
+            .map(|(_, (transform, camera, controller))| (*transform, *camera, controller.copied()));
+        let (Some((transform, mut camera, controller)), Some(camera_entity)) =
+            (saved, self.camera_entity)
+        else {
+            return;
+        };
+        // The aspect ratio belongs to the current window, not the saved one
+        if let Ok(current) = self.world.query_one_mut::<&Camera>(camera_entity) {
+            camera.aspect = current.aspect;
+            if let CameraProjection::Orthographic { width, height } = &mut camera.projection {
+                *width = *height * camera.aspect;
+            }
+        }
+        self.world
+            .insert(camera_entity, (transform, camera))
+            .unwrap();
+        if let Some(controller) = controller {
+            self.world.insert_one(camera_entity, controller).unwrap();
+        }
+    }
 }
 
 impl<'window> ApplicationHandler for App<'window> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if self.window.is_none() {
+            let config = WgpuCtxConfig::default();
             let win_attr = Window::default_attributes()
                 .with_title("Voxel Renderer")
                 .with_inner_size(winit::dpi::PhysicalSize::new(800, 800))
-                .with_min_inner_size(winit::dpi::PhysicalSize::new(200, 200));
+                .with_min_inner_size(winit::dpi::PhysicalSize::new(200, 200))
+                .with_transparent(config.transparent());
             let window = Arc::new(event_loop.create_window(win_attr).unwrap());
             self.window = Some(window.clone());
-            self.wgpu_ctx = Some(WgpuCtx::new(window.clone()));
+            self.wgpu_ctx = Some(WgpuCtx::new(window.clone(), config));
 
             // Initialize ECS world
             self.world = World::new();
@@ -59,11 +192,12 @@ impl<'window> ApplicationHandler for App<'window> {
 
             // if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
             //     // Load a model
-            //     if let Some(model_index) = wgpu_ctx.load_model("./assets/models/suzanne.gltf") {
+            //     let path = "./assets/models/suzanne.gltf";
+            //     if let Ok(model_key) = wgpu_ctx.load_model(path, false) {
             //         // Spawn a model entity
             //         crate::world::spawn_model_entity(
             //             &mut self.world,
-            //             model_index,
+            //             model_key,
             //             Point3::new(2.0, 0.0, 0.0), // Position to the right
             //         );
             //     }
@@ -81,29 +215,44 @@ impl<'window> ApplicationHandler for App<'window> {
         window_id: WindowId,
         event: WindowEvent,
     ) {
-        match event.clone() {
+        match &event {
             WindowEvent::CloseRequested => event_loop.exit(),
             // In app.rs, update the window_event handler for WindowEvent::Resized
             WindowEvent::Resized(new_size) => {
-                if let (Some(wgpu_ctx), Some(window)) =
-                    (self.wgpu_ctx.as_mut(), self.window.as_ref())
-                {
-                    wgpu_ctx.resize((new_size.width, new_size.height));
+                // Minimizing reports a zero size, keep the old surface and skip drawing instead
+                self.is_minimized = new_size.width == 0 || new_size.height == 0;
+
+                if let (false, Some(wgpu_ctx), Some(window)) = (
+                    self.is_minimized,
+                    self.wgpu_ctx.as_mut(),
+                    self.window.as_ref(),
+                ) {
+                    wgpu_ctx.resize((new_size.width, new_size.height), false);
 
                     // Update camera aspect ratio
                     if let Some(camera_entity) = self.camera_entity {
                         if let Ok(camera) = self.world.query_one_mut::<&mut Camera>(camera_entity) {
                             camera.aspect = new_size.width as f32 / new_size.height as f32;
+                            if let CameraProjection::Orthographic { width, height } =
+                                &mut camera.projection
+                            {
+                                *width = *height * camera.aspect;
+                            }
                         }
                     }
 
                     window.request_redraw();
                 }
             }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(wgpu_ctx) = self.wgpu_ctx.as_mut() {
+                    wgpu_ctx.resize_hidpi_only(*scale_factor);
+                }
+            }
             WindowEvent::KeyboardInput { event, .. } => {
                 let imgui = &mut self.wgpu_ctx.as_mut().unwrap().imgui;
                 let io = imgui.context.io();
-                
+
                 // Only process keyboard input if ImGui isn't capturing it
                 if !io.want_capture_keyboard {
                     if let Key::Named(NamedKey::Escape) = event.logical_key {
@@ -111,12 +260,28 @@ impl<'window> ApplicationHandler for App<'window> {
                             event_loop.exit();
                         }
                     }
-            
+                    if let Key::Named(NamedKey::F1) = event.logical_key {
+                        if event.state.is_pressed() && !event.repeat {
+                            let wgpu_ctx = self.wgpu_ctx.as_mut().unwrap();
+                            wgpu_ctx.show_perf_hud = !wgpu_ctx.show_perf_hud;
+                        }
+                    }
+                    if let Key::Named(NamedKey::F2) = event.logical_key {
+                        if event.state.is_pressed() && !event.repeat {
+                            let wgpu_ctx = self.wgpu_ctx.as_mut().unwrap();
+                            wgpu_ctx.show_render_stats = !wgpu_ctx.show_render_stats;
+                        }
+                    }
+
                     if let PhysicalKey::Code(key) = event.physical_key {
                         self.input_system.handle_key_input(key, event.state);
                     }
                 }
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input_system
+                    .handle_modifiers_changed(modifiers.state());
+            }
             WindowEvent::RedrawRequested => {
                 let now = Instant::now();
                 let dt = self
@@ -125,9 +290,96 @@ impl<'window> ApplicationHandler for App<'window> {
                     .unwrap_or_default();
                 self.last_frame_time = Some(now);
 
+                // Debug shapes only live for one frame
+                if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                    wgpu_ctx.debug_draw.clear();
+                    wgpu_ctx.perf_stats.push_frame_time(dt);
+                    let scale = self
+                        .ui_state
+                        .dynamic_resolution
+                        .update(wgpu_ctx.last_frame_ms());
+                    wgpu_ctx.set_render_scale(scale);
+                }
+
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepad) = &mut self.gamepad {
+                    gamepad.poll(&mut self.input_system);
+                }
+
+                // Ctrl+S saves the camera and voxel settings, Ctrl+O loads them back
+                if self.input_system.is_modifier_down(ModifierKey::Control) {
+                    if self.input_system.is_key_just_pressed(KeyCode::KeyS) {
+                        self.save_scene();
+                    }
+                    if self.input_system.is_key_just_pressed(KeyCode::KeyO) {
+                        self.ui_state.scene_files.requested_load =
+                            Some(self.ui_state.scene_files.path.clone());
+                    }
+                }
+                if let Some(path) = self.ui_state.scene_files.requested_load.take() {
+                    self.load_scene(&path);
+                }
+
                 // Update camera system
                 update_camera_system(&mut self.world, &self.input_system, dt);
 
+                // Stream terrain chunks around the camera
+                if let (Some(wgpu_ctx), Some(camera_entity)) =
+                    (&mut self.wgpu_ctx, self.camera_entity)
+                {
+                    if let Ok(transform) = self.world.query_one_mut::<&Transform>(camera_entity) {
+                        let camera_position = transform.position;
+                        self.chunk_streaming
+                            .update(&mut self.world, wgpu_ctx, camera_position);
+                    }
+                }
+
+                // Place and remove voxels with the right mouse button
+                if let (Some(wgpu_ctx), Some(camera_entity), Some(window)) =
+                    (&mut self.wgpu_ctx, self.camera_entity, &self.window)
+                {
+                    if let Ok((transform, camera)) = self
+                        .world
+                        .query_one_mut::<(&Transform, &Camera)>(camera_entity)
+                    {
+                        let size = window.inner_size();
+                        self.voxel_editor.update(
+                            &self.input_system,
+                            wgpu_ctx,
+                            transform,
+                            camera,
+                            (size.width, size.height),
+                        );
+                    }
+                }
+
+                if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                    wgpu_ctx.update_animations(&mut self.world, dt);
+                }
+
+                // Move the sun before the camera update so the shadow map follows it
+                if self.ui_state.time_of_day.enabled {
+                    self.ui_state.time_of_day.advance(dt);
+                    if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                        wgpu_ctx.set_sun(
+                            self.ui_state.time_of_day.light_direction(),
+                            self.ui_state.time_of_day.light_color(),
+                        );
+                    }
+                }
+
+                self.ui_state.water_surface.advance(dt);
+                if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                    wgpu_ctx.set_water_height(self.ui_state.water_surface.water_height());
+                }
+
+                if self.ui_state.snow_accumulation.snowfall {
+                    self.ui_state.snow_accumulation.advance(dt);
+                    if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                        wgpu_ctx.set_snow_height(self.ui_state.snow_accumulation.snow_height());
+                    }
+                }
+
                 if let (Some(wgpu_ctx), Some(camera_entity)) =
                     (&mut self.wgpu_ctx, self.camera_entity)
                 {
@@ -147,8 +399,20 @@ impl<'window> ApplicationHandler for App<'window> {
                     }
                 }
 
-                if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
-                    wgpu_ctx.draw(&self.world, self.window.as_mut().unwrap());
+                if !self.is_minimized {
+                    if let Some(wgpu_ctx) = &mut self.wgpu_ctx {
+                        wgpu_ctx.draw(&mut self.world, &mut self.ui_state);
+                    }
+                }
+
+                // Read after drawing so the depth matches this frame's camera. Plain left
+                // drags keep looking around
+                if self.input_system.is_modifier_down(ModifierKey::Control)
+                    && self
+                        .input_system
+                        .is_mouse_button_just_pressed(MouseButton::Left)
+                {
+                    self.teleport_camera_to_cursor();
                 }
 
                 self.input_system.update();
@@ -156,28 +420,31 @@ impl<'window> ApplicationHandler for App<'window> {
             WindowEvent::MouseInput { button, state, .. } => {
                 let imgui = &mut self.wgpu_ctx.as_mut().unwrap().imgui;
                 let io = imgui.context.io();
-                
+
                 if !io.want_capture_mouse {
-                    self.input_system.handle_mouse_button(button, state);
+                    self.input_system.handle_mouse_button(*button, *state);
                 }
             }
-            
+
             WindowEvent::CursorMoved { position, .. } => {
                 let imgui = &mut self.wgpu_ctx.as_mut().unwrap().imgui;
                 let io = imgui.context.io();
-                
+
                 if !io.want_capture_mouse {
-                    self.input_system.handle_cursor_moved(&position);
+                    self.input_system.handle_cursor_moved(position);
                 }
             }
-            WindowEvent::MouseWheel { delta, .. } => match delta {
-                LineDelta(_, y) => {
-                    self.input_system.handle_mouse_scroll(y as f64);
-                }
-                PixelDelta(d) => {
-                    self.input_system.handle_mouse_scroll(d.y);
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.input_system.handle_mouse_scroll(*delta);
+            }
+            WindowEvent::Touch(touch) => {
+                let imgui = &mut self.wgpu_ctx.as_mut().unwrap().imgui;
+                let io = imgui.context.io();
+
+                if !io.want_capture_mouse {
+                    self.input_system.handle_touch(touch);
                 }
-            },
+            }
             _ => (),
         }
 
@@ -219,15 +486,39 @@ impl<'window> ApplicationHandler for App<'window> {
         );
     }
 
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(wgpu_ctx) = &self.wgpu_ctx {
+            wgpu_ctx.save_pipeline_cache();
+        }
+    }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         let window = self.window.as_mut().unwrap();
         let imgui = &mut self.wgpu_ctx.as_mut().unwrap().imgui;
-        window.request_redraw();
-        imgui.platform.handle_event::<()>(
-            imgui.context.io_mut(),
-            &window,
-            &Event::AboutToWait,
-        );
+
+        // Nothing to show while hidden or in the background, sleep until an event
+        // like regaining focus arrives
+        let idle = !window.has_focus() || window.is_visible() == Some(false);
+        // Sleep until the next frame is due instead of spinning the event loop
+        let next_frame = self
+            .last_frame_time
+            .map(|last| last + self.ui_state.target_frame_time);
+        match next_frame {
+            _ if self.ui_state.low_power_mode && idle => {
+                event_loop.set_control_flow(ControlFlow::Wait);
+            }
+            Some(next_frame)
+                if !self.ui_state.target_frame_time.is_zero() && Instant::now() < next_frame =>
+            {
+                event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            }
+            _ => {
+                event_loop.set_control_flow(ControlFlow::Poll);
+                window.request_redraw();
+            }
+        }
+        imgui
+            .platform
+            .handle_event::<()>(imgui.context.io_mut(), &window, &Event::AboutToWait);
     }
 }
@@ -0,0 +1,295 @@
+use crate::bloom::create_compute_pipeline;
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Instant;
+use wgpu::util::DeviceExt;
+
+// Must match HISTOGRAM_BINS and the workgroup sizes in auto_exposure.wgsl
+const HISTOGRAM_BINS: u64 = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AutoExposureSettings {
+    pub enabled: u32, // 0 keeps the exposure at 1
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    pub adaptation_speed: f32, // Seconds to reach a new target exposure
+    delta_time: f32,           // Seconds since the last adaptation, set every drawn frame
+    _padding: [f32; 3],
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: 0,
+            min_exposure: 0.25,
+            max_exposure: 4.0,
+            adaptation_speed: 1.0,
+            delta_time: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Adapts the exposure to the average luminance of the bloom prefilter output. A
+/// histogram pass bins the luminance, an average pass turns it into a target exposure
+/// and moves the current one toward it. The result lives in a 1x1 `R32Float`
+/// texture the color correction multiplies the image by
+pub struct AutoExposureEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    settings: AutoExposureSettings,
+    settings_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    state_buffer: wgpu::Buffer,
+    exposure_texture: wgpu::Texture,
+    exposure_view: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    last_frame: Instant,
+}
+
+impl AutoExposureEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Auto Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("auto_exposure.wgsl"))),
+        });
+
+        let settings = AutoExposureSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Auto Exposure Histogram Buffer"),
+            size: HISTOGRAM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The adapted exposure carried between frames
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Auto Exposure State Buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let exposure_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Auto Exposure Texture"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let exposure_view = exposure_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Auto Exposure Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::R32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let histogram_pipeline = create_compute_pipeline(
+            &device,
+            &[&bind_group_layout],
+            &shader,
+            "histogram_main",
+            "Auto Exposure Histogram Pipeline",
+            pipeline_cache,
+        );
+        let average_pipeline = create_compute_pipeline(
+            &device,
+            &[&bind_group_layout],
+            &shader,
+            "average_main",
+            "Auto Exposure Average Pipeline",
+            pipeline_cache,
+        );
+
+        let effect = Self {
+            device,
+            queue,
+            settings,
+            settings_buffer,
+            histogram_buffer,
+            state_buffer,
+            exposure_texture,
+            exposure_view,
+            bind_group_layout,
+            histogram_pipeline,
+            average_pipeline,
+            last_frame: Instant::now(),
+        };
+        effect.reset_exposure();
+        effect
+    }
+
+    pub fn exposure_view(&self) -> &wgpu::TextureView {
+        &self.exposure_view
+    }
+
+    /// The exposure texture as a render graph resource
+    pub fn exposure_target(&self) -> RenderTarget<'_> {
+        RenderTarget {
+            texture: &self.exposure_texture,
+            view: &self.exposure_view,
+        }
+    }
+
+    pub fn update_uniform(&mut self, settings: AutoExposureSettings) {
+        if settings.enabled == 0 && self.settings.enabled != 0 {
+            self.reset_exposure();
+        }
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Advances the adaptation by the time since the last call, call once per drawn frame
+    pub fn next_frame(&mut self) {
+        let now = Instant::now();
+        let mut settings = self.settings;
+        settings.delta_time = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.update_uniform(settings);
+    }
+
+    fn reset_exposure(&self) {
+        self.queue
+            .write_buffer(&self.state_buffer, 0, bytemuck::cast_slice(&[1.0f32]));
+        self.queue.write_texture(
+            self.exposure_texture.as_image_copy(),
+            bytemuck::cast_slice(&[1.0f32]),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: None,
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Measures `input` and updates the exposure texture, skipped entirely while disabled
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, input: RenderTarget) {
+        if self.settings.enabled == 0 {
+            return;
+        }
+
+        // Bound per call so the input can be recreated on resize
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.state_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.exposure_view),
+                },
+            ],
+            label: Some("Auto Exposure Bind Group"),
+        });
+
+        encoder.clear_buffer(&self.histogram_buffer, 0, None);
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Auto Exposure Compute Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.set_pipeline(&self.histogram_pipeline);
+        cpass.dispatch_workgroups(
+            input.texture.width().div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            input.texture.height().div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            1,
+        );
+        cpass.set_pipeline(&self.average_pipeline);
+        cpass.dispatch_workgroups(1, 1, 1);
+    }
+}
+
+impl RenderNode for AutoExposureEffect {
+    /// Input: the bloom prefilter output, output: the exposure texture
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        inputs: &[RenderTarget],
+        _outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, inputs[0]);
+    }
+}
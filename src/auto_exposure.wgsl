@@ -0,0 +1,97 @@
+struct AutoExposureSettings {
+    enabled: u32,
+    min_exposure: f32,
+    max_exposure: f32,
+    adaptation_speed: f32,
+    delta_time: f32,
+};
+
+@group(0) @binding(0) var<uniform> settings: AutoExposureSettings;
+@group(0) @binding(1) var input: texture_2d<f32>;
+@group(0) @binding(2) var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(3) var<storage, read_write> adapted_exposure: array<f32, 1>;
+@group(0) @binding(4) var exposure_out: texture_storage_2d<r32float, write>;
+
+const HISTOGRAM_BINS: u32 = 256u;
+// Bins cover log2 luminance from MIN_LOG_LUMINANCE over LOG_LUMINANCE_RANGE stops,
+// bin 0 holds the black pixels the prefilter threshold removed
+const MIN_LOG_LUMINANCE: f32 = -8.0;
+const LOG_LUMINANCE_RANGE: f32 = 12.0;
+// Width of the Gaussian centered on the histogram, in fractions of the range.
+// Narrower ignores more of the darkest and brightest pixels
+const EXPOSURE_WEIGHT_WIDTH: f32 = 0.35;
+// Middle grey, the average luminance is mapped here
+const KEY_VALUE: f32 = 0.18;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+fn luminance_bin(color: vec3f) -> u32 {
+    let luminance = dot(color, vec3f(0.2126, 0.7152, 0.0722));
+    if (luminance < exp2(MIN_LOG_LUMINANCE)) {
+        return 0u;
+    }
+    let t = saturate((log2(luminance) - MIN_LOG_LUMINANCE) / LOG_LUMINANCE_RANGE);
+    return u32(t * f32(HISTOGRAM_BINS - 2u)) + 1u;
+}
+
+@compute @workgroup_size(16, 16)
+fn histogram_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let dims = textureDimensions(input);
+    if (id.x < dims.x && id.y < dims.y) {
+        let color = textureLoad(input, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+var<workgroup> weighted_log_sum: array<f32, 256>;
+var<workgroup> weight_sum: array<f32, 256>;
+
+@compute @workgroup_size(256)
+fn average_main(@builtin(local_invocation_index) index: u32) {
+    let count = f32(atomicLoad(&histogram[index]));
+    let t = (f32(index) - 0.5) / f32(HISTOGRAM_BINS - 2u);
+    let log_luminance = MIN_LOG_LUMINANCE + t * LOG_LUMINANCE_RANGE;
+    let offset = (t - 0.5) / EXPOSURE_WEIGHT_WIDTH;
+    let weight = select(count * exp(-0.5 * offset * offset), 0.0, index == 0u);
+    weighted_log_sum[index] = weight * log_luminance;
+    weight_sum[index] = weight;
+    workgroupBarrier();
+
+    for (var stride = HISTOGRAM_BINS / 2u; stride > 0u; stride = stride / 2u) {
+        if (index < stride) {
+            weighted_log_sum[index] += weighted_log_sum[index + stride];
+            weight_sum[index] += weight_sum[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (index == 0u) {
+        let previous = adapted_exposure[0];
+        // An all black input keeps the current exposure
+        var target_exposure = previous;
+        if (weight_sum[0] > 0.0) {
+            let average_luminance = exp2(weighted_log_sum[0] / weight_sum[0]);
+            target_exposure = clamp(
+                KEY_VALUE / average_luminance,
+                settings.min_exposure,
+                settings.max_exposure,
+            );
+        }
+        var blend = 1.0;
+        if (settings.adaptation_speed > 0.0) {
+            blend = saturate(settings.delta_time / settings.adaptation_speed);
+        }
+        let exposure = mix(previous, target_exposure, blend);
+        adapted_exposure[0] = exposure;
+        textureStore(exposure_out, vec2<i32>(0), vec4f(exposure, 0.0, 0.0, 1.0));
+    }
+}
@@ -1,13 +1,177 @@
-use std::sync::Arc;
+use crate::render_graph::{RenderNode, RenderTarget};
+use crate::{texture_bytes, with_validation};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
 use wgpu::{util::DeviceExt, PipelineCompilationOptions};
 
+// Must match `@workgroup_size` of every entry point in bloom.wgsl
+const BLOOM_WORKGROUP_SIZE: u32 = 8;
+
+// Weight of each blurred mip level in the composite, levels past the end weigh 1
+const BLOOM_LEVEL_WEIGHTS: [f32; 8] = [1.0, 1.5, 1.0, 1.5, 1.8, 1.0, 1.0, 1.0];
+
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct BloomSettings {
-    min_brightness: f32,
-    max_brightness: f32,
-    blur_radius: f32,
-    blur_type: u32, // 0 = Gaussian, 1 = Box, etc.
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BloomSettings {
+    pub min_brightness: f32, // Brightness where pixels start to bloom
+    pub max_brightness: f32, // Brightness where they bloom fully
+    pub blur_radius: f32,
+    pub blur_type: u32, // 0 = Gaussian, 1 = Box, 2 = Tent
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            min_brightness: 0.9,
+            max_brightness: 1.0,
+            blur_radius: 1.0,
+            blur_type: 0,
+        }
+    }
+}
+
+/// GPU milliseconds of each bloom stage, summed over all mips
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct BloomTimings {
+    pub prefilter: f32,
+    pub downsample: f32,
+    pub horizontal_blur: f32,
+    pub vertical_blur: f32,
+    pub composite: f32,
+}
+
+impl BloomTimings {
+    /// Milliseconds of the whole effect
+    pub fn total(&self) -> f32 {
+        self.prefilter
+            + self.downsample
+            + self.horizontal_blur
+            + self.vertical_blur
+            + self.composite
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum TimestampState {
+    Idle,    // Free to record this frame
+    Copied,  // Resolved into the readback buffer, mapped once that frame was submitted
+    Mapping, // Waiting for the map callback
+}
+
+struct TimestampReadback {
+    state: TimestampState,
+    timings: BloomTimings,
+}
+
+/// One begin and end timestamp per compute pass, read back a few frames late
+struct BloomTimestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period: f32, // Nanoseconds per tick
+    readback: Arc<Mutex<TimestampReadback>>,
+}
+
+impl BloomTimestamps {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, max_level: u32) -> Self {
+        // Prefilter, downsamples, horizontal and vertical blurs, composite
+        let count = 2 * (1 + max_level + max_level + max_level + 1);
+        let size = count as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Bloom Timestamp Query Set"),
+                ty: wgpu::QueryType::Timestamp,
+                count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bloom Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bloom Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            readback: Arc::new(Mutex::new(TimestampReadback {
+                state: TimestampState::Idle,
+                timings: BloomTimings::default(),
+            })),
+        }
+    }
+
+    /// Timestamp writes of compute pass number `pass`
+    fn writes(&self, pass: u32) -> wgpu::ComputePassTimestampWrites<'_> {
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(2 * pass),
+            end_of_pass_write_index: Some(2 * pass + 1),
+        }
+    }
+
+    fn state(&self) -> TimestampState {
+        self.readback.lock().unwrap().state
+    }
+
+    fn set_state(&self, state: TimestampState) {
+        self.readback.lock().unwrap().state = state;
+    }
+
+    /// Starts mapping the readback buffer, the callback turns the ticks into timings
+    fn map(&self, max_level: u32) {
+        let buffer = self.readback_buffer.clone();
+        let readback = self.readback.clone();
+        let period = self.period;
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let mut readback = readback.lock().unwrap();
+                readback.state = TimestampState::Idle;
+                if result.is_err() {
+                    return;
+                }
+                {
+                    let data = buffer.slice(..).get_mapped_range();
+                    let ticks: &[u64] = bytemuck::cast_slice(&data);
+                    let milliseconds = |passes: &mut dyn Iterator<Item = u32>| {
+                        passes
+                            .map(|pass| {
+                                let pass = pass as usize;
+                                ticks[2 * pass + 1].wrapping_sub(ticks[2 * pass])
+                            })
+                            .sum::<u64>() as f32
+                            * period
+                            / 1_000_000.0
+                    };
+                    readback.timings = BloomTimings {
+                        prefilter: milliseconds(&mut (0..1)),
+                        downsample: milliseconds(&mut (1..max_level)),
+                        horizontal_blur: milliseconds(
+                            &mut (0..max_level).map(|mip| blur_pass(max_level, mip)),
+                        ),
+                        vertical_blur: milliseconds(
+                            &mut (0..max_level).map(|mip| blur_pass(max_level, mip) + 1),
+                        ),
+                        composite: milliseconds(&mut std::iter::once(composite_pass(max_level))),
+                    };
+                }
+                buffer.unmap();
+            });
+    }
+}
+
+/// Inputs of `BloomEffect::new`, the shared layout and sampler and the size, level
+/// count and format of the mip chain
+pub struct BloomParams {
+    pub texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    pub sampler: Arc<wgpu::Sampler>,
+    pub max_level: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
 }
 
 pub struct BloomEffect {
@@ -15,13 +179,15 @@ pub struct BloomEffect {
     queue: Arc<wgpu::Queue>,
     texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
     sampler: Arc<wgpu::Sampler>,
-    max_level: u32,
+    max_level: u32,           // Mip levels of the chain, capped by `bloom_level_count`
+    requested_max_level: u32, // As passed to `new`, used again once the size allows it
     downsample_texture: wgpu::Texture,
     downsample_views: Vec<wgpu::TextureView>,
     horizontal_blur_texture: wgpu::Texture,
     horizontal_blur_views: Vec<wgpu::TextureView>,
     vertical_blur_texture: wgpu::Texture,
     vertical_blur_views: Vec<wgpu::TextureView>,
+    settings: BloomSettings,
     settings_buffer: wgpu::Buffer,
     downsample_bind_groups: Vec<wgpu::BindGroup>,
     horizontal_blur_bind_groups: Vec<wgpu::BindGroup>,
@@ -31,7 +197,6 @@ pub struct BloomEffect {
     horizontal_blur_pipeline: wgpu::ComputePipeline,
     vertical_blur_pipeline: wgpu::ComputePipeline,
     composite_pipeline: wgpu::ComputePipeline,
-    composite_bind_group_layout: wgpu::BindGroupLayout,
     full_width: u32,
     full_height: u32,
     half_width: u32,
@@ -40,28 +205,44 @@ pub struct BloomEffect {
     group1_layout: wgpu::BindGroupLayout,
     group2_layout: wgpu::BindGroupLayout,
     settings_bind_group: wgpu::BindGroup,
+    timestamps: Option<BloomTimestamps>, // Needs TIMESTAMP_QUERY
+    format: wgpu::TextureFormat,         // Of the mip chain and the composite output
+    pipeline_cache: Option<wgpu::PipelineCache>, // Reused when `resize` rebuilds the effect
 }
 
 impl BloomEffect {
     pub fn new(
         device: Arc<wgpu::Device>,
         queue: Arc<wgpu::Queue>,
-        texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
-        sampler: Arc<wgpu::Sampler>,
-        width: u32,
-        height: u32,
-        _render_texture_view: &wgpu::TextureView,
-        bloom_shader: &wgpu::ShaderModule,
+        params: BloomParams,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
     ) -> Self {
-        let max_level = 8;
-        let half_width = width / 2;
-        let half_height = height / 2;
+        let BloomParams {
+            texture_bind_group_layout,
+            sampler,
+            max_level,
+            width,
+            height,
+            format,
+        } = params;
+        let half_width = (width / 2).max(1);
+        let half_height = (height / 2).max(1);
+        let requested_max_level = max_level;
+        let max_level = max_level.min(bloom_level_count(half_width, half_height));
+
+        let bloom_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                bloom_source(include_str!("bloom.wgsl"), format, max_level).into(),
+            ),
+        });
 
         let downsample_texture = create_mip_texture(
             &device,
             half_width,
             half_height,
             max_level,
+            format,
             "Downsample Texture",
         );
         let horizontal_blur_texture = create_mip_texture(
@@ -69,6 +250,7 @@ impl BloomEffect {
             half_width,
             half_height,
             max_level,
+            format,
             "Horizontal Blur Texture",
         );
         let vertical_blur_texture = create_mip_texture(
@@ -76,6 +258,7 @@ impl BloomEffect {
             half_width,
             half_height,
             max_level,
+            format,
             "Vertical Blur Texture",
         );
 
@@ -83,12 +266,7 @@ impl BloomEffect {
         let horizontal_blur_views = create_mip_views(&horizontal_blur_texture, max_level);
         let vertical_blur_views = create_mip_views(&vertical_blur_texture, max_level);
 
-        let settings = BloomSettings {
-            min_brightness: 0.9,
-            max_brightness: 1.0,
-            blur_radius: 1.0,
-            blur_type: 0,
-        };
+        let settings = BloomSettings::default();
         let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Bloom Settings Buffer"),
             contents: bytemuck::cast_slice(&[settings]),
@@ -129,7 +307,7 @@ impl BloomEffect {
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::StorageTexture {
                         access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba32Float,
+                        format,
                         view_dimension: wgpu::TextureViewDimension::D2,
                     },
                     count: None,
@@ -137,10 +315,12 @@ impl BloomEffect {
             ],
         });
 
+        // Composite pass inputs: one texture per level of the blurred mip chain, then
+        // its filtering sampler
         let group2_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Bloom Textures Bind Group Layout"),
             entries: &{
-                let mut entries = (0..8)
+                let mut entries = (0..max_level)
                     .map(|i| wgpu::BindGroupLayoutEntry {
                         binding: i,
                         visibility: wgpu::ShaderStages::COMPUTE,
@@ -153,7 +333,7 @@ impl BloomEffect {
                     })
                     .collect::<Vec<_>>();
                 entries.push(wgpu::BindGroupLayoutEntry {
-                    binding: 8,
+                    binding: max_level,
                     visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
@@ -230,80 +410,8 @@ impl BloomEffect {
             })
             .collect::<Vec<_>>();
 
-        let texture_binding = wgpu::BindGroupLayoutEntry {
-            binding: 0, // Will be overridden
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Texture {
-                multisampled: false,
-                view_dimension: wgpu::TextureViewDimension::D2,
-                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-            },
-            count: None,
-        };
-
-        let composite_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Composite Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 4,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 5,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 6,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 7,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 8,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 9,
-                        ..texture_binding
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 10,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba32Float,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                ],
-            });
-
+        // Only binds the settings buffer, so unlike the texture bind groups above it is
+        // created once here and never rebuilt by `resize`
         let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &group0_layout,
             entries: &[wgpu::BindGroupEntry {
@@ -313,43 +421,25 @@ impl BloomEffect {
             label: Some("Settings Bind Group"),
         });
 
-        let prefilter_pipeline = create_compute_pipeline(
-            &device,
-            &[&group0_layout, &group1_layout],
-            bloom_shader,
-            "prefilter_main",
-            "Prefilter Pipeline",
-        );
-        let downsample_pipeline = create_compute_pipeline(
+        let BloomPipelines {
+            prefilter: prefilter_pipeline,
+            downsample: downsample_pipeline,
+            horizontal_blur: horizontal_blur_pipeline,
+            vertical_blur: vertical_blur_pipeline,
+            composite: composite_pipeline,
+        } = BloomPipelines::new(
             &device,
-            &[&group0_layout, &group1_layout],
-            bloom_shader,
-            "downsample_main",
-            "Downsample Pipeline",
+            &group0_layout,
+            &group1_layout,
+            &group2_layout,
+            &bloom_shader,
+            pipeline_cache,
         );
 
-        let horizontal_blur_pipeline = create_compute_pipeline(
-            &device,
-            &[&group0_layout, &group1_layout],
-            bloom_shader,
-            "horizontal_blur_main",
-            "Horizontal Blur Pipeline",
-        );
-
-        let vertical_blur_pipeline = create_compute_pipeline(
-            &device,
-            &[&group0_layout, &group1_layout],
-            bloom_shader,
-            "vertical_blur_main",
-            "Vertical Blur Pipeline",
-        );
-        let composite_pipeline = create_compute_pipeline(
-            &device,
-            &[&group0_layout, &group1_layout, &group2_layout],
-            bloom_shader,
-            "composite_main",
-            "Composite Pipeline",
-        );
+        let timestamps = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| BloomTimestamps::new(&device, &queue, max_level));
 
         Self {
             device,
@@ -357,12 +447,14 @@ impl BloomEffect {
             texture_bind_group_layout,
             sampler,
             max_level,
+            requested_max_level,
             downsample_texture,
             downsample_views,
             horizontal_blur_texture,
             horizontal_blur_views,
             vertical_blur_texture,
             vertical_blur_views,
+            settings,
             settings_buffer,
             downsample_bind_groups,
             horizontal_blur_bind_groups,
@@ -372,7 +464,6 @@ impl BloomEffect {
             horizontal_blur_pipeline,
             vertical_blur_pipeline,
             composite_pipeline,
-            composite_bind_group_layout,
             full_width: width,
             full_height: height,
             half_width,
@@ -381,19 +472,124 @@ impl BloomEffect {
             group1_layout,
             group2_layout,
             settings_bind_group,
+            timestamps,
+            format,
+            pipeline_cache: pipeline_cache.cloned(),
         }
     }
-    pub fn resize(&mut self, width: u32, height: u32, _render_texture_view: &wgpu::TextureView) {
+
+    pub fn set_settings(&mut self, settings: BloomSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Recompiles `bloom.wgsl` from `source`, keeping the current pipelines on failure
+    pub fn reload_shader(&mut self, source: &str) -> Result<(), String> {
+        let pipelines = with_validation(&self.device, || {
+            let shader = self
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("Bloom Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        bloom_source(source, self.format, self.max_level).into(),
+                    ),
+                });
+            BloomPipelines::new(
+                &self.device,
+                &self.group0_layout,
+                &self.group1_layout,
+                &self.group2_layout,
+                &shader,
+                None,
+            )
+        })?;
+
+        self.prefilter_pipeline = pipelines.prefilter;
+        self.downsample_pipeline = pipelines.downsample;
+        self.horizontal_blur_pipeline = pipelines.horizontal_blur;
+        self.vertical_blur_pipeline = pipelines.vertical_blur;
+        self.composite_pipeline = pipelines.composite;
+        Ok(())
+    }
+
+    /// Per stage GPU cost of a recent frame, all zero without timestamp query support
+    pub fn last_timings(&self) -> BloomTimings {
+        self.timestamps
+            .as_ref()
+            .map(|timestamps| timestamps.readback.lock().unwrap().timings)
+            .unwrap_or_default()
+    }
+
+    /// Timestamp writes for compute pass number `pass`, None while the last
+    /// frame's timestamps are still being read back
+    fn timestamp_writes(&self, pass: u32) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        self.timestamps
+            .as_ref()
+            .filter(|timestamps| timestamps.state() == TimestampState::Idle)
+            .map(|timestamps| timestamps.writes(pass))
+    }
+
+    /// The half resolution, brightness filtered scene the blur chain starts from
+    pub fn prefilter_target(&self) -> RenderTarget<'_> {
+        RenderTarget {
+            texture: &self.downsample_texture,
+            view: &self.downsample_views[0],
+        }
+    }
+
+    /// Bytes of the downsample and both blur mip chains
+    pub fn mip_bytes(&self) -> u64 {
+        [
+            &self.downsample_texture,
+            &self.horizontal_blur_texture,
+            &self.vertical_blur_texture,
+        ]
+        .into_iter()
+        .map(texture_bytes)
+        .sum()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        // The level count is baked into the shader and the composite layout, so a size
+        // that changes it rebuilds the whole effect
+        let half_width = (width / 2).max(1);
+        let half_height = (height / 2).max(1);
+        let max_level = self
+            .requested_max_level
+            .min(bloom_level_count(half_width, half_height));
+        if max_level != self.max_level {
+            let settings = self.settings;
+            *self = Self::new(
+                Arc::clone(&self.device),
+                Arc::clone(&self.queue),
+                BloomParams {
+                    texture_bind_group_layout: Arc::clone(&self.texture_bind_group_layout),
+                    sampler: Arc::clone(&self.sampler),
+                    max_level: self.requested_max_level,
+                    width,
+                    height,
+                    format: self.format,
+                },
+                self.pipeline_cache.as_ref(),
+            );
+            self.set_settings(settings);
+            return;
+        }
+
+        // settings_bind_group does not need to be rebuilt on resize, the settings buffer
+        // it binds is the same size at every resolution
         self.full_width = width;
         self.full_height = height;
-        self.half_width = width / 2;
-        self.half_height = height / 2;
+        self.half_width = half_width;
+        self.half_height = half_height;
 
         self.downsample_texture = create_mip_texture(
             &self.device,
             self.half_width,
             self.half_height,
             self.max_level,
+            self.format,
             "Downsample Texture",
         );
         self.horizontal_blur_texture = create_mip_texture(
@@ -401,6 +597,7 @@ impl BloomEffect {
             self.half_width,
             self.half_height,
             self.max_level,
+            self.format,
             "Horizontal Blur Texture",
         );
         self.vertical_blur_texture = create_mip_texture(
@@ -408,6 +605,7 @@ impl BloomEffect {
             self.half_width,
             self.half_height,
             self.max_level,
+            self.format,
             "Vertical Blur Texture",
         );
 
@@ -491,65 +689,65 @@ impl BloomEffect {
         encoder: &mut wgpu::CommandEncoder,
         scene_texture_view: &wgpu::TextureView,
     ) {
-        // Create the prefilter bind group
-        let prefilter_group1_bind_group =
-            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &self.group1_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(scene_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&self.downsample_views[0]),
-                    },
-                ],
-                label: Some("Prefilter Group 1 Bind Group"),
-            });
-
-        // Prefilter pass
+        // Prefilter pass, bound per call so the scene texture can be recreated freely
+        let prefilter_bind_group = create_prefilter_bind_group(
+            &self.device,
+            &self.group1_layout,
+            scene_texture_view,
+            &self.downsample_views[0],
+        );
         {
             let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Prefilter Compute Pass"),
-                timestamp_writes: None,
+                timestamp_writes: self.timestamp_writes(0),
             });
             cpass.set_pipeline(&self.prefilter_pipeline);
             cpass.set_bind_group(0, &self.settings_bind_group, &[]);
-            cpass.set_bind_group(1, &prefilter_group1_bind_group, &[]);
-            let dispatch_x = (self.half_width + 7) / 8;
-            let dispatch_y = (self.half_height + 7) / 8;
+            cpass.set_bind_group(1, &prefilter_bind_group, &[]);
+            let dispatch_x = dispatch_count(self.half_width);
+            let dispatch_y = dispatch_count(self.half_height);
             cpass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
         }
 
-        // Downsample pass (corrected)
+        // Downsample pass, downsample_main averages 2x2 source texels per output texel,
+        // so each level has to be half the previous one like the texture's own mip chain
         for i in 1..self.max_level {
             let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some(&format!("Downsample Compute Pass Mip {}", i)),
-                timestamp_writes: None,
+                timestamp_writes: self.timestamp_writes(i),
             });
             cpass.set_pipeline(&self.downsample_pipeline);
             cpass.set_bind_group(0, &self.settings_bind_group, &[]);
             cpass.set_bind_group(1, &self.downsample_bind_groups[i as usize - 1], &[]);
-            let mip_width = (self.half_width >> i).max(1);
-            let mip_height = (self.half_height >> i).max(1);
-            let dispatch_x = (mip_width + 7) / 8;
-            let dispatch_y = (mip_height + 7) / 8;
+            let mip_width = mip_size(self.half_width, i);
+            let mip_height = mip_size(self.half_height, i);
+            let allocated = self
+                .downsample_texture
+                .size()
+                .mip_level_size(i, wgpu::TextureDimension::D2);
+            debug_assert_eq!(
+                (mip_width, mip_height),
+                (allocated.width, allocated.height),
+                "downsample mip {} dispatch doesn't cover the allocated level",
+                i
+            );
+            let dispatch_x = dispatch_count(mip_width);
+            let dispatch_y = dispatch_count(mip_height);
             cpass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
         }
 
         // Blur passes
         for i in 0..self.max_level {
-            let mip_width = (self.half_width >> i).max(1);
-            let mip_height = (self.half_height >> i).max(1);
-            let dispatch_x = (mip_width + 7) / 8;
-            let dispatch_y = (mip_height + 7) / 8;
+            let mip_width = mip_size(self.half_width, i);
+            let mip_height = mip_size(self.half_height, i);
+            let dispatch_x = dispatch_count(mip_width);
+            let dispatch_y = dispatch_count(mip_height);
 
             // Horizontal blur
             {
                 let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                     label: Some(&format!("Horizontal Blur Compute Pass Mip {}", i)),
-                    timestamp_writes: None,
+                    timestamp_writes: self.timestamp_writes(blur_pass(self.max_level, i)),
                 });
                 cpass.set_pipeline(&self.horizontal_blur_pipeline);
                 cpass.set_bind_group(0, &self.settings_bind_group, &[]);
@@ -561,7 +759,7 @@ impl BloomEffect {
             {
                 let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                     label: Some(&format!("Vertical Blur Compute Pass Mip {}", i)),
-                    timestamp_writes: None,
+                    timestamp_writes: self.timestamp_writes(blur_pass(self.max_level, i) + 1),
                 });
                 cpass.set_pipeline(&self.vertical_blur_pipeline);
                 cpass.set_bind_group(0, &self.settings_bind_group, &[]);
@@ -577,6 +775,10 @@ impl BloomEffect {
         target_view: &wgpu::TextureView,
         scene_texture_view: &wgpu::TextureView,
     ) {
+        // Both composite bind groups are built per call and dropped at the end of it.
+        // wgpu keeps the bound views alive itself, so caching them is memory safe, but a
+        // cached group would keep sampling the old views after `resize` or when called with
+        // different targets. A cache has to be rebuilt whenever any of these views change
         let composite_group1_bind_group =
             self.device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout: &self.group1_layout,
@@ -593,69 +795,189 @@ impl BloomEffect {
                 label: Some("Composite Group 1 Bind Group"),
             });
 
+        let mut composite_group2_entries = self
+            .vertical_blur_views
+            .iter()
+            .zip(0..)
+            .map(|(view, binding)| wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect::<Vec<_>>();
+        composite_group2_entries.push(wgpu::BindGroupEntry {
+            binding: self.max_level,
+            resource: wgpu::BindingResource::Sampler(&self.sampler),
+        });
         let composite_group2_bind_group =
             self.device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout: &self.group2_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[0]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[1]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[2]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[3]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 4,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[4]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 5,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[5]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 6,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[6]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 7,
-                        resource: wgpu::BindingResource::TextureView(&self.vertical_blur_views[7]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 8,
-                        resource: wgpu::BindingResource::Sampler(&self.sampler),
-                    },
-                ],
+                entries: &composite_group2_entries,
                 label: Some("Composite Group 2 Bind Group"),
             });
 
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Composite Compute Pass"),
-            timestamp_writes: None,
-        });
-        cpass.set_pipeline(&self.composite_pipeline);
-        cpass.set_bind_group(0, &self.settings_bind_group, &[]);
-        cpass.set_bind_group(1, &composite_group1_bind_group, &[]);
-        cpass.set_bind_group(2, &composite_group2_bind_group, &[]);
-        let dispatch_x = (self.full_width + 7) / 8;
-        let dispatch_y = (self.full_height + 7) / 8;
-        cpass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Composite Compute Pass"),
+                timestamp_writes: self.timestamp_writes(composite_pass(self.max_level)),
+            });
+            cpass.set_pipeline(&self.composite_pipeline);
+            cpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            cpass.set_bind_group(1, &composite_group1_bind_group, &[]);
+            cpass.set_bind_group(2, &composite_group2_bind_group, &[]);
+            let dispatch_x = dispatch_count(self.full_width);
+            let dispatch_y = dispatch_count(self.full_height);
+            cpass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
+        }
+
+        // A frame's timestamps are resolved here and mapped once the next frame records,
+        // after the commands that wrote them have been submitted
+        if let Some(timestamps) = &self.timestamps {
+            match timestamps.state() {
+                TimestampState::Idle => {
+                    let queries = 2 * (composite_pass(self.max_level) + 1);
+                    encoder.resolve_query_set(
+                        &timestamps.query_set,
+                        0..queries,
+                        &timestamps.resolve_buffer,
+                        0,
+                    );
+                    encoder.copy_buffer_to_buffer(
+                        &timestamps.resolve_buffer,
+                        0,
+                        &timestamps.readback_buffer,
+                        0,
+                        queries as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress,
+                    );
+                    timestamps.set_state(TimestampState::Copied);
+                }
+                TimestampState::Copied => {
+                    timestamps.set_state(TimestampState::Mapping);
+                    timestamps.map(self.max_level);
+                }
+                TimestampState::Mapping => {}
+            }
+        }
+    }
+}
+
+impl RenderNode for BloomEffect {
+    /// Input: the scene, output: the scene with bloom composited
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.render(encoder, inputs[0].view);
+        self.apply(encoder, outputs[0].view, inputs[0].view);
+    }
+}
+
+struct BloomPipelines {
+    prefilter: wgpu::ComputePipeline,
+    downsample: wgpu::ComputePipeline,
+    horizontal_blur: wgpu::ComputePipeline,
+    vertical_blur: wgpu::ComputePipeline,
+    composite: wgpu::ComputePipeline,
+}
+
+impl BloomPipelines {
+    fn new(
+        device: &wgpu::Device,
+        group0_layout: &wgpu::BindGroupLayout,
+        group1_layout: &wgpu::BindGroupLayout,
+        group2_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let prefilter = create_compute_pipeline(
+            device,
+            &[group0_layout, group1_layout],
+            shader,
+            "prefilter_main",
+            "Prefilter Pipeline",
+            cache,
+        );
+        let downsample = create_compute_pipeline(
+            device,
+            &[group0_layout, group1_layout],
+            shader,
+            "downsample_main",
+            "Downsample Pipeline",
+            cache,
+        );
+        let horizontal_blur = create_compute_pipeline(
+            device,
+            &[group0_layout, group1_layout],
+            shader,
+            "horizontal_blur_main",
+            "Horizontal Blur Pipeline",
+            cache,
+        );
+        let vertical_blur = create_compute_pipeline(
+            device,
+            &[group0_layout, group1_layout],
+            shader,
+            "vertical_blur_main",
+            "Vertical Blur Pipeline",
+            cache,
+        );
+        let composite = create_compute_pipeline(
+            device,
+            &[group0_layout, group1_layout, group2_layout],
+            shader,
+            "composite_main",
+            "Composite Pipeline",
+            cache,
+        );
+
+        Self {
+            prefilter,
+            downsample,
+            horizontal_blur,
+            vertical_blur,
+            composite,
+        }
     }
 }
 
+// Compute pass numbers of the blurs, the prefilter is pass 0 and downsample `i` is pass `i`
+fn blur_pass(max_level: u32, mip: u32) -> u32 {
+    max_level + 2 * mip
+}
+
+fn composite_pass(max_level: u32) -> u32 {
+    3 * max_level
+}
+
+fn create_prefilter_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scene_texture_view: &wgpu::TextureView,
+    downsample_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(scene_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(downsample_view),
+            },
+        ],
+        label: Some("Prefilter Group 1 Bind Group"),
+    })
+}
+
 fn create_mip_texture(
     device: &wgpu::Device,
     width: u32,
     height: u32,
     mip_count: u32,
+    format: wgpu::TextureFormat,
     label: &str,
 ) -> wgpu::Texture {
     device.create_texture(&wgpu::TextureDescriptor {
@@ -668,12 +990,23 @@ fn create_mip_texture(
         mip_level_count: mip_count,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba32Float,
+        format,
         usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
         view_formats: &[],
     })
 }
 
+/// Mip levels the bloom chain can have at `half_width` x `half_height`, so the last one
+/// is still at least a pixel on the short axis rather than repeating a 1x1 level
+fn bloom_level_count(half_width: u32, half_height: u32) -> u32 {
+    (31 - half_width.min(half_height).max(1).leading_zeros()).max(1)
+}
+
+/// Size of mip `level` along one axis, matching what wgpu allocates for it
+fn mip_size(size: u32, level: u32) -> u32 {
+    (size >> level).max(1)
+}
+
 fn create_mip_views(texture: &wgpu::Texture, mip_count: u32) -> Vec<wgpu::TextureView> {
     (0..mip_count)
         .map(|level| {
@@ -687,12 +1020,46 @@ fn create_mip_views(texture: &wgpu::Texture, mip_count: u32) -> Vec<wgpu::Textur
         .collect()
 }
 
-fn create_compute_pipeline(
+/// The post-processing shaders declare their HDR storage textures as `rgba32float`,
+/// this swaps that for `format` when rendering at a lower precision
+pub(crate) fn storage_format_source(source: &str, format: wgpu::TextureFormat) -> Cow<'_, str> {
+    match format {
+        wgpu::TextureFormat::Rgba16Float => {
+            Cow::Owned(source.replace("rgba32float", "rgba16float"))
+        }
+        _ => Cow::Borrowed(source),
+    }
+}
+
+/// `source` with the composite pass bindings appended: `bloom0` up to the last mip
+/// level, `bloom_sampler` right after them and `sample_bloom` summing every level
+fn bloom_source(source: &str, format: wgpu::TextureFormat, max_level: u32) -> String {
+    let mut source = storage_format_source(source, format).into_owned();
+    let mut sum = String::new();
+    for level in 0..max_level {
+        source += &format!("@group(2) @binding({level}) var bloom{level}: texture_2d<f32>;\n");
+        let weight = BLOOM_LEVEL_WEIGHTS
+            .get(level as usize)
+            .copied()
+            .unwrap_or(1.0);
+        sum += &format!(
+            "    bloom += textureSampleBicubic(bloom{level}, bloom_sampler, uv).rgb * {weight:?};\n"
+        );
+    }
+    source += &format!("@group(2) @binding({max_level}) var bloom_sampler: sampler;\n");
+    source += &format!(
+        "fn sample_bloom(uv: vec2<f32>) -> vec3<f32> {{\n    var bloom = vec3<f32>(0.0);\n{sum}    return bloom;\n}}\n"
+    );
+    source
+}
+
+pub(crate) fn create_compute_pipeline(
     device: &wgpu::Device,
     bind_group_layouts: &[&wgpu::BindGroupLayout],
     shader: &wgpu::ShaderModule,
     entry_point: &str,
     label: &str,
+    cache: Option<&wgpu::PipelineCache>,
 ) -> wgpu::ComputePipeline {
     let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some(label),
@@ -705,6 +1072,60 @@ fn create_compute_pipeline(
         module: shader,
         entry_point: Some(entry_point),
         compilation_options: Default::default(),
-        cache: None,
+        cache,
     })
 }
+
+/// Workgroups needed to cover `size` pixels along one axis
+fn dispatch_count(size: u32) -> u32 {
+    size.div_ceil(BLOOM_WORKGROUP_SIZE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bloom_level_count, bloom_source, dispatch_count, mip_size, BLOOM_WORKGROUP_SIZE};
+
+    #[test]
+    pub fn test_dispatch_covers_texture() {
+        for width in [1, 7, 8, 9, 400, 799, 1920] {
+            let dispatch_x = dispatch_count(width);
+            assert!(dispatch_x * BLOOM_WORKGROUP_SIZE >= width);
+            // No whole workgroup past the edge
+            assert!((dispatch_x - 1) * BLOOM_WORKGROUP_SIZE < width);
+        }
+    }
+
+    #[test]
+    pub fn test_mip_size_matches_wgpu() {
+        for width in [1, 7, 400, 799, 960] {
+            let extent = wgpu::Extent3d {
+                width,
+                height: 1,
+                depth_or_array_layers: 1,
+            };
+            for level in 1..8 {
+                let expected = extent
+                    .mip_level_size(level, wgpu::TextureDimension::D2)
+                    .width;
+                assert_eq!(mip_size(width, level), expected);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_level_count_fits_small_windows() {
+        // A 200x200 window blooms at 100x100
+        assert_eq!(bloom_level_count(100, 100), 6);
+        assert_eq!(mip_size(100, bloom_level_count(100, 100) - 1), 3);
+        assert_eq!(bloom_level_count(960, 540), 9);
+        assert_eq!(bloom_level_count(1, 0), 1);
+    }
+
+    #[test]
+    pub fn test_composite_bindings_follow_max_level() {
+        let source = bloom_source("", wgpu::TextureFormat::Rgba32Float, 10);
+        assert!(source.contains("@group(2) @binding(9) var bloom9: texture_2d<f32>;"));
+        assert!(source.contains("@group(2) @binding(10) var bloom_sampler: sampler;"));
+        assert_eq!(source.matches("textureSampleBicubic").count(), 10);
+    }
+}
@@ -12,7 +12,7 @@ struct BloomSettings {
 @group(1) @binding(0) var scene: texture_2d<f32>;
 @group(1) @binding(1) var output: texture_storage_2d<rgba32float, write>;
 
-@compute @workgroup_size(8, 8)
+@compute @workgroup_size(8, 8) // BLOOM_WORKGROUP_SIZE in bloom.rs
 fn prefilter_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let dims = textureDimensions(output);
     if (id.x >= dims.x || id.y >= dims.y) {
@@ -41,7 +41,7 @@ fn prefilter_main(@builtin(global_invocation_id) id: vec3<u32>) {
 @group(1) @binding(0) var input_texture: texture_2d<f32>;
 @group(1) @binding(1) var output_texture: texture_storage_2d<rgba32float, write>;
 
-@compute @workgroup_size(8, 8)
+@compute @workgroup_size(8, 8) // BLOOM_WORKGROUP_SIZE in bloom.rs
 fn downsample_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let dims = textureDimensions(output_texture);
     if (id.x >= dims.x || id.y >= dims.y) {
@@ -50,14 +50,15 @@ fn downsample_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let input_dims = textureDimensions(input_texture);
     let x = id.x * 2u;
     let y = id.y * 2u;
+    // Odd sized inputs have one texel left over, the last row and column take it
+    let size_x = select(2u, input_dims.x - x, id.x == dims.x - 1u);
+    let size_y = select(2u, input_dims.y - y, id.y == dims.y - 1u);
     var color = vec4<f32>(0.0);
     var count = 0.0;
-    for (var dx = 0u; dx < 2u; dx = dx + 1u) {
-        for (var dy = 0u; dy < 2u; dy = dy + 1u) {
-            if (x + dx < input_dims.x && y + dy < input_dims.y) {
-                color += textureLoad(input_texture, vec2<i32>(i32(x + dx), i32(y + dy)), 0);
-                count += 1.0;
-            }
+    for (var dx = 0u; dx < size_x; dx = dx + 1u) {
+        for (var dy = 0u; dy < size_y; dy = dy + 1u) {
+            color += textureLoad(input_texture, vec2<i32>(i32(x + dx), i32(y + dy)), 0);
+            count += 1.0;
         }
     }
     color /= count;
@@ -67,7 +68,24 @@ fn downsample_main(@builtin(global_invocation_id) id: vec3<u32>) {
 // Blur Shaders (5-tap Gaussian)
 const BLUR_WEIGHTS: array<f32, 5> = array<f32, 5>(0.19638062, 0.29675293, 0.09442139, 0.01037598, 0.00025940);
 
-@compute @workgroup_size(8, 8)
+// Weight of tap `i` in -2..2 for the selected kernel
+fn blur_weight(i: i32) -> f32 {
+    switch settings.blur_type {
+        // Box: plain average of the neighborhood
+        case 1u: {
+            return 1.0 / 5.0;
+        }
+        // Tent: linear falloff, 1 2 3 2 1
+        case 2u: {
+            return f32(3 - abs(i)) / 9.0;
+        }
+        default: {
+            return BLUR_WEIGHTS[u32(abs(i))];
+        }
+    }
+}
+
+@compute @workgroup_size(8, 8) // BLOOM_WORKGROUP_SIZE in bloom.rs
 fn horizontal_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let dims = textureDimensions(output_texture);
     if (id.x >= dims.x || id.y >= dims.y) {
@@ -78,13 +96,13 @@ fn horizontal_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
         let offset = i32(i) * i32(settings.blur_radius);
         let coord = i32(id.x) + offset;
         if (coord >= 0 && coord < i32(dims.x)) {
-            color += textureLoad(input_texture, vec2<i32>(coord, i32(id.y)), 0).rgb * BLUR_WEIGHTS[u32(abs(i))];
+            color += textureLoad(input_texture, vec2<i32>(coord, i32(id.y)), 0).rgb * blur_weight(i);
         }
     }
     textureStore(output_texture, vec2<i32>(i32(id.x), i32(id.y)), vec4<f32>(color, 1.0));
 }
 
-@compute @workgroup_size(8, 8)
+@compute @workgroup_size(8, 8) // BLOOM_WORKGROUP_SIZE in bloom.rs
 fn vertical_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let dims = textureDimensions(output_texture);
     if (id.x >= dims.x || id.y >= dims.y) {
@@ -95,7 +113,7 @@ fn vertical_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
         let offset = i32(i) * i32(settings.blur_radius);
         let coord = i32(id.y) + offset;
         if (coord >= 0 && coord < i32(dims.y)) {
-            color += textureLoad(input_texture, vec2<i32>(i32(id.x), coord), 0).rgb * BLUR_WEIGHTS[u32(abs(i))];
+            color += textureLoad(input_texture, vec2<i32>(i32(id.x), coord), 0).rgb * blur_weight(i);
         }
     }
     textureStore(output_texture, vec2<i32>(i32(id.x), i32(id.y)), vec4<f32>(color, 1.0));
@@ -105,16 +123,8 @@ fn vertical_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
 @group(1) @binding(0) var scene_tex: texture_2d<f32>;
 @group(1) @binding(1) var output_tex: texture_storage_2d<rgba32float, write>;
 
-// Bloom textures and sampler
-@group(2) @binding(0) var bloom0: texture_2d<f32>;
-@group(2) @binding(1) var bloom1: texture_2d<f32>;
-@group(2) @binding(2) var bloom2: texture_2d<f32>;
-@group(2) @binding(3) var bloom3: texture_2d<f32>;
-@group(2) @binding(4) var bloom4: texture_2d<f32>;
-@group(2) @binding(5) var bloom5: texture_2d<f32>;
-@group(2) @binding(6) var bloom6: texture_2d<f32>;
-@group(2) @binding(7) var bloom7: texture_2d<f32>;
-@group(2) @binding(8) var bloom_sampler: sampler;
+// Group 2 holds one bloom texture per mip level and the sampler after them, declared
+// along with `sample_bloom` by `bloom_source` in bloom.rs to match the level count
 
 
 fn cubic(v: f32) -> vec4<f32> {
@@ -148,7 +158,10 @@ fn textureSampleBicubic(tex: texture_2d<f32>, tex_sampler: sampler, texCoords: v
     return mix(mix(sample3, sample2, vec4<f32>(sx)), mix(sample1, sample0, vec4<f32>(sx)), vec4<f32>(sy));
 }
 
-@compute @workgroup_size(8, 8)
+// Weight of the summed bloom levels added to the scene
+const BLOOM_STRENGTH: f32 = 0.3;
+
+@compute @workgroup_size(8, 8) // BLOOM_WORKGROUP_SIZE in bloom.rs
 fn composite_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let dims = textureDimensions(output_tex);
     if (id.x >= dims.x || id.y >= dims.y) {
@@ -159,26 +172,16 @@ fn composite_main(@builtin(global_invocation_id) id: vec3<u32>) {
     let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(dims.xy);
     
     // Sample scene texture
-    var color = textureLoad(scene_tex, vec2<i32>(i32(id.x), i32(id.y)), 0).rgb;
-    color = ACESFilm(color);
-
-    var bloom = vec3<f32>(0.0);
+    let scene = textureLoad(scene_tex, vec2<i32>(i32(id.x), i32(id.y)), 0);
 
     // Sample bloom textures with bicubic filtering and add contributions
-    bloom += textureSampleBicubic(bloom0, bloom_sampler, uv).rgb * 1.0;
-    bloom += textureSampleBicubic(bloom1, bloom_sampler, uv).rgb * 1.5;
-    bloom += textureSampleBicubic(bloom2, bloom_sampler, uv).rgb * 1.0;
-    bloom += textureSampleBicubic(bloom3, bloom_sampler, uv).rgb * 1.5;
-    bloom += textureSampleBicubic(bloom4, bloom_sampler, uv).rgb * 1.8;
-    bloom += textureSampleBicubic(bloom5, bloom_sampler, uv).rgb * 1.0;
-    bloom += textureSampleBicubic(bloom6, bloom_sampler, uv).rgb * 1.0;
-    bloom += textureSampleBicubic(bloom7, bloom_sampler, uv).rgb * 1.0;
-
-    // Add bloom to scene color
-    // color += bloom * 0.3;
+    let bloom = sample_bloom(uv);
+
+    // Add bloom to the HDR scene color before tone mapping
+    let color = ACESFilm(scene.rgb + bloom * BLOOM_STRENGTH);
 
     // Write to output texture
-    textureStore(output_tex, vec2<i32>(i32(id.x), i32(id.y)), vec4<f32>(color, 1.0));
+    textureStore(output_tex, vec2<i32>(i32(id.x), i32(id.y)), vec4<f32>(color, scene.a));
 }
 
 fn ACESFilm(x: vec3<f32>) -> vec3<f32> {
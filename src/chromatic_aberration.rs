@@ -0,0 +1,258 @@
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::borrow::Cow;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChromaticAberrationSettings {
+    pub strength: f32, // 0 disables the effect
+    _padding: [f32; 3],
+}
+
+impl Default for ChromaticAberrationSettings {
+    fn default() -> Self {
+        Self {
+            strength: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Splits the red and blue channels radially, growing toward the screen edges.
+/// The swapchain can't be sampled, so this runs on the post process texture right
+/// before the color correction. Both are per pixel, so the order barely matters
+pub struct ChromaticAberrationEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    settings: ChromaticAberrationSettings,
+    settings_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    output_texture: wgpu::Texture,
+    output_view: wgpu::TextureView,
+    pipeline: wgpu::RenderPipeline,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl ChromaticAberrationEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        input_texture_view: &wgpu::TextureView,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Chromatic Aberration Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "chromatic_aberration.wgsl"
+            ))),
+        });
+
+        let settings = ChromaticAberrationSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Chromatic Aberration Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Chromatic Aberration Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = create_bind_group(
+            &device,
+            &bind_group_layout,
+            &settings_buffer,
+            input_texture_view,
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Chromatic Aberration Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Chromatic Aberration Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let output_texture = create_output_texture(&device, width, height, format);
+        let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            device,
+            queue,
+            settings,
+            settings_buffer,
+            bind_group_layout,
+            bind_group,
+            output_texture,
+            output_view,
+            pipeline,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, input_texture_view: &wgpu::TextureView) {
+        self.width = width;
+        self.height = height;
+        self.output_texture = create_output_texture(&self.device, width, height, self.format);
+        self.output_view = self
+            .output_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.bind_group = create_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            &self.settings_buffer,
+            input_texture_view,
+        );
+    }
+
+    pub fn settings(&self) -> ChromaticAberrationSettings {
+        self.settings
+    }
+
+    pub fn update_uniform(&mut self, settings: ChromaticAberrationSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Splits the channels of `input_texture` in place, skipped entirely while the strength is zero
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, input_texture: &wgpu::Texture) {
+        if self.settings.strength <= 0.0 {
+            return;
+        }
+
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Chromatic Aberration Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.output_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            input_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for ChromaticAberrationEffect {
+    /// Reads and writes the post process texture, declared as its single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].texture);
+    }
+}
+
+fn create_output_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Chromatic Aberration Output Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    settings_buffer: &wgpu::Buffer,
+    input_texture_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(input_texture_view),
+            },
+        ],
+        label: Some("Chromatic Aberration Bind Group"),
+    })
+}
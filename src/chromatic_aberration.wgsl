@@ -0,0 +1,51 @@
+struct ChromaticAberrationSettings {
+    strength: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+};
+
+@group(0) @binding(0) var<uniform> settings: ChromaticAberrationSettings;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+
+// Fullscreen triangle, the corners past the screen are clipped
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let positions = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(positions[vertex_index], 0.0, 1.0);
+}
+
+fn load_clamped(pixel: vec2<i32>, dims: vec2<i32>) -> vec4<f32> {
+    return textureLoad(input_texture, clamp(pixel, vec2<i32>(0), dims - 1), 0);
+}
+
+// Rgba32Float isn't filterable, so filter by hand
+fn load_bilinear(uv: vec2<f32>) -> vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(input_texture));
+    let p = uv * vec2<f32>(dims) - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = fract(p);
+    let top = mix(load_clamped(base, dims), load_clamped(base + vec2<i32>(1, 0), dims), f.x);
+    let bottom = mix(
+        load_clamped(base + vec2<i32>(0, 1), dims),
+        load_clamped(base + vec2<i32>(1, 1), dims),
+        f.x
+    );
+    return mix(top, bottom, f.y);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let dims = vec2<f32>(textureDimensions(input_texture));
+    let uv = frag_coord.xy / dims;
+    // Grows with the distance from the center, red pushed out and blue pulled in
+    let offset = (uv - 0.5) * settings.strength;
+    let center = load_bilinear(uv);
+    let r = load_bilinear(uv + offset).r;
+    let b = load_bilinear(uv - offset).b;
+    return vec4<f32>(r, center.g, b, center.a);
+}
@@ -0,0 +1,274 @@
+use crate::{ProcGenTerrain, VoxelChunk, WgpuCtx};
+use cgmath::{Point3, Vector3};
+use hecs::World;
+use std::collections::HashSet;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+// Chunks generating on worker threads at once, the nearest missing chunks start first
+const MAX_PENDING_CHUNKS: usize = 8;
+// Chunks never get coarser than this many voxels per side
+const MIN_CHUNK_RESOLUTION: u32 = 4;
+
+struct GeneratedChunk {
+    position: Vector3<i32>,
+    terrain: ProcGenTerrain,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkAtlasHeader {
+    slots: i32,      // Per side of the atlas
+    resolution: i32, // Voxels per slot side
+    chunk_size: f32,
+    _padding: f32,
+}
+
+const SLOTS_OFFSET: wgpu::BufferAddress =
+    std::mem::size_of::<ChunkAtlasHeader>() as wgpu::BufferAddress;
+// Chunk position and voxels per side, as a vec4<i32>
+const SLOT_SIZE: wgpu::BufferAddress = 16;
+
+/// The streamed chunk volumes packed into one 3D texture for the voxel shader. A chunk
+/// goes into the slot at its position modulo the slots per side, so the chunks in view
+/// never share one. `table` holds the chunk position and voxels per side of each slot,
+/// the shader falls back to the ProcGenTerrain volume where they don't match
+pub struct ChunkAtlas {
+    pub texture: wgpu::Texture,
+    pub table: wgpu::Buffer,
+    slots: i32,
+    resolution: u32,
+    chunk_size: f32,
+}
+
+impl ChunkAtlas {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        slots: i32,
+        resolution: u32,
+        chunk_size: f32,
+    ) -> Self {
+        let side = slots as u32 * resolution;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Chunk Atlas Texture"),
+            size: wgpu::Extent3d {
+                width: side,
+                height: side,
+                depth_or_array_layers: side,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: ProcGenTerrain::texture_format(device),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        // Zeroed slots have no voxels, so they start out empty
+        let table = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Chunk Atlas Table Buffer"),
+            size: SLOTS_OFFSET + SLOT_SIZE * (slots as wgpu::BufferAddress).pow(3),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let header = ChunkAtlasHeader {
+            slots,
+            resolution: resolution as i32,
+            chunk_size,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&table, 0, bytemuck::cast_slice(&[header]));
+        Self {
+            texture,
+            table,
+            slots,
+            resolution,
+            chunk_size,
+        }
+    }
+
+    pub fn fits(&self, slots: i32, resolution: u32, chunk_size: f32) -> bool {
+        self.slots == slots && self.resolution == resolution && self.chunk_size == chunk_size
+    }
+
+    /// Copies a chunk volume no bigger than the slot resolution into the chunk's slot
+    pub fn write_chunk(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        position: Vector3<i32>,
+        volume: &wgpu::Texture,
+    ) {
+        let slot = position.map(|v| v.rem_euclid(self.slots));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Chunk Atlas Copy Encoder"),
+        });
+        encoder.copy_texture_to_texture(
+            volume.as_image_copy(),
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: slot.x as u32 * self.resolution,
+                    y: slot.y as u32 * self.resolution,
+                    z: slot.z as u32 * self.resolution,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            volume.size(),
+        );
+        let index = ((slot.z * self.slots + slot.y) * self.slots + slot.x) as wgpu::BufferAddress;
+        let entry = [position.x, position.y, position.z, volume.width() as i32];
+        queue.write_buffer(
+            &self.table,
+            SLOTS_OFFSET + SLOT_SIZE * index,
+            bytemuck::cast_slice(&entry),
+        );
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// Keeps `VoxelChunk` entities spawned around the camera. Missing chunks generate
+/// their SDF on worker threads and get uploaded into the `ChunkAtlas` once done,
+/// chunks out of view distance are despawned and their textures freed
+pub struct ChunkStreamingSystem {
+    pub chunk_size: f32,    // World units per chunk side
+    pub resolution: u32,    // Voxels per chunk side at lod 0
+    pub view_distance: i32, // In chunks along each axis
+    pub lod_distance: i32,  // Chunks per lod level
+    pub seed: u64,
+    pending: HashSet<Vector3<i32>>,
+    sender: Sender<GeneratedChunk>,
+    receiver: Receiver<GeneratedChunk>,
+}
+
+impl Default for ChunkStreamingSystem {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            chunk_size: 64.0,
+            resolution: 32,
+            view_distance: 2,
+            lod_distance: 2,
+            seed: 0,
+            pending: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl ChunkStreamingSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chunk_at(&self, position: Point3<f32>) -> Vector3<i32> {
+        Vector3::new(
+            (position.x / self.chunk_size).floor() as i32,
+            (position.y / self.chunk_size).floor() as i32,
+            (position.z / self.chunk_size).floor() as i32,
+        )
+    }
+
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        wgpu_ctx: &mut WgpuCtx,
+        camera_position: Point3<f32>,
+    ) {
+        // The atlas has a slot for every chunk in view, a new one lost the written chunks
+        let slots = 2 * self.view_distance + 1;
+        let resolution = self.resolution.max(MIN_CHUNK_RESOLUTION);
+        if wgpu_ctx.resize_chunk_atlas(slots, resolution, self.chunk_size) {
+            for (_, chunk) in world.query::<&VoxelChunk>().iter() {
+                if let Some(texture) = &chunk.data {
+                    wgpu_ctx.write_chunk_volume(chunk.position, texture);
+                }
+            }
+        }
+
+        let center = self.chunk_at(camera_position);
+        let distance = |position: Vector3<i32>| {
+            let offset = position - center;
+            offset.x.abs().max(offset.y.abs()).max(offset.z.abs())
+        };
+
+        // Despawn chunks that left the view distance
+        let out_of_view: Vec<_> = wgpu_ctx
+            .voxel_chunks
+            .keys()
+            .copied()
+            .filter(|&position| distance(position) > self.view_distance)
+            .collect();
+        for position in out_of_view {
+            let entity = wgpu_ctx.voxel_chunks.remove(&position).unwrap();
+            if let Ok(chunk) = world.remove_one::<VoxelChunk>(entity) {
+                if let Some(texture) = chunk.data {
+                    texture.destroy();
+                }
+            }
+            let _ = world.despawn(entity);
+        }
+
+        // Upload chunks the workers finished, dropping those despawned in the meantime
+        for generated in self.receiver.try_iter() {
+            self.pending.remove(&generated.position);
+            let Some(&entity) = wgpu_ctx.voxel_chunks.get(&generated.position) else {
+                continue;
+            };
+            let texture = wgpu_ctx.upload_procgen_volume(&generated.terrain);
+            wgpu_ctx.write_chunk_volume(generated.position, &texture);
+            if let Ok(chunk) = world.query_one_mut::<&mut VoxelChunk>(entity) {
+                chunk.data = Some(texture);
+            }
+        }
+
+        // Spawn entities for the chunks that came into view
+        let range = -self.view_distance..=self.view_distance;
+        let mut missing = Vec::new();
+        for z in range.clone() {
+            for y in range.clone() {
+                for x in range.clone() {
+                    let position = center + Vector3::new(x, y, z);
+                    if !wgpu_ctx.voxel_chunks.contains_key(&position) {
+                        missing.push(position);
+                    }
+                }
+            }
+        }
+        for position in missing {
+            let lod = (distance(position) / self.lod_distance.max(1)) as u32;
+            let entity = world.spawn((VoxelChunk {
+                position,
+                lod,
+                data: None,
+            },));
+            wgpu_ctx.voxel_chunks.insert(position, entity);
+        }
+
+        // Start generating the nearest chunks that have no data yet
+        let mut waiting: Vec<_> = world
+            .query_mut::<&VoxelChunk>()
+            .into_iter()
+            .filter(|(_, chunk)| chunk.data.is_none() && !self.pending.contains(&chunk.position))
+            .map(|(_, chunk)| (chunk.position, chunk.lod))
+            .collect();
+        waiting.sort_by_key(|&(position, _)| distance(position));
+        let free = MAX_PENDING_CHUNKS.saturating_sub(self.pending.len());
+        for (position, lod) in waiting.into_iter().take(free) {
+            self.pending.insert(position);
+            let size = (self.resolution >> lod).max(MIN_CHUNK_RESOLUTION);
+            let seed = self.seed;
+            let sender = self.sender.clone();
+            std::thread::spawn(move || {
+                let origin = [position.x as f64, position.y as f64, position.z as f64];
+                let terrain = ProcGenTerrain {
+                    size,
+                    sdf: ProcGenTerrain::generate_chunk(size, seed, origin),
+                };
+                // The receiver only goes away with the system
+                let _ = sender.send(GeneratedChunk { position, terrain });
+            });
+        }
+    }
+}
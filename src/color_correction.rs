@@ -1,3 +1,5 @@
+use crate::render_graph::{RenderNode, RenderTarget};
+use crate::{with_validation, FrameAllocator};
 use std::sync::Arc;
 
 use std::borrow::Cow;
@@ -10,23 +12,54 @@ pub struct ColorCorrectionUniform {
     pub brightness: f32,
     pub contrast: f32,
     pub saturation: f32,
+    pub gamma: f32,
+    pub vignette_strength: f32, // Width of the darkening falloff, 0 disables the vignette
+    pub vignette_radius: f32,   // Distance from the screen center where darkening starts
+    pub premultiply_alpha: u32, // Set for transparent surfaces, the sky keeps its alpha of 0
+    _padding: f32,
+}
+
+impl Default for ColorCorrectionUniform {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            gamma: 1.0,
+            vignette_strength: 0.0,
+            vignette_radius: 0.75,
+            premultiply_alpha: 0,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl ColorCorrectionUniform {
+    /// Defaults for a surface that is transparent when `premultiply_alpha` is set
+    pub fn with_premultiplied_alpha(premultiply_alpha: bool) -> Self {
+        Self {
+            premultiply_alpha: premultiply_alpha as u32,
+            ..Default::default()
+        }
+    }
 }
 
 pub struct ColorCorrectionEffect {
     device: Arc<wgpu::Device>,
-    queue: Arc<wgpu::Queue>,
     sampler: Arc<wgpu::Sampler>,
     cc_pipeline: wgpu::RenderPipeline,
     cc_bind_group_layout: wgpu::BindGroupLayout,
+    surface_format: wgpu::TextureFormat,
     uniform_buffer: wgpu::Buffer,
+    exposure_view: wgpu::TextureView,
     bind_group: wgpu::BindGroup,
 }
 
 impl ColorCorrectionEffect {
     pub fn new(
         device: Arc<wgpu::Device>,
-        queue: Arc<wgpu::Queue>,
         input_texture_view: &wgpu::TextureView,
+        exposure_view: &wgpu::TextureView,
         sampler: Arc<wgpu::Sampler>,
         surface_format: wgpu::TextureFormat,
     ) -> Self {
@@ -61,16 +94,20 @@ impl ColorCorrectionEffect {
                         },
                         count: None,
                     },
+                    // 1x1 exposure from AutoExposureEffect
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        // Create pipeline layout
-        let cc_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Color Correction Pipeline Layout"),
-            bind_group_layouts: &[&cc_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
         // Load shader
         let cc_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Color Correction Shader"),
@@ -78,44 +115,13 @@ impl ColorCorrectionEffect {
         });
 
         // Create render pipeline
-        let cc_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Color Correction Pipeline"),
-            layout: Some(&cc_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &cc_shader,
-                entry_point: Some("vs_main"),
-                buffers: &[],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &cc_shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(surface_format.into())],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        let cc_pipeline =
+            create_cc_pipeline(&device, &cc_bind_group_layout, &cc_shader, surface_format);
 
         // Create uniform buffer with default values
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Color Correction Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[ColorCorrectionUniform {
-                brightness: 1.0,
-                contrast: 1.0,
-                saturation: 1.0,
-            }]),
+            contents: bytemuck::cast_slice(&[ColorCorrectionUniform::default()]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
@@ -135,21 +141,45 @@ impl ColorCorrectionEffect {
                     binding: 2,
                     resource: uniform_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(exposure_view),
+                },
             ],
             label: Some("Color Correction Bind Group"),
         });
 
         Self {
             device,
-            queue,
             sampler,
             cc_pipeline,
             cc_bind_group_layout,
+            surface_format,
             uniform_buffer,
+            exposure_view: exposure_view.clone(),
             bind_group,
         }
     }
 
+    /// Recompiles `color_correction.wgsl` from `source`, keeping the current pipeline on failure
+    pub fn reload_shader(&mut self, source: &str) -> Result<(), String> {
+        self.cc_pipeline = with_validation(&self.device, || {
+            let cc_shader = self
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("Color Correction Shader"),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+            create_cc_pipeline(
+                &self.device,
+                &self.cc_bind_group_layout,
+                &cc_shader,
+                self.surface_format,
+            )
+        })?;
+        Ok(())
+    }
+
     pub fn resize(&mut self, input_texture_view: &wgpu::TextureView) {
         self.bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.cc_bind_group_layout,
@@ -166,19 +196,30 @@ impl ColorCorrectionEffect {
                     binding: 2,
                     resource: self.uniform_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.exposure_view),
+                },
             ],
             label: Some("Color Correction Bind Group"),
         });
     }
 
-    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, target_view: &wgpu::TextureView) {
+    /// Draws the corrected image to `target_view` with the caller's `load_op`. The quad is
+    /// opaque and covers the whole target, so `LoadOp::Load` just skips a redundant clear
+    pub fn apply(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+        load_op: wgpu::LoadOp<wgpu::Color>,
+    ) {
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Color Correction Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: target_view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    load: load_op,
                     store: wgpu::StoreOp::Store,
                 },
             })],
@@ -191,8 +232,72 @@ impl ColorCorrectionEffect {
         rpass.draw(0..4, 0..1);
     }
 
-    pub fn update_uniform(&self, uniform: ColorCorrectionUniform) {
-        self.queue
-            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    /// Writes the grading and vignette parameters through `frame_allocator`, so they
+    /// apply from the next submitted frame
+    pub fn update_uniform(
+        &self,
+        uniform: ColorCorrectionUniform,
+        frame_allocator: &mut FrameAllocator,
+    ) {
+        frame_allocator.write(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+impl RenderNode for ColorCorrectionEffect {
+    /// Samples the input bound at creation, writes to the single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(
+            encoder,
+            outputs[0].view,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+        );
     }
 }
+
+fn create_cc_pipeline(
+    device: &wgpu::Device,
+    cc_bind_group_layout: &wgpu::BindGroupLayout,
+    cc_shader: &wgpu::ShaderModule,
+    surface_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let cc_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Color Correction Pipeline Layout"),
+        bind_group_layouts: &[cc_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Color Correction Pipeline"),
+        layout: Some(&cc_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: cc_shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: cc_shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(surface_format.into())],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -44,16 +44,25 @@ fn tonemap(color: vec3<f32>) -> vec3<f32> {
     return c;
 }
 
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>, // Of the input, which may be smaller than the target
+};
+
 // Vertex Shader: Full-screen quad
 @vertex
-fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     let positions = array<vec2<f32>, 4>(
         vec2<f32>(-1.0, -1.0),
         vec2<f32>(1.0, -1.0),
         vec2<f32>(-1.0, 1.0),
         vec2<f32>(1.0, 1.0)
     );
-    return vec4<f32>(positions[vertex_index], 0.0, 1.0);
+    let position = positions[vertex_index];
+    var out: VertexOutput;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    out.tex_coord = position * vec2<f32>(0.5, -0.5) + 0.5;
+    return out;
 }
 
 // Fragment Shader: Color correction
@@ -61,17 +70,39 @@ struct ColorCorrectionUniform {
     brightness: f32,
     contrast: f32,
     saturation: f32,
+    gamma: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    premultiply_alpha: u32,
+    _padding0: f32,
 }
 
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
 @group(0) @binding(1) var input_sampler: sampler;
 @group(0) @binding(2) var<uniform> cc_uniform: ColorCorrectionUniform;
+@group(0) @binding(3) var exposure_texture: texture_2d<f32>;
 
 @fragment
-fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
-    let dims = textureDimensions(input_texture);
-    let tex_coord = frag_coord.xy / vec2<f32>(f32(dims.x), f32(dims.y));
-    var color = textureSample(input_texture, input_sampler, tex_coord);
-    color = vec4(tonemap(color.rgb), 1.0);
-    return color;
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(input_texture, input_sampler, in.tex_coord);
+    let exposure = textureLoad(exposure_texture, vec2<i32>(0), 0).r;
+    var c = tonemap(color.rgb * exposure);
+
+    c *= cc_uniform.brightness;
+    c = (c - 0.5) * cc_uniform.contrast + 0.5;
+    let luma = dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+    c = mix(vec3<f32>(luma), c, cc_uniform.saturation);
+    c = pow(max(c, vec3<f32>(0.0)), vec3<f32>(1.0 / cc_uniform.gamma));
+
+    // smoothstep needs distinct edges, a strength of 0 leaves the image as is
+    if cc_uniform.vignette_strength > 0.0 {
+        let dist = length(in.tex_coord - vec2<f32>(0.5));
+        let radius = cc_uniform.vignette_radius;
+        c *= smoothstep(radius, radius - cc_uniform.vignette_strength, dist);
+    }
+
+    if cc_uniform.premultiply_alpha != 0u {
+        return vec4(c * color.a, color.a);
+    }
+    return vec4(c, 1.0);
 }
@@ -1,7 +1,12 @@
-use cgmath::{InnerSpace, Matrix3, Point3, Quaternion, Rad, Rotation3, SquareMatrix, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Point3, Quaternion, Rad, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use winit::keyboard::KeyCode;
 
-#[derive(Debug)]
+// Seconds between keyframes added with `CameraAnimation::record`
+const RECORD_SPACING: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Transform {
     pub position: Point3<f32>,
     pub rotation: Quaternion<f32>,
@@ -18,9 +23,31 @@ impl Default for Transform {
     }
 }
 
-#[derive(Debug)]
+impl Transform {
+    /// Rotates the transform so -Z points at `target` and Y is as close to `up` as
+    /// possible. Left unchanged if `target` is the position or lies along `up`
+    pub fn look_at(&mut self, target: Point3<f32>, up: Vector3<f32>) {
+        let to_target = target - self.position;
+        let right = to_target.cross(up);
+        if to_target.magnitude2() <= f32::EPSILON || right.magnitude2() <= f32::EPSILON {
+            return;
+        }
+        let forward = to_target.normalize();
+        let right = right.normalize();
+        let up = right.cross(forward);
+        self.rotation = Quaternion::from(Matrix3::from_cols(right, up, -forward));
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CameraProjection {
+    Perspective { fov: Rad<f32> },
+    Orthographic { width: f32, height: f32 }, // World units covered by the view
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Camera {
-    pub fov: Rad<f32>,
+    pub projection: CameraProjection,
     pub aspect: f32,
     pub near: f32,
     pub far: f32,
@@ -30,7 +57,9 @@ pub struct Camera {
 impl Default for Camera {
     fn default() -> Self {
         Self {
-            fov: Rad(std::f32::consts::FRAC_PI_4),
+            projection: CameraProjection::Perspective {
+                fov: Rad(std::f32::consts::FRAC_PI_4),
+            },
             aspect: 16.0 / 9.0,
             near: 0.1,
             far: 100.0,
@@ -39,13 +68,17 @@ impl Default for Camera {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct CameraController {
     pub move_speed: f32,
     pub move_speed_mult: f32,
+    pub scroll_sensitivity: f32, // Fractional speed change per scroll wheel notch
     pub look_speed: f32,
     pub pitch: Rad<f32>,
     pub yaw: Rad<f32>,
+    pub sprint_multiplier: f32, // Speed multiple while the sprint key is held
+    pub sprint_key: KeyCode,
+    pub descend_key: KeyCode,
 }
 
 impl Default for CameraController {
@@ -53,14 +86,216 @@ impl Default for CameraController {
         Self {
             move_speed: 5.0,
             move_speed_mult: 1.0,
+            scroll_sensitivity: 0.1,
             look_speed: 0.003,
             pitch: Rad(0.0),
             yaw: Rad(0.0),
+            sprint_multiplier: 3.0,
+            sprint_key: KeyCode::ShiftLeft,
+            descend_key: KeyCode::KeyQ,
         }
     }
 }
 
+/// Cube map drawn behind the scene. `texture_path` is a directory holding the faces
+/// as px.png, nx.png, py.png, ny.png, pz.png and nz.png
+#[derive(Debug)]
+pub struct Skybox {
+    pub texture_path: String,
+}
+
+/// One streamed piece of the terrain SDF, `position` is in chunk coordinates.
+/// `data` stays None until the chunk finished generating
+#[derive(Debug)]
+pub struct VoxelChunk {
+    pub position: Vector3<i32>,
+    pub lod: u32, // Resolution is halved per level
+    pub data: Option<wgpu::Texture>,
+}
+
 #[derive(Debug)]
 pub struct ModelInstance {
-    pub model: usize, // Index into the model registry
+    pub model: crate::ModelKey,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CameraKeyframe {
+    pub time: f32, // Seconds from the start of the path
+    pub position: Point3<f32>,
+    pub rotation: Quaternion<f32>,
+}
+
+/// Scripted camera path, overrides the camera's `Transform` while playing.
+/// Keyframes must be sorted by time
+#[derive(Debug, Default)]
+pub struct CameraAnimation {
+    pub keyframes: Vec<CameraKeyframe>,
+    pub time: f32,
+    pub playing: bool,
+    pub loop_: bool,
+}
+
+impl CameraAnimation {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Appends the current camera pose `RECORD_SPACING` seconds after the last keyframe
+    pub fn record(&mut self, transform: &Transform) {
+        let time = self
+            .keyframes
+            .last()
+            .map_or(0.0, |keyframe| keyframe.time + RECORD_SPACING);
+        self.keyframes.push(CameraKeyframe {
+            time,
+            position: transform.position,
+            rotation: transform.rotation,
+        });
+    }
+
+    /// Moves the playhead, wrapping when looping and stopping at the end otherwise
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+        let duration = self.duration();
+        if self.time >= duration {
+            if self.loop_ && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+    }
+
+    /// Pose at `time`, cubic Hermite positions with Catmull-Rom tangents and slerped
+    /// rotations. None without keyframes
+    pub fn sample(&self, time: f32) -> Option<(Point3<f32>, Quaternion<f32>)> {
+        let keys = &self.keyframes;
+        let (first, last) = (keys.first()?, keys.last()?);
+        if time <= first.time {
+            return Some((first.position, first.rotation));
+        }
+        if time >= last.time {
+            return Some((last.position, last.rotation));
+        }
+
+        // First key after `time`, so the segment has a non zero duration
+        let next = keys.partition_point(|keyframe| keyframe.time <= time);
+        let (k0, k1) = (&keys[next - 1], &keys[next]);
+        let duration = k1.time - k0.time;
+        let s = (time - k0.time) / duration;
+
+        // Velocity through a key, one sided at the ends of the path
+        let tangent = |i: usize| {
+            let (prev, next) = (
+                &keys[i.saturating_sub(1)],
+                &keys[(i + 1).min(keys.len() - 1)],
+            );
+            (next.position - prev.position) / (next.time - prev.time).max(f32::EPSILON)
+        };
+        let (m0, m1) = (tangent(next - 1) * duration, tangent(next) * duration);
+
+        let (s2, s3) = (s * s, s * s * s);
+        let position = Point3::from_vec(
+            k0.position.to_vec() * (2.0 * s3 - 3.0 * s2 + 1.0)
+                + m0 * (s3 - 2.0 * s2 + s)
+                + k1.position.to_vec() * (-2.0 * s3 + 3.0 * s2)
+                + m1 * (s3 - s2),
+        );
+        Some((position, k0.rotation.slerp(k1.rotation, s)))
+    }
+}
+
+/// Playback state of a model's animation clip
+#[derive(Debug)]
+pub struct AnimationController {
+    pub clip_index: usize, // Index into the model's animations
+    pub time: f32,         // Seconds into the clip
+    pub playing: bool,
+}
+
+impl Default for AnimationController {
+    fn default() -> Self {
+        Self {
+            clip_index: 0,
+            time: 0.0,
+            playing: true,
+        }
+    }
+}
+
+/// Axis aligned bounds in model space, used for frustum culling
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingBox {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl BoundingBox {
+    /// Smallest box containing all `points`, or a degenerate box at the origin if empty
+    pub fn from_points<I: IntoIterator<Item = [f32; 3]>>(points: I) -> Self {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        let mut empty = true;
+        for [x, y, z] in points {
+            min = Point3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = Point3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+            empty = false;
+        }
+        if empty {
+            return Self {
+                min: Point3::new(0.0, 0.0, 0.0),
+                max: Point3::new(0.0, 0.0, 0.0),
+            };
+        }
+        Self { min, max }
+    }
+
+    /// Bounds of this box after applying `matrix` to all eight corners
+    pub fn transformed(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        use cgmath::Transform;
+        let corners = (0..8).map(|i| {
+            let corner = Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            matrix.transform_point(corner).into()
+        });
+        Self::from_points(corners)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::components::{CameraAnimation, Transform};
+    use cgmath::{InnerSpace, Point3, Vector3};
+
+    #[test]
+    pub fn test_look_at_forward_is_identity() {
+        let mut transform = Transform::default();
+        transform.look_at(Point3::new(0.0, 0.0, -1.0), Vector3::unit_y());
+        let rotation = transform.rotation;
+        assert!((rotation.s.abs() - 1.0).abs() < 1e-6);
+        assert!(rotation.v.x.abs() < 1e-6);
+        assert!(rotation.v.y.abs() < 1e-6);
+        assert!(rotation.v.z.abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn test_camera_animation_hits_keyframes() {
+        let mut animation = CameraAnimation::default();
+        for x in [0.0, 1.0, 4.0] {
+            animation.record(&Transform {
+                position: Point3::new(x, 0.0, 0.0),
+                ..Default::default()
+            });
+        }
+        for keyframe in &animation.keyframes {
+            let (position, _) = animation.sample(keyframe.time).unwrap();
+            assert!((position - keyframe.position).magnitude() < 1e-5);
+        }
+        let (position, _) = animation.sample(3.0).unwrap();
+        assert!(position.x > 1.0 && position.x < 4.0);
+    }
 }
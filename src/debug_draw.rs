@@ -0,0 +1,186 @@
+use cgmath::Point3;
+use std::borrow::Cow;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+// Line segments per circle of a debug sphere
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Immediate mode world space lines drawn over the scene. Shapes only live for
+/// the frame they were added in, `clear` starts a new frame
+pub struct DebugDraw {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    vertices: Vec<DebugVertex>,
+    vertex_buffer: Option<wgpu::Buffer>, // Grown on demand
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        time_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Draw Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("debug_draw.wgsl"))),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[time_bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                }],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                // Float32 targets aren't blendable without an extra feature, lines are opaque
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Overlays, drawn on top of everything
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue,
+            vertices: Vec::new(),
+            vertex_buffer: None,
+            pipeline,
+        }
+    }
+
+    /// Drops all shapes of the previous frame
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn draw_line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex {
+            position: a.into(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    pub fn draw_aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 4]) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        // Each edge joins two corners differing in a single axis bit
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.draw_line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    /// Approximates the sphere with three axis aligned circles
+    pub fn draw_sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4]) {
+        let point = |plane: usize, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            let (u, v) = (cos * radius, sin * radius);
+            match plane {
+                0 => Point3::new(center.x + u, center.y + v, center.z),
+                1 => Point3::new(center.x + u, center.y, center.z + v),
+                _ => Point3::new(center.x, center.y + u, center.z + v),
+            }
+        };
+        for plane in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                let a = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                let b = (i + 1) as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                self.draw_line(point(plane, a), point(plane, b), color);
+            }
+        }
+    }
+
+    /// Uploads this frame's lines and draws them over `target_view`
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+        time_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let data: &[u8] = bytemuck::cast_slice(&self.vertices);
+        let size = data.len() as wgpu::BufferAddress;
+        if self
+            .vertex_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.vertex_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Debug Draw Vertex Buffer"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let vertex_buffer = self.vertex_buffer.as_ref().unwrap();
+        self.queue.write_buffer(vertex_buffer, 0, data);
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug Draw Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, time_bind_group, &[]);
+        rpass.set_bind_group(1, camera_bind_group, &[]);
+        rpass.set_vertex_buffer(0, vertex_buffer.slice(..size));
+        rpass.draw(0..self.vertices.len() as u32, 0..1);
+    }
+}
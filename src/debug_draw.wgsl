@@ -0,0 +1,34 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+@group(1) @binding(0) var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(1) color: vec4f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+};
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = camera.view_proj * vec4f(vertex.position, 1.0);
+    // The camera projection uses OpenGL depth (-1..1), remap to wgpu's 0..1
+    output.position.z = output.position.z * 0.5 + output.position.w * 0.5;
+    output.color = vertex.color;
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4f {
+    return input.color;
+}
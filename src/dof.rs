@@ -0,0 +1,306 @@
+use crate::bloom::{create_compute_pipeline, storage_format_source};
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Must match `@workgroup_size` of the entry points in dof.wgsl
+const DOF_WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DofSettings {
+    pub focal_distance: f32,
+    pub aperture: f32, // 0 disables the effect
+    pub focal_range: f32,
+    _padding: f32,
+}
+
+impl Default for DofSettings {
+    fn default() -> Self {
+        Self {
+            focal_distance: 10.0,
+            aperture: 0.0,
+            focal_range: 2.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Inputs of `DepthOfFieldEffect::new`, the camera and the targets the blur reads
+pub struct DepthOfFieldParams<'a> {
+    pub camera_buffer: &'a wgpu::Buffer,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub scene_texture_view: &'a wgpu::TextureView,
+    pub depth_texture_view: &'a wgpu::TextureView,
+}
+
+/// Gather based bokeh blur driven by the scene depth buffer
+pub struct DepthOfFieldEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    settings: DofSettings,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group: wgpu::BindGroup,
+    output_texture: wgpu::Texture,
+    pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl DepthOfFieldEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        params: DepthOfFieldParams,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let DepthOfFieldParams {
+            camera_buffer,
+            width,
+            height,
+            format,
+            scene_texture_view,
+            depth_texture_view,
+        } = params;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Of Field Shader"),
+            source: wgpu::ShaderSource::Wgsl(storage_format_source(
+                include_str!("dof.wgsl"),
+                format,
+            )),
+        });
+
+        let settings = DofSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth Of Field Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Group 0: settings and camera uniforms
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let settings_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Depth Of Field Settings Bind Group Layout"),
+                entries: &[uniform_entry(0), uniform_entry(1)],
+            });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Depth Of Field Settings Bind Group"),
+        });
+
+        // Group 1: scene color, scene depth and the output
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Depth Of Field Texture Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_bind_group_layout, &texture_bind_group_layout],
+            &shader,
+            "dof_main",
+            "Depth Of Field Pipeline",
+            pipeline_cache,
+        );
+
+        let output_texture = create_output_texture(&device, width, height, format);
+        let texture_bind_group = create_texture_bind_group(
+            &device,
+            &texture_bind_group_layout,
+            scene_texture_view,
+            depth_texture_view,
+            &output_texture,
+        );
+
+        Self {
+            device,
+            queue,
+            settings,
+            settings_buffer,
+            settings_bind_group,
+            texture_bind_group_layout,
+            texture_bind_group,
+            output_texture,
+            pipeline,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        scene_texture_view: &wgpu::TextureView,
+        depth_texture_view: &wgpu::TextureView,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.output_texture = create_output_texture(&self.device, width, height, self.format);
+        self.texture_bind_group = create_texture_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            scene_texture_view,
+            depth_texture_view,
+            &self.output_texture,
+        );
+    }
+
+    pub fn update_uniform(&mut self, settings: DofSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Blurs `scene_texture` in place, skipped entirely while the aperture is closed
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, scene_texture: &wgpu::Texture) {
+        if self.settings.aperture <= 0.0 {
+            return;
+        }
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Depth Of Field Compute Pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            cpass.set_bind_group(1, &self.texture_bind_group, &[]);
+            cpass.dispatch_workgroups(
+                self.width.div_ceil(DOF_WORKGROUP_SIZE),
+                self.height.div_ceil(DOF_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            scene_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for DepthOfFieldEffect {
+    /// Reads and writes the scene, declared as its single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].texture);
+    }
+}
+
+fn create_output_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Of Field Output Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scene_texture_view: &wgpu::TextureView,
+    depth_texture_view: &wgpu::TextureView,
+    output_texture: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(scene_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(depth_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            },
+        ],
+        label: Some("Depth Of Field Texture Bind Group"),
+    })
+}
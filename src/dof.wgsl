@@ -0,0 +1,80 @@
+struct DofSettings {
+    focal_distance: f32,
+    aperture: f32,
+    focal_range: f32,
+    _padding: f32,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+@group(0) @binding(0) var<uniform> settings: DofSettings;
+@group(0) @binding(1) var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0) var scene: texture_2d<f32>;
+@group(1) @binding(1) var depth_texture: texture_depth_2d;
+@group(1) @binding(2) var output: texture_storage_2d<rgba32float, write>;
+
+// Largest blur radius in pixels
+const MAX_COC: f32 = 16.0;
+const SAMPLES: u32 = 48u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+// Distance from the camera, the inverse view projection carries the near and far planes
+fn linear_depth(pixel: vec2<i32>, dims: vec2<u32>) -> f32 {
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let uv = (vec2f(pixel) + 0.5) / vec2f(dims);
+    // Depth was written remapped from OpenGL's -1..1 range
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth * 2.0 - 1.0, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    return distance(world.xyz / world.w, camera.camera_position);
+}
+
+// Circle of confusion radius in pixels
+fn coc_radius(depth: f32) -> f32 {
+    let defocus = max(abs(depth - settings.focal_distance) - settings.focal_range, 0.0);
+    return clamp(settings.aperture * defocus / max(depth, 1e-3), 0.0, 1.0) * MAX_COC;
+}
+
+@compute @workgroup_size(8, 8) // DOF_WORKGROUP_SIZE in dof.rs
+fn dof_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let center_depth = linear_depth(pixel, dims);
+    let center_coc = coc_radius(center_depth);
+    let center = textureLoad(scene, pixel, 0);
+
+    if (center_coc < 0.5) {
+        textureStore(output, pixel, center);
+        return;
+    }
+
+    // Gather along a golden angle spiral covering the circle of confusion
+    var color = center.rgb;
+    var weight_sum = 1.0;
+    for (var i = 1u; i < SAMPLES; i = i + 1u) {
+        let r = sqrt(f32(i) / f32(SAMPLES)) * center_coc;
+        let theta = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2f(cos(theta), sin(theta)) * r;
+        let coord = clamp(pixel + vec2<i32>(round(offset)), vec2<i32>(0), vec2<i32>(dims) - 1);
+
+        let sample_depth = linear_depth(coord, dims);
+        // Background samples only count if their own blur reaches this pixel,
+        // which keeps sharp foreground edges from bleeding into the bokeh
+        let sample_coc = coc_radius(sample_depth);
+        let w = select(1.0, clamp(sample_coc - r + 1.0, 0.0, 1.0), sample_depth > center_depth);
+
+        color += textureLoad(scene, coord, 0).rgb * w;
+        weight_sum += w;
+    }
+
+    textureStore(output, pixel, vec4f(color / weight_sum, center.a));
+}
@@ -0,0 +1,63 @@
+/// Scales the render resolution to keep the frame time within budget. Grows slowly
+/// and shrinks fast, so a costly view drops resolution before it drops frames
+#[derive(Debug)]
+pub struct DynamicResolutionScaler {
+    pub target_frame_ms: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    pub current_scale: f32, // Of the render targets relative to the surface
+    pub enabled: bool,
+}
+
+impl Default for DynamicResolutionScaler {
+    fn default() -> Self {
+        Self {
+            // Frame times include waiting on vsync and the frame limiter, so the budget
+            // sits a bit above a 60 Hz refresh and only missed refreshes count
+            target_frame_ms: 20.0,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            current_scale: 1.0,
+            enabled: false,
+        }
+    }
+}
+
+impl DynamicResolutionScaler {
+    /// Steps the scale for the last frame time and returns it, holds `max_scale` while
+    /// disabled. A frame time of 0 means nothing was measured yet
+    pub fn update(&mut self, frame_ms: f32) -> f32 {
+        if !self.enabled {
+            self.current_scale = self.max_scale;
+        } else if frame_ms > self.target_frame_ms {
+            self.current_scale -= 0.05;
+        } else if frame_ms > 0.0 {
+            self.current_scale += 0.01;
+        }
+        self.current_scale = self
+            .current_scale
+            .clamp(self.min_scale, self.max_scale.max(self.min_scale));
+        self.current_scale
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DynamicResolutionScaler;
+
+    #[test]
+    pub fn test_scale_stays_within_bounds() {
+        let mut scaler = DynamicResolutionScaler {
+            enabled: true,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            scaler.update(40.0);
+        }
+        assert_eq!(scaler.current_scale, scaler.min_scale);
+        for _ in 0..100 {
+            scaler.update(5.0);
+        }
+        assert_eq!(scaler.current_scale, scaler.max_scale);
+    }
+}
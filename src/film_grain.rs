@@ -0,0 +1,178 @@
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::borrow::Cow;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FilmGrainSettings {
+    pub strength: f32, // 0 disables the effect
+    pub frame: u32,    // Seeds the noise, advanced every drawn frame
+    _padding: [u32; 2],
+}
+
+impl Default for FilmGrainSettings {
+    fn default() -> Self {
+        Self {
+            strength: 0.0,
+            frame: 0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Animated per pixel luminance noise over the final image. The swapchain can't be
+/// bound as a storage texture, so the grain is a fullscreen pass blended onto it
+pub struct FilmGrainEffect {
+    queue: Arc<wgpu::Queue>,
+    settings: FilmGrainSettings,
+    settings_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl FilmGrainEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        surface_format: wgpu::TextureFormat,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Film Grain Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("film_grain.wgsl"))),
+        });
+
+        let settings = FilmGrainSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Film Grain Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Film Grain Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            }],
+            label: Some("Film Grain Bind Group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Film Grain Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Film Grain Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                // result = grain * dst + dst * grain, brightening or darkening around 0.5
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Dst,
+                            dst_factor: wgpu::BlendFactor::Src,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            queue,
+            settings,
+            settings_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn settings(&self) -> FilmGrainSettings {
+        self.settings
+    }
+
+    pub fn update_uniform(&mut self, settings: FilmGrainSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Reseeds the noise, call once per drawn frame
+    pub fn next_frame(&mut self) {
+        let mut settings = self.settings;
+        settings.frame = settings.frame.wrapping_add(1);
+        self.update_uniform(settings);
+    }
+
+    /// Blends the grain over `target_view`, skipped entirely while the strength is zero
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, target_view: &wgpu::TextureView) {
+        if self.settings.strength <= 0.0 {
+            return;
+        }
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Film Grain Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl RenderNode for FilmGrainEffect {
+    /// Blends over the single output, the final image
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].view);
+    }
+}
@@ -0,0 +1,36 @@
+struct FilmGrainSettings {
+    strength: f32,
+    frame: u32,
+    _padding: vec2<u32>,
+};
+
+@group(0) @binding(0) var<uniform> settings: FilmGrainSettings;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let positions = array<vec2<f32>, 4>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, 1.0)
+    );
+    return vec4<f32>(positions[vertex_index], 0.0, 1.0);
+}
+
+// PCG hash, decorrelates neighbouring pixels and frames
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<u32>(frag_coord.xy);
+    let seed = hash(pixel.x + hash(pixel.y + hash(settings.frame)));
+    let noise = f32(seed) / 4294967295.0 * 2.0 - 1.0;
+
+    // Blended as src * dst * 2, so 0.5 leaves the image unchanged
+    let grain = 0.5 + 0.5 * noise * settings.strength;
+    return vec4<f32>(vec3<f32>(grain), 1.0);
+}
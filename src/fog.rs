@@ -0,0 +1,364 @@
+use crate::bloom::{create_compute_pipeline, storage_format_source};
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Must match `@workgroup_size` of the entry points in fog.wgsl
+const FOG_WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogSettings {
+    pub color: [f32; 4],
+    pub density: f32, // 0 disables the effect
+    pub anisotropy: f32,
+    pub start_distance: f32,
+    _padding: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            color: [0.8, 0.85, 0.9, 1.0],
+            density: 0.0,
+            anisotropy: 0.5,
+            start_distance: 2.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Inputs of `VolumetricFogEffect::new`, the shared uniforms, the density noise and the
+/// targets the fog is ray marched against
+pub struct FogParams<'a> {
+    pub camera_buffer: &'a wgpu::Buffer,
+    pub voxel_settings_buffer: &'a wgpu::Buffer,
+    pub time_buffer: &'a wgpu::Buffer,
+    pub noise_view: wgpu::TextureView,
+    pub noise_sampler: Arc<wgpu::Sampler>,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub scene_texture_view: &'a wgpu::TextureView,
+    pub depth_texture_view: &'a wgpu::TextureView,
+}
+
+/// Ray marched fog lit by the voxel sun, using the 3D terrain noise as density
+pub struct VolumetricFogEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    settings: FogSettings,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    noise_view: wgpu::TextureView,
+    noise_sampler: Arc<wgpu::Sampler>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group: wgpu::BindGroup,
+    output_texture: wgpu::Texture,
+    pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl VolumetricFogEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        params: FogParams,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let FogParams {
+            camera_buffer,
+            voxel_settings_buffer,
+            time_buffer,
+            noise_view,
+            noise_sampler,
+            width,
+            height,
+            format,
+            scene_texture_view,
+            depth_texture_view,
+        } = params;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Volumetric Fog Shader"),
+            source: wgpu::ShaderSource::Wgsl(storage_format_source(
+                include_str!("fog.wgsl"),
+                format,
+            )),
+        });
+
+        let settings = FogSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Volumetric Fog Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Group 0: fog settings, camera, voxel settings and time uniforms
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let settings_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Volumetric Fog Settings Bind Group Layout"),
+                entries: &[
+                    uniform_entry(0),
+                    uniform_entry(1),
+                    uniform_entry(2),
+                    uniform_entry(3),
+                ],
+            });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: voxel_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: time_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Volumetric Fog Settings Bind Group"),
+        });
+
+        // Group 1: scene color, scene depth, density noise and the output
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Volumetric Fog Texture Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_bind_group_layout, &texture_bind_group_layout],
+            &shader,
+            "fog_main",
+            "Volumetric Fog Pipeline",
+            pipeline_cache,
+        );
+
+        let output_texture = create_output_texture(&device, width, height, format);
+        let texture_bind_group = create_texture_bind_group(
+            &device,
+            &texture_bind_group_layout,
+            scene_texture_view,
+            depth_texture_view,
+            &noise_view,
+            &noise_sampler,
+            &output_texture,
+        );
+
+        Self {
+            device,
+            queue,
+            settings,
+            settings_buffer,
+            settings_bind_group,
+            noise_view,
+            noise_sampler,
+            texture_bind_group_layout,
+            texture_bind_group,
+            output_texture,
+            pipeline,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        scene_texture_view: &wgpu::TextureView,
+        depth_texture_view: &wgpu::TextureView,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.output_texture = create_output_texture(&self.device, width, height, self.format);
+        self.texture_bind_group = create_texture_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            scene_texture_view,
+            depth_texture_view,
+            &self.noise_view,
+            &self.noise_sampler,
+            &self.output_texture,
+        );
+    }
+
+    pub fn update_uniform(&mut self, settings: FogSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Fogs `scene_texture` in place, skipped entirely while the density is zero
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, scene_texture: &wgpu::Texture) {
+        if self.settings.density <= 0.0 {
+            return;
+        }
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Volumetric Fog Compute Pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            cpass.set_bind_group(1, &self.texture_bind_group, &[]);
+            cpass.dispatch_workgroups(
+                self.width.div_ceil(FOG_WORKGROUP_SIZE),
+                self.height.div_ceil(FOG_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            scene_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for VolumetricFogEffect {
+    /// Reads and writes the scene, declared as its single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].texture);
+    }
+}
+
+fn create_output_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Volumetric Fog Output Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scene_texture_view: &wgpu::TextureView,
+    depth_texture_view: &wgpu::TextureView,
+    noise_view: &wgpu::TextureView,
+    noise_sampler: &wgpu::Sampler,
+    output_texture: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(scene_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(depth_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(noise_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(noise_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            },
+        ],
+        label: Some("Volumetric Fog Texture Bind Group"),
+    })
+}
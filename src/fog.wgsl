@@ -0,0 +1,129 @@
+struct FogSettings {
+    color: vec4f,
+    density: f32,
+    anisotropy: f32,
+    start_distance: f32,
+    _padding: f32,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+// Leading fields of the voxel settings uniform, only the lighting is read here
+struct VoxelSettings {
+    max: f32,
+    r_inner: f32,
+    r: f32,
+    max_height: f32,
+    max_water_height: f32,
+    water_height: f32,
+    tunnel_radius: f32,
+    surface_factor: f32,
+    camera_speed: f32,
+    camera_time_offset: f32,
+    voxel_level: i32,
+    voxel_size: f32,
+    steps: i32,
+    max_dist: f32,
+    min_dist: f32,
+    eps: f32,
+
+    light_color: vec4f,
+    light_direction: vec4f,
+
+    show_normals: i32,
+    show_steps: i32,
+    visualize_distance_field: i32,
+    show_shadows: i32,
+
+    ambient_color: vec4f,
+    ambient_strength: f32,
+};
+
+struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    frame: u32,
+    _padding: u32,
+};
+
+@group(0) @binding(0) var<uniform> settings: FogSettings;
+@group(0) @binding(1) var<uniform> camera: CameraUniform;
+@group(0) @binding(2) var<uniform> voxel_settings: VoxelSettings;
+@group(0) @binding(3) var<uniform> time: TimeUniform;
+
+@group(1) @binding(0) var scene: texture_2d<f32>;
+@group(1) @binding(1) var depth_texture: texture_depth_2d;
+@group(1) @binding(2) var noise_texture: texture_3d<f32>;
+@group(1) @binding(3) var noise_sampler: sampler;
+@group(1) @binding(4) var output: texture_storage_2d<rgba32float, write>;
+
+const STEPS: u32 = 32u;
+// Rays that hit nothing are marched this far
+const MAX_DISTANCE: f32 = 100.0;
+const NOISE_SCALE: f32 = 0.02;
+const WIND: vec3f = vec3f(0.3, 0.0, 0.1);
+const PI: f32 = 3.141592;
+
+fn world_position(pixel: vec2<i32>, dims: vec2<u32>, depth: f32) -> vec3f {
+    let uv = (vec2f(pixel) + 0.5) / vec2f(dims);
+    // Depth was written remapped from OpenGL's -1..1 range
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth * 2.0 - 1.0, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+// Henyey-Greenstein, positive anisotropy scatters toward the light
+fn phase(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+fn fog_density(p: vec3f) -> f32 {
+    let q = (p + WIND * time.elapsed) * NOISE_SCALE;
+    let noise = textureSampleLevel(noise_texture, noise_sampler, q, 0.0).r;
+    return settings.density * noise;
+}
+
+@compute @workgroup_size(8, 8) // FOG_WORKGROUP_SIZE in fog.rs
+fn fog_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let center = textureLoad(scene, pixel, 0);
+
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let hit = world_position(pixel, dims, depth);
+    let to_hit = hit - camera.camera_position;
+    let ray_dir = normalize(to_hit);
+    let end = select(length(to_hit), MAX_DISTANCE, depth >= 1.0);
+    let start = min(settings.start_distance, end);
+
+    let light_dir = normalize(voxel_settings.light_direction.xyz);
+    let light = voxel_settings.light_color.rgb * voxel_settings.light_color.w
+        * phase(dot(ray_dir, light_dir), settings.anisotropy);
+    let ambient = voxel_settings.ambient_color.rgb * voxel_settings.ambient_strength;
+    let scatter_color = settings.color.rgb * (light + ambient);
+
+    // March from the fog start to the surface, accumulating in-scattered light
+    let step_size = (end - start) / f32(STEPS);
+    var transmittance = 1.0;
+    var in_scattered = vec3f(0.0);
+    for (var i = 0u; i < STEPS; i = i + 1u) {
+        let t = start + (f32(i) + 0.5) * step_size;
+        let sigma = fog_density(camera.camera_position + ray_dir * t);
+        let step_transmittance = exp(-sigma * step_size);
+        // Energy conserving integration of the scattering over the step
+        in_scattered += transmittance * scatter_color * (1.0 - step_transmittance);
+        transmittance *= step_transmittance;
+    }
+
+    textureStore(output, pixel, vec4f(center.rgb * transmittance + in_scattered, center.a));
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+// Staging space for one frame of small uploads
+const FRAME_ALLOCATOR_SIZE: wgpu::BufferAddress = 1 << 20;
+
+struct PendingCopy<T> {
+    target: T,
+    target_offset: wgpu::BufferAddress,
+    source_offset: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+}
+
+/// CPU side of `FrameAllocator`: the packed staging bytes and the copies out of them.
+/// Generic over the copy target so the offset bookkeeping works without a device
+struct StagingData<T> {
+    capacity: wgpu::BufferAddress,
+    data: Vec<u8>, // Bump allocated contents of the staging buffer
+    copies: Vec<PendingCopy<T>>,
+    // Index into `copies` of the write to each (target, offset, size)
+    copy_indices: HashMap<(T, wgpu::BufferAddress, wgpu::BufferAddress), usize>,
+}
+
+impl<T: Clone + Eq + Hash> StagingData<T> {
+    fn new(capacity: wgpu::BufferAddress) -> Self {
+        Self {
+            capacity,
+            data: Vec::new(),
+            copies: Vec::new(),
+            copy_indices: HashMap::new(),
+        }
+    }
+
+    /// Stages `data` for `target` at `offset`, false if it doesn't fit or isn't 4 byte
+    /// aligned and has to be written directly instead
+    fn stage(&mut self, target: &T, offset: wgpu::BufferAddress, data: &[u8]) -> bool {
+        let size = data.len() as wgpu::BufferAddress;
+        // Rewriting the same range replaces the earlier write, so a uniform updated every
+        // frame takes the same space no matter how many frames pass between flushes
+        if let Some(&index) = self.copy_indices.get(&(target.clone(), offset, size)) {
+            let start = self.copies[index].source_offset as usize;
+            self.data[start..start + data.len()].copy_from_slice(data);
+            return true;
+        }
+        let source_offset = self.data.len() as wgpu::BufferAddress;
+        let aligned = size.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)
+            && offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        if !aligned || source_offset + size > self.capacity {
+            return false;
+        }
+        self.data.extend_from_slice(data);
+        self.copy_indices
+            .insert((target.clone(), offset, size), self.copies.len());
+        self.copies.push(PendingCopy {
+            target: target.clone(),
+            target_offset: offset,
+            source_offset,
+            size,
+        });
+        true
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.copies.clear();
+        self.copy_indices.clear();
+    }
+}
+
+/// Packs the small uniform writes of a frame into one staging buffer, uploaded with a
+/// single `write_buffer` and copied to their targets by `flush` at the start of `draw`.
+/// Writes that don't fit or aren't 4 byte aligned go straight to the queue instead
+pub struct FrameAllocator {
+    queue: Arc<wgpu::Queue>,
+    buffer: wgpu::Buffer,
+    staging: StagingData<wgpu::Buffer>,
+}
+
+impl FrameAllocator {
+    pub fn new(device: &wgpu::Device, queue: Arc<wgpu::Queue>) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Allocator Buffer"),
+            size: FRAME_ALLOCATOR_SIZE,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            queue,
+            buffer,
+            staging: StagingData::new(FRAME_ALLOCATOR_SIZE),
+        }
+    }
+
+    /// Like `Queue::write_buffer`, but lands when the next `flush` is recorded
+    pub fn write(&mut self, target: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        if !self.staging.stage(target, offset, data) {
+            self.queue.write_buffer(target, offset, data);
+        }
+    }
+
+    /// Uploads everything written since the last flush and records the copies into
+    /// `encoder`, ahead of any pass recorded after this.
+    ///
+    /// Call it at most once per queue submit. The upload to the staging buffer lands
+    /// when the next submit starts, so a second flush before it would overwrite the
+    /// bytes the first flush's copies read
+    pub fn flush(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.staging.copies.is_empty() {
+            return;
+        }
+        self.queue.write_buffer(&self.buffer, 0, &self.staging.data);
+        for copy in &self.staging.copies {
+            encoder.copy_buffer_to_buffer(
+                &self.buffer,
+                copy.source_offset,
+                &copy.target,
+                copy.target_offset,
+                copy.size,
+            );
+        }
+        self.staging.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StagingData;
+
+    #[test]
+    pub fn test_writes_pack_back_to_back() {
+        let mut staging = StagingData::new(64);
+        assert!(staging.stage(&0, 0, &[1; 16]));
+        assert!(staging.stage(&1, 32, &[2; 8]));
+        assert_eq!(staging.data.len(), 24);
+        assert_eq!(staging.copies[1].source_offset, 16);
+        assert_eq!(staging.copies[1].target_offset, 32);
+        assert_eq!(staging.copies[1].size, 8);
+    }
+
+    #[test]
+    pub fn test_rewrite_replaces_earlier_write() {
+        let mut staging = StagingData::new(64);
+        assert!(staging.stage(&0, 0, &[1; 8]));
+        assert!(staging.stage(&0, 8, &[2; 8]));
+        assert!(staging.stage(&0, 0, &[3; 8]));
+        assert_eq!(staging.copies.len(), 2);
+        assert_eq!(&staging.data[..8], &[3; 8]);
+        assert_eq!(&staging.data[8..], &[2; 8]);
+
+        // A different size at the same offset is a separate write
+        assert!(staging.stage(&0, 0, &[4; 4]));
+        assert_eq!(staging.copies.len(), 3);
+    }
+
+    #[test]
+    pub fn test_unaligned_and_overflowing_writes_fall_back() {
+        let mut staging = StagingData::new(16);
+        assert!(!staging.stage(&0, 0, &[1; 6]));
+        assert!(!staging.stage(&0, 2, &[1; 8]));
+        assert!(staging.stage(&0, 0, &[1; 12]));
+        assert!(!staging.stage(&1, 0, &[1; 8]));
+        assert_eq!(staging.data.len(), 12);
+        assert_eq!(staging.copies.len(), 1);
+
+        staging.clear();
+        assert!(staging.stage(&1, 0, &[1; 16]));
+        assert_eq!(staging.copies[0].source_offset, 0);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::BoundingBox;
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// Plane `normal . p + distance = 0`, with the normal pointing into the frustum
+#[derive(Debug, Copy, Clone)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_vector(v: Vector4<f32>) -> Self {
+        let normal = v.truncate();
+        let length = normal.magnitude();
+        Self {
+            normal: normal / length,
+            distance: v.w / length,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the six clip planes (Gribb/Hartmann). Expects the OpenGL style
+    /// -1..1 depth range produced by `calculate_view_projection`.
+    pub fn from_view_projection(vp: Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (vp.row(0), vp.row(1), vp.row(2), vp.row(3));
+        Self {
+            planes: [
+                Plane::from_vector(r3 + r0), // Left
+                Plane::from_vector(r3 - r0), // Right
+                Plane::from_vector(r3 + r1), // Bottom
+                Plane::from_vector(r3 - r1), // Top
+                Plane::from_vector(r3 + r2), // Near
+                Plane::from_vector(r3 - r2), // Far
+            ],
+        }
+    }
+
+    /// False only if the box is completely outside one of the planes
+    pub fn contains_aabb(&self, aabb: &BoundingBox) -> bool {
+        self.planes.iter().all(|plane| {
+            // Corner furthest along the plane normal
+            let p = Vector3::new(
+                if plane.normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.normal.dot(p) + plane.distance >= 0.0
+        })
+    }
+}
@@ -0,0 +1,137 @@
+// FXAA 3.11 by Timothy Lottes, quality preset 12
+const EDGE_THRESHOLD: f32 = 0.166;     // Local contrast needed to be treated as an edge
+const EDGE_THRESHOLD_MIN: f32 = 0.0833; // Darker areas than this are left alone
+const SUBPIX: f32 = 0.75;              // Amount of sub-pixel aliasing removed
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var input_sampler: sampler;
+
+// Fullscreen triangle, the corners past the screen are clipped
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let positions = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(positions[vertex_index], 0.0, 1.0);
+}
+
+fn sample_color(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0);
+}
+
+// The input is still HDR, so the luma is tone mapped and roughly gamma encoded
+// before the thresholds compare it
+fn luma(color: vec3<f32>) -> f32 {
+    let l = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    return sqrt(l / (1.0 + l));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(sample_color(uv).rgb);
+}
+
+@fragment
+fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input_texture));
+    let uv = frag_coord.xy * texel;
+
+    let color_m = sample_color(uv);
+    let luma_m = luma(color_m.rgb);
+    var luma_n = luma_at(uv + vec2<f32>(0.0, -texel.y));
+    var luma_s = luma_at(uv + vec2<f32>(0.0, texel.y));
+    let luma_e = luma_at(uv + vec2<f32>(texel.x, 0.0));
+    let luma_w = luma_at(uv + vec2<f32>(-texel.x, 0.0));
+
+    let range_max = max(max(luma_m, max(luma_n, luma_s)), max(luma_e, luma_w));
+    let range_min = min(min(luma_m, min(luma_n, luma_s)), min(luma_e, luma_w));
+    let range = range_max - range_min;
+    if range < max(EDGE_THRESHOLD_MIN, range_max * EDGE_THRESHOLD) {
+        return color_m;
+    }
+
+    let luma_nw = luma_at(uv - texel);
+    let luma_se = luma_at(uv + texel);
+    let luma_ne = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_sw = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+
+    // Sub-pixel blend from the contrast of the 3x3 neighbourhood
+    let luma_ns = luma_n + luma_s;
+    let luma_we = luma_w + luma_e;
+    let subpix_a = (luma_ns + luma_we) * 2.0 + luma_nw + luma_sw + luma_ne + luma_se;
+    let subpix_c = saturate(abs(subpix_a / 12.0 - luma_m) / range);
+    let subpix_f = (-2.0 * subpix_c + 3.0) * subpix_c * subpix_c;
+    let subpix_h = subpix_f * subpix_f * SUBPIX;
+
+    // Whether the edge runs horizontally or vertically
+    let edge_horz = abs(-2.0 * luma_m + luma_ns) * 2.0
+        + abs(-2.0 * luma_w + luma_nw + luma_sw) * 2.0
+        + abs(-2.0 * luma_e + luma_ne + luma_se);
+    let edge_vert = abs(-2.0 * luma_m + luma_we) * 2.0
+        + abs(-2.0 * luma_s + luma_sw + luma_se) * 2.0
+        + abs(-2.0 * luma_n + luma_nw + luma_ne);
+    let horz_span = edge_horz >= edge_vert;
+
+    var length_sign = select(texel.x, texel.y, horz_span);
+    if !horz_span {
+        luma_n = luma_w;
+        luma_s = luma_e;
+    }
+    let gradient_n = luma_n - luma_m;
+    let gradient_s = luma_s - luma_m;
+    let pair_n = abs(gradient_n) >= abs(gradient_s);
+    let gradient = max(abs(gradient_n), abs(gradient_s));
+    if pair_n {
+        length_sign = -length_sign;
+    }
+    let luma_pair = select(luma_s + luma_m, luma_n + luma_m, pair_n) * 0.5;
+
+    // Walk along both directions of the edge until its end is found
+    var pos_b = uv;
+    if horz_span {
+        pos_b.y += length_sign * 0.5;
+    } else {
+        pos_b.x += length_sign * 0.5;
+    }
+    let step = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), horz_span);
+    var offsets = array<f32, 5>(1.0, 1.5, 2.0, 4.0, 12.0);
+    var pos_n = pos_b - step * offsets[0];
+    var pos_p = pos_b + step * offsets[0];
+    var luma_end_n = luma_at(pos_n) - luma_pair;
+    var luma_end_p = luma_at(pos_p) - luma_pair;
+    let gradient_scaled = gradient * 0.25;
+    var done_n = abs(luma_end_n) >= gradient_scaled;
+    var done_p = abs(luma_end_p) >= gradient_scaled;
+    for (var i = 1; i < 5 && !(done_n && done_p); i++) {
+        if !done_n {
+            pos_n -= step * offsets[i];
+            luma_end_n = luma_at(pos_n) - luma_pair;
+            done_n = abs(luma_end_n) >= gradient_scaled;
+        }
+        if !done_p {
+            pos_p += step * offsets[i];
+            luma_end_p = luma_at(pos_p) - luma_pair;
+            done_p = abs(luma_end_p) >= gradient_scaled;
+        }
+    }
+
+    // Blend toward the nearer end, only where the edge changes sign the right way
+    let dst_n = select(uv.x - pos_n.x, uv.y - pos_n.y, !horz_span);
+    let dst_p = select(pos_p.x - uv.x, pos_p.y - uv.y, !horz_span);
+    let luma_m_below = luma_m - luma_pair < 0.0;
+    let good_span_n = (luma_end_n < 0.0) != luma_m_below;
+    let good_span_p = (luma_end_p < 0.0) != luma_m_below;
+    let direction_n = dst_n < dst_p;
+    let good_span = select(good_span_p, good_span_n, direction_n);
+    let pixel_offset = 0.5 - min(dst_n, dst_p) / (dst_n + dst_p);
+    let offset = max(select(0.0, pixel_offset, good_span), subpix_h);
+
+    var final_uv = uv;
+    if horz_span {
+        final_uv.y += offset * length_sign;
+    } else {
+        final_uv.x += offset * length_sign;
+    }
+    return sample_color(final_uv);
+}
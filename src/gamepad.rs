@@ -0,0 +1,85 @@
+use crate::input::{GamepadAxis, GamepadButton, Input};
+use gilrs::{Axis, Button, EventType, Gilrs};
+use winit::event::ElementState;
+
+/// Feeds gamepad events from gilrs into `Input`, all connected gamepads share one state
+pub struct GilrsBackend {
+    gilrs: Gilrs,
+}
+
+impl GilrsBackend {
+    /// None if the platform has no gamepad support
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Self { gilrs }),
+            Err(err) => {
+                tracing::warn!(%err, "Gamepad input unavailable");
+                None
+            }
+        }
+    }
+
+    /// Drains the pending events, call once per frame before the systems run
+    pub fn poll(&mut self, input: &mut Input) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        input.handle_gamepad_button(button, ElementState::Pressed);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = map_button(button) {
+                        input.handle_gamepad_button(button, ElementState::Released);
+                    }
+                }
+                // Analog triggers report as buttons with a value
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    input.handle_gamepad_axis(GamepadAxis::LeftTrigger, value);
+                }
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                    input.handle_gamepad_axis(GamepadAxis::RightTrigger, value);
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    if let Some(axis) = map_axis(axis) {
+                        input.handle_gamepad_axis(axis, value);
+                    }
+                }
+                EventType::Disconnected => input.clear_gamepad(),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn map_axis(axis: Axis) -> Option<GamepadAxis> {
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        Axis::LeftZ => GamepadAxis::LeftTrigger,
+        Axis::RightZ => GamepadAxis::RightTrigger,
+        _ => return None,
+    })
+}
+
+fn map_button(button: Button) -> Option<GamepadButton> {
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftThumb,
+        Button::RightThumb => GamepadButton::RightThumb,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
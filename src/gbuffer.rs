@@ -0,0 +1,233 @@
+use crate::texture_bytes;
+use std::sync::Arc;
+
+pub const GBUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// G-buffer channel shown instead of the lit scene, stored as an i32 in
+/// `VoxelSettings::gbuffer_debug`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GBufferDebug {
+    Off,
+    Albedo,
+    Normal,
+    Position,
+}
+
+pub const GBUFFER_DEBUG_MODES: [GBufferDebug; 4] = [
+    GBufferDebug::Off,
+    GBufferDebug::Albedo,
+    GBufferDebug::Normal,
+    GBufferDebug::Position,
+];
+
+impl GBufferDebug {
+    pub fn from_index(index: i32) -> Self {
+        GBUFFER_DEBUG_MODES
+            .get(index as usize)
+            .copied()
+            .unwrap_or(Self::Off)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Albedo => "Albedo",
+            Self::Normal => "Normal",
+            Self::Position => "Position",
+        }
+    }
+}
+
+/// Traces the terrain once more into albedo, world normal and world position targets,
+/// w is 1 where terrain was hit and 0 for the sky. Models are only drawn forward. Nothing
+/// reads the G-buffer yet but the debug view, so the caller only renders it for that
+pub struct GBufferPass {
+    device: Arc<wgpu::Device>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    textures: [wgpu::Texture; 3], // Albedo, normal, position
+    views: [wgpu::TextureView; 3],
+    pub deferred_bind_group_layout: wgpu::BindGroupLayout,
+    pub deferred_bind_group: wgpu::BindGroup, // The three targets at bindings 0..=2
+}
+
+impl GBufferPass {
+    /// `pipeline_layout` is the voxel render pipeline's, the pass shares its bind groups
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        pipeline_layout: &wgpu::PipelineLayout,
+        voxel_shader: &wgpu::ShaderModule,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let deferred_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Deferred Bind Group Layout"),
+                entries: &[0, 1, 2].map(|binding| wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                }),
+            });
+        let (textures, views) = create_targets(&device, width, height);
+        let deferred_bind_group =
+            create_deferred_bind_group(&device, &deferred_bind_group_layout, &views);
+        let pipeline = create_gbuffer_pipeline(&device, pipeline_layout, voxel_shader);
+
+        Self {
+            device,
+            pipeline_layout: pipeline_layout.clone(),
+            pipeline,
+            textures,
+            views,
+            deferred_bind_group_layout,
+            deferred_bind_group,
+        }
+    }
+
+    pub fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        voxel_shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        create_gbuffer_pipeline(device, &self.pipeline_layout, voxel_shader)
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: wgpu::RenderPipeline) {
+        self.pipeline = pipeline;
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        (self.textures, self.views) = create_targets(&self.device, width, height);
+        self.deferred_bind_group =
+            create_deferred_bind_group(&self.device, &self.deferred_bind_group_layout, &self.views);
+    }
+
+    pub fn texture_bytes(&self) -> u64 {
+        self.textures.iter().map(texture_bytes).sum()
+    }
+
+    /// View of the channel `debug` shows, None for `GBufferDebug::Off`
+    pub fn debug_view(&self, debug: GBufferDebug) -> Option<&wgpu::TextureView> {
+        match debug {
+            GBufferDebug::Off => None,
+            GBufferDebug::Albedo => Some(&self.views[0]),
+            GBufferDebug::Normal => Some(&self.views[1]),
+            GBufferDebug::Position => Some(&self.views[2]),
+        }
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        time_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        terrain_bind_group: &wgpu::BindGroup,
+        voxel_settings_bind_group: &wgpu::BindGroup,
+    ) {
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        };
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Render Pass"),
+            color_attachments: &[
+                attachment(&self.views[0]),
+                attachment(&self.views[1]),
+                attachment(&self.views[2]),
+            ],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, time_bind_group, &[]);
+        rpass.set_bind_group(1, camera_bind_group, &[]);
+        rpass.set_bind_group(2, terrain_bind_group, &[]);
+        rpass.set_bind_group(3, voxel_settings_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+) -> ([wgpu::Texture; 3], [wgpu::TextureView; 3]) {
+    let textures = ["G-Buffer Albedo", "G-Buffer Normal", "G-Buffer Position"].map(|label| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: GBUFFER_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+    });
+    let views = [0, 1, 2].map(|i| textures[i].create_view(&wgpu::TextureViewDescriptor::default()));
+    (textures, views)
+}
+
+fn create_deferred_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    views: &[wgpu::TextureView; 3],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[0, 1, 2].map(|i| wgpu::BindGroupEntry {
+            binding: i,
+            resource: wgpu::BindingResource::TextureView(&views[i as usize]),
+        }),
+        label: Some("Deferred Bind Group"),
+    })
+}
+
+fn create_gbuffer_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    voxel_shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("G-Buffer Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: voxel_shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: voxel_shader,
+            entry_point: Some("fs_gbuffer"),
+            compilation_options: Default::default(),
+            targets: &[0, 1, 2].map(|_| Some(GBUFFER_FORMAT.into())),
+        }),
+        // The fullscreen quad of vs_main as a strip, no index buffer needed
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -1,7 +1,10 @@
 use image::GenericImageView;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::path::Path;
+use wgpu::util::DeviceExt;
 
 pub struct RgbaImg {
     pub width: u32,
@@ -11,19 +14,287 @@ pub struct RgbaImg {
 
 impl RgbaImg {
     pub fn new(file_path: &str) -> Option<Self> {
-        if let Ok(file_bytes) = read_file_to_memory(file_path) {
-            let dynamic_img = image::load_from_memory(&file_bytes[..]).unwrap();
-            let rgba_img = dynamic_img.to_rgba8();
-            let (width, height) = dynamic_img.dimensions();
-            Some(Self {
-                width,
-                height,
-                bytes: rgba_img.into_raw(),
-            })
-        } else {
-            None
+        let file_bytes = match read_file_to_memory(file_path) {
+            Ok(file_bytes) => file_bytes,
+            Err(err) => {
+                tracing::error!(path = file_path, %err, "Failed to read image");
+                return None;
+            }
+        };
+        let dynamic_img = match image::load_from_memory(&file_bytes[..]) {
+            Ok(dynamic_img) => dynamic_img,
+            Err(err) => {
+                tracing::error!(path = file_path, %err, "Failed to decode image");
+                return None;
+            }
+        };
+        let rgba_img = dynamic_img.to_rgba8();
+        let (width, height) = dynamic_img.dimensions();
+        tracing::info!(path = file_path, width, height, "Loaded image");
+        Some(Self {
+            width,
+            height,
+            bytes: rgba_img.into_raw(),
+        })
+    }
+
+    /// A single pixel image of the given color
+    pub fn from_color(color: [u8; 4]) -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            bytes: color.to_vec(),
         }
     }
+
+    /// Reads a 2D KTX2 texture with its mip chain as stored. Only formats in
+    /// `ktx2_texture_format` without supercompression are supported
+    pub fn from_ktx2(file_path: &str) -> Option<CompressedImg> {
+        let file_bytes = match read_file_to_memory(file_path) {
+            Ok(file_bytes) => file_bytes,
+            Err(err) => {
+                tracing::error!(path = file_path, %err, "Failed to read KTX2 texture");
+                return None;
+            }
+        };
+        let reader = match ktx2::Reader::new(&file_bytes[..]) {
+            Ok(reader) => reader,
+            Err(err) => {
+                tracing::error!(path = file_path, %err, "Failed to parse KTX2 texture");
+                return None;
+            }
+        };
+        let header = reader.header();
+        let Some(format) = header.format.and_then(ktx2_texture_format) else {
+            tracing::error!(path = file_path, format = ?header.format, "Unsupported KTX2 format");
+            return None;
+        };
+        if header.supercompression_scheme.is_some()
+            || header.pixel_depth > 1
+            || header.layer_count > 1
+            || header.face_count != 1
+        {
+            tracing::error!(
+                path = file_path,
+                "Only single layer 2D KTX2 textures without supercompression are supported"
+            );
+            return None;
+        }
+
+        // Level 0 first, the order create_texture_with_data expects for MipMajor
+        let data = reader
+            .levels()
+            .flat_map(|level| level.data)
+            .copied()
+            .collect();
+        let mip_levels = header.level_count.max(1);
+        tracing::info!(
+            path = file_path,
+            width = header.pixel_width,
+            height = header.pixel_height,
+            mip_levels,
+            ?format,
+            "Loaded KTX2 texture"
+        );
+        Some(CompressedImg {
+            data,
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height,
+            mip_levels,
+        })
+    }
+}
+
+/// Texture data ready to upload as is, every mip level in order
+pub struct CompressedImg {
+    pub data: Vec<u8>,
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+}
+
+fn ktx2_texture_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    Some(match format {
+        ktx2::Format::BC7_SRGB_BLOCK => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+        ktx2::Format::BC7_UNORM_BLOCK => wgpu::TextureFormat::Bc7RgbaUnorm,
+        ktx2::Format::BC3_SRGB_BLOCK => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+        ktx2::Format::BC3_UNORM_BLOCK => wgpu::TextureFormat::Bc3RgbaUnorm,
+        ktx2::Format::BC1_RGBA_SRGB_BLOCK => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+        ktx2::Format::BC1_RGBA_UNORM_BLOCK => wgpu::TextureFormat::Bc1RgbaUnorm,
+        ktx2::Format::R8G8B8A8_SRGB => wgpu::TextureFormat::Rgba8UnormSrgb,
+        ktx2::Format::R8G8B8A8_UNORM => wgpu::TextureFormat::Rgba8Unorm,
+        _ => return None,
+    })
+}
+
+/// Uploads the image at `png_path` as an sRGB texture, preferring a `.ktx2` file next to it
+/// whose format the device supports. Compressed files skip decoding and keep their mips
+pub fn load_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    png_path: &str,
+) -> Option<wgpu::Texture> {
+    let ktx2_path = Path::new(png_path).with_extension("ktx2");
+    if let Some(img) = ktx2_path
+        .to_str()
+        .filter(|_| ktx2_path.exists())
+        .and_then(RgbaImg::from_ktx2)
+    {
+        if device.features().contains(img.format.required_features()) {
+            return Some(device.create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: img.width,
+                        height: img.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: img.mip_levels,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: img.format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                },
+                wgpu::util::TextureDataOrder::MipMajor,
+                &img.data,
+            ));
+        }
+        tracing::warn!(
+            path = %ktx2_path.display(),
+            format = ?img.format,
+            "Device can't sample the KTX2 format, falling back to the PNG"
+        );
+    }
+
+    let img = RgbaImg::new(png_path)?;
+    Some(device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: img.width,
+                height: img.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &img.bytes,
+    ))
+}
+
+/// Levels of a full mip chain down to 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Fills mip levels 1.. of `texture` from level 0, each level a linear downsample of
+/// the previous one. The texture needs `RENDER_ATTACHMENT` and `TEXTURE_BINDING` usage
+pub fn generate_mipmaps(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+) {
+    if texture.mip_level_count() <= 1 {
+        return;
+    }
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Mipmap Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("mipmap.wgsl"))),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mipmap Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(format.into())],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Mipmap Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+    let level_view = |level| {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Mipmap Level View"),
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })
+    };
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    for level in 1..texture.mip_level_count() {
+        let source_view = level_view(level - 1);
+        let target_view = level_view(level);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("Mipmap Bind Group"),
+        });
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&pipeline);
+        rpass.set_bind_group(0, &bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+    queue.submit(Some(encoder.finish()));
 }
 
 fn read_file_to_memory(filename: &str) -> io::Result<Vec<u8>> {
@@ -51,4 +322,17 @@ mod test {
     pub fn test_not_exists() {
         assert!(RgbaImg::new("abc/efg/img.png").is_none());
     }
+
+    #[test]
+    pub fn test_ktx2_not_exists() {
+        assert!(RgbaImg::from_ktx2("abc/efg/img.ktx2").is_none());
+    }
+
+    #[test]
+    pub fn test_mip_level_count() {
+        use crate::img_utils::mip_level_count;
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(320, 320), 9);
+        assert_eq!(mip_level_count(256, 64), 9);
+    }
 }
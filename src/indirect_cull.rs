@@ -0,0 +1,192 @@
+use crate::bloom::create_compute_pipeline;
+use crate::{BoundingBox, Frustum};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+const CULL_WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    draw_count: u32,
+    _padding: [u32; 3],
+}
+
+/// World space bounds of one indirect draw, laid out as two vec4s
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullBounds {
+    min: [f32; 4],
+    max: [f32; 4],
+}
+
+impl From<BoundingBox> for CullBounds {
+    fn from(aabb: BoundingBox) -> Self {
+        Self {
+            min: [aabb.min.x, aabb.min.y, aabb.min.z, 0.0],
+            max: [aabb.max.x, aabb.max.y, aabb.max.z, 0.0],
+        }
+    }
+}
+
+/// Tests every indirect draw's bounds against the camera frustum on the GPU and
+/// zeroes the instance count of the culled ones
+pub struct IndirectCullPass {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    bounds_buffer: Option<wgpu::Buffer>, // Grows to the most draws seen so far
+    bind_group: Option<(wgpu::Buffer, wgpu::BindGroup)>, // With the indirect buffer it binds
+}
+
+impl IndirectCullPass {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Indirect Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("indirect_cull.wgsl"))),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Indirect Cull Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+        });
+
+        let pipeline = create_compute_pipeline(
+            &device,
+            &[&bind_group_layout],
+            &shader,
+            "cull_main",
+            "Indirect Cull Pipeline",
+            pipeline_cache,
+        );
+
+        Self {
+            device,
+            queue,
+            uniform_buffer,
+            bind_group_layout,
+            pipeline,
+            bounds_buffer: None,
+            bind_group: None,
+        }
+    }
+
+    /// `bounds[i]` belongs to the i-th `DrawIndexedIndirectArgs` in `indirect_buffer`
+    pub fn cull(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        indirect_buffer: &wgpu::Buffer,
+        bounds: &[CullBounds],
+        frustum: &Frustum,
+    ) {
+        if bounds.is_empty() {
+            return;
+        }
+
+        let uniform = CullUniform {
+            planes: frustum.planes.map(|plane| {
+                [
+                    plane.normal.x,
+                    plane.normal.y,
+                    plane.normal.z,
+                    plane.distance,
+                ]
+            }),
+            draw_count: bounds.len() as u32,
+            _padding: [0; 3],
+        };
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let size = std::mem::size_of_val(bounds) as wgpu::BufferAddress;
+        if self
+            .bounds_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.bounds_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Indirect Cull Bounds Buffer"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.bind_group = None;
+        }
+        let bounds_buffer = self.bounds_buffer.as_ref().unwrap();
+        self.queue
+            .write_buffer(bounds_buffer, 0, bytemuck::cast_slice(bounds));
+
+        // Both buffers only change when they grow, so the bind group is rebuilt then
+        if self
+            .bind_group
+            .as_ref()
+            .is_none_or(|(buffer, _)| buffer != indirect_buffer)
+        {
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: bounds_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: indirect_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("Indirect Cull Bind Group"),
+            });
+            self.bind_group = Some((indirect_buffer.clone(), bind_group));
+        }
+        let (_, bind_group) = self.bind_group.as_ref().unwrap();
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Indirect Cull Compute Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, bind_group, &[]);
+        cpass.dispatch_workgroups((bounds.len() as u32).div_ceil(CULL_WORKGROUP_SIZE), 1, 1);
+    }
+}
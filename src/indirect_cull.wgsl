@@ -0,0 +1,43 @@
+struct CullUniform {
+    // xyz is the inward normal, w the distance, as in frustum.rs
+    planes: array<vec4f, 6>,
+    // Draws this frame, both buffers can be larger
+    draw_count: u32,
+};
+
+// Matches wgpu::util::DrawIndexedIndirectArgs
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+// World space bounds of the instance drawn by the command at the same index
+struct Bounds {
+    min: vec4f,
+    max: vec4f,
+};
+
+@group(0) @binding(0) var<uniform> frustum: CullUniform;
+@group(0) @binding(1) var<storage, read> bounds: array<Bounds>;
+@group(0) @binding(2) var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+@compute @workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= frustum.draw_count) {
+        return;
+    }
+    let aabb = bounds[index];
+    for (var i = 0u; i < 6u; i = i + 1u) {
+        let plane = frustum.planes[i];
+        // Corner furthest along the plane normal
+        let corner = select(aabb.min.xyz, aabb.max.xyz, plane.xyz >= vec3f(0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            draws[index].instance_count = 0u;
+            return;
+        }
+    }
+}
@@ -1,8 +1,63 @@
 // input_system.rs
 use std::collections::HashMap;
 use winit::dpi::PhysicalPosition;
-use winit::event::{ElementState, MouseButton, MouseScrollDelta};
-use winit::keyboard::KeyCode;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, Touch, TouchPhase};
+use winit::keyboard::{KeyCode, ModifiersState};
+
+// Roughly how many pixels a trackpad scrolls for one mouse wheel notch
+const PIXELS_PER_LINE: f64 = 20.0;
+// Stick drift below this is reported as zero
+const GAMEPAD_DEADZONE: f32 = 0.15;
+
+/// Sticks range from -1 to 1 with +Y up, triggers from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// Face buttons are named by position, South is A on Xbox and Cross on PlayStation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Either side of a modifier, see `Input::is_modifier_down`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModifierKey {
+    Shift,
+    Control,
+    Alt,
+    Super,
+}
+
+impl ModifierKey {
+    fn key_codes(self) -> [KeyCode; 2] {
+        match self {
+            ModifierKey::Shift => [KeyCode::ShiftLeft, KeyCode::ShiftRight],
+            ModifierKey::Control => [KeyCode::ControlLeft, KeyCode::ControlRight],
+            ModifierKey::Alt => [KeyCode::AltLeft, KeyCode::AltRight],
+            ModifierKey::Super => [KeyCode::SuperLeft, KeyCode::SuperRight],
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Input {
@@ -13,6 +68,12 @@ pub struct Input {
     mouse_position: (f64, f64),
     mouse_delta: (f64, f64),
     scroll_delta: f64,
+    modifiers: ModifiersState,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    gamepad_buttons: HashMap<GamepadButton, ElementState>,
+    touch_state: HashMap<u64, (f64, f64)>, // Position of every finger down, by touch id
+    touch_pressed: bool,                   // A one finger gesture is holding the left button
+    left_button_down: bool,                // By the mouse, touch_pressed also holds Left
 }
 
 impl Input {
@@ -24,8 +85,29 @@ impl Input {
         self.keys_current.insert(key, state);
     }
 
+    /// The OS's view of the modifiers, which also sees presses made while the
+    /// window was unfocused or the key events went to imgui
+    pub fn handle_modifiers_changed(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
     pub fn handle_mouse_button(&mut self, button: MouseButton, state: ElementState) {
-        self.mouse_buttons_current.insert(button, state);
+        if button == MouseButton::Left {
+            self.left_button_down = state.is_pressed();
+            self.update_left_button();
+        } else {
+            self.mouse_buttons_current.insert(button, state);
+        }
+    }
+
+    /// Left is down while either the mouse or a one finger gesture holds it
+    fn update_left_button(&mut self) {
+        let state = if self.left_button_down || self.touch_pressed {
+            ElementState::Pressed
+        } else {
+            ElementState::Released
+        };
+        self.mouse_buttons_current.insert(MouseButton::Left, state);
     }
 
     pub fn handle_cursor_moved(&mut self, position: &PhysicalPosition<f64>) {
@@ -37,43 +119,155 @@ impl Input {
         self.mouse_delta.1 += delta.1;
     }
 
-    pub fn handle_mouse_scroll(&mut self, delta: f64) {
-        self.scroll_delta += delta;
+    /// Accumulates scroll in wheel notches so line and pixel devices behave the same
+    pub fn handle_mouse_scroll(&mut self, delta: MouseScrollDelta) {
+        self.scroll_delta += match delta {
+            MouseScrollDelta::LineDelta(_, y) => y as f64,
+            MouseScrollDelta::PixelDelta(d) => d.y / PIXELS_PER_LINE,
+        };
+    }
+
+    /// One finger drags like the left mouse button, two fingers pinch to scroll and
+    /// pan the cursor with their midpoint
+    pub fn handle_touch(&mut self, touch: &Touch) {
+        let position = (touch.location.x, touch.location.y);
+        let previous_spread = self.touch_spread();
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touch_state.insert(touch.id, position);
+            }
+            TouchPhase::Moved => {
+                let previous = self.touch_state.insert(touch.id, position);
+                if let (Some(previous), 1) = (previous, self.touch_state.len()) {
+                    self.handle_mouse_motion((position.0 - previous.0, position.1 - previous.1));
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touch_state.remove(&touch.id);
+            }
+        }
+
+        if let (Some(previous_spread), Some(spread)) = (previous_spread, self.touch_spread()) {
+            self.scroll_delta += (spread - previous_spread) / PIXELS_PER_LINE;
+            let mut points = self.touch_state.values();
+            let (a, b) = (points.next().unwrap(), points.next().unwrap());
+            self.mouse_position = ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5);
+        }
+
+        // Only the first finger presses, a second one ends the drag for the rest of the
+        // gesture so lifting it again doesn't click
+        let touch_pressed = match touch.phase {
+            TouchPhase::Started => self.touch_state.len() == 1,
+            TouchPhase::Moved => self.touch_pressed,
+            TouchPhase::Ended | TouchPhase::Cancelled => false,
+        };
+        if touch_pressed != self.touch_pressed {
+            self.touch_pressed = touch_pressed;
+            self.update_left_button();
+        }
+    }
+
+    /// Distance between the fingers while exactly two are down
+    fn touch_spread(&self) -> Option<f64> {
+        if self.touch_state.len() != 2 {
+            return None;
+        }
+        let mut points = self.touch_state.values();
+        let (a, b) = (points.next()?, points.next()?);
+        Some((a.0 - b.0).hypot(a.1 - b.1))
+    }
+
+    pub fn handle_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    pub fn handle_gamepad_button(&mut self, button: GamepadButton, state: ElementState) {
+        self.gamepad_buttons.insert(button, state);
+    }
+
+    /// Releases everything, for when the gamepad disconnects
+    pub fn clear_gamepad(&mut self) {
+        self.gamepad_axes.clear();
+        self.gamepad_buttons.clear();
     }
 
+    /// Ends the frame. Must be called exactly once per frame, after all systems ran:
+    /// the `*_just_pressed` / `*_just_released` queries compare against the state
+    /// saved here, so they stay true for exactly one frame
     pub fn update(&mut self) {
-        self.keys_previous = self.keys_current.clone();
-        self.mouse_buttons_previous = self.mouse_buttons_current.clone();
+        // clone_from reuses the previous maps' storage instead of allocating every frame
+        self.keys_previous.clone_from(&self.keys_current);
+        self.mouse_buttons_previous
+            .clone_from(&self.mouse_buttons_current);
         self.mouse_delta = (0.0, 0.0);
         self.scroll_delta = 0.0;
     }
 
     // Key state queries
-    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+    /// True only on the frame the key went down, use `is_key_down` while held
+    pub fn is_key_just_pressed(&self, key: KeyCode) -> bool {
         self.keys_current.get(&key) == Some(&ElementState::Pressed)
             && self.keys_previous.get(&key) != Some(&ElementState::Pressed)
     }
 
-    pub fn is_key_released(&self, key: KeyCode) -> bool {
+    /// True only on the frame the key went up
+    pub fn is_key_just_released(&self, key: KeyCode) -> bool {
         self.keys_current.get(&key) == Some(&ElementState::Released)
             && self.keys_previous.get(&key) == Some(&ElementState::Pressed)
     }
 
+    #[deprecated(note = "edge triggered, renamed to `is_key_just_pressed`")]
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.is_key_just_pressed(key)
+    }
+
+    #[deprecated(note = "edge triggered, renamed to `is_key_just_released`")]
+    pub fn is_key_released(&self, key: KeyCode) -> bool {
+        self.is_key_just_released(key)
+    }
+
     pub fn is_key_down(&self, key: KeyCode) -> bool {
         self.keys_current.get(&key) == Some(&ElementState::Pressed)
     }
 
+    /// True while either the left or the right key of `modifier` is held
+    pub fn is_modifier_down(&self, modifier: ModifierKey) -> bool {
+        let from_os = match modifier {
+            ModifierKey::Shift => self.modifiers.shift_key(),
+            ModifierKey::Control => self.modifiers.control_key(),
+            ModifierKey::Alt => self.modifiers.alt_key(),
+            ModifierKey::Super => self.modifiers.super_key(),
+        };
+        from_os
+            || modifier
+                .key_codes()
+                .iter()
+                .any(|&key| self.is_key_down(key))
+    }
+
     // Mouse state queries
-    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+    /// True only on the frame the button went down, use `is_mouse_button_down` while held
+    pub fn is_mouse_button_just_pressed(&self, button: MouseButton) -> bool {
         self.mouse_buttons_current.get(&button) == Some(&ElementState::Pressed)
             && self.mouse_buttons_previous.get(&button) != Some(&ElementState::Pressed)
     }
 
-    pub fn is_mouse_button_released(&self, button: MouseButton) -> bool {
+    /// True only on the frame the button went up
+    pub fn is_mouse_button_just_released(&self, button: MouseButton) -> bool {
         self.mouse_buttons_current.get(&button) == Some(&ElementState::Released)
             && self.mouse_buttons_previous.get(&button) == Some(&ElementState::Pressed)
     }
 
+    #[deprecated(note = "edge triggered, renamed to `is_mouse_button_just_pressed`")]
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.is_mouse_button_just_pressed(button)
+    }
+
+    #[deprecated(note = "edge triggered, renamed to `is_mouse_button_just_released`")]
+    pub fn is_mouse_button_released(&self, button: MouseButton) -> bool {
+        self.is_mouse_button_just_released(button)
+    }
+
     pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
         self.mouse_buttons_current.get(&button) == Some(&ElementState::Pressed)
     }
@@ -82,6 +276,21 @@ impl Input {
         self.mouse_position
     }
 
+    /// Maps the physical cursor position from `(0, 0)..=(width, height)` to `(-1, -1)..=(1, 1)`.
+    /// Y still grows downward, flip it for NDC
+    pub fn mouse_position_normalized(&self, viewport_width: u32, viewport_height: u32) -> [f32; 2] {
+        let (x, y) = self.mouse_position;
+        [
+            (x / viewport_width.max(1) as f64 * 2.0 - 1.0) as f32,
+            (y / viewport_height.max(1) as f64 * 2.0 - 1.0) as f32,
+        ]
+    }
+
+    /// Cursor position in logical pixels, `hidpi` is the window's scale factor
+    pub fn mouse_position_logical(&self, hidpi: f64) -> (f64, f64) {
+        (self.mouse_position.0 / hidpi, self.mouse_position.1 / hidpi)
+    }
+
     pub fn mouse_delta(&self) -> (f64, f64) {
         self.mouse_delta
     }
@@ -89,4 +298,18 @@ impl Input {
     pub fn scroll_delta(&self) -> f64 {
         self.scroll_delta
     }
+
+    // Gamepad state queries, always idle unless built with the "gamepad" feature
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        let value = self.gamepad_axes.get(&axis).copied().unwrap_or(0.0);
+        if value.abs() < GAMEPAD_DEADZONE {
+            0.0
+        } else {
+            value
+        }
+    }
+
+    pub fn is_gamepad_button_down(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.get(&button) == Some(&ElementState::Pressed)
+    }
 }
@@ -0,0 +1,141 @@
+struct LensFlareSettings {
+    intensity: f32,
+    visible_threshold: f32,
+    num_ghosts: u32,
+    _padding: u32,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+// Leading fields of the voxel settings uniform, only the light is read here
+struct VoxelSettings {
+    max: f32,
+    r_inner: f32,
+    r: f32,
+    max_height: f32,
+    max_water_height: f32,
+    water_height: f32,
+    tunnel_radius: f32,
+    surface_factor: f32,
+    camera_speed: f32,
+    camera_time_offset: f32,
+    voxel_level: i32,
+    voxel_size: f32,
+    steps: i32,
+    max_dist: f32,
+    min_dist: f32,
+    eps: f32,
+
+    light_color: vec4f,
+    light_direction: vec4f,
+};
+
+@group(0) @binding(0) var<uniform> settings: LensFlareSettings;
+@group(0) @binding(1) var<uniform> camera: CameraUniform;
+@group(0) @binding(2) var<uniform> voxel_settings: VoxelSettings;
+
+@group(1) @binding(0) var scene: texture_2d<f32>;
+@group(1) @binding(1) var depth_texture: texture_depth_2d;
+@group(1) @binding(2) var output: texture_storage_2d<rgba32float, write>;
+
+// Depth taps around the sun are spread over this many pixels
+const OCCLUSION_RADIUS: i32 = 8;
+const OCCLUSION_TAPS: i32 = 5;
+// Flare fades out over this distance past the screen edge, in uv
+const EDGE_FADE: f32 = 0.25;
+const STARBURST_RAYS: f32 = 6.0;
+
+// Fraction of depth taps around the sun that see the sky
+fn sun_visibility(sun_uv: vec2f, dims: vec2<u32>) -> f32 {
+    let sun_pixel = vec2<i32>(sun_uv * vec2f(dims));
+    let step = OCCLUSION_RADIUS * 2 / (OCCLUSION_TAPS - 1);
+    var visible = 0.0;
+    for (var y = 0; y < OCCLUSION_TAPS; y = y + 1) {
+        for (var x = 0; x < OCCLUSION_TAPS; x = x + 1) {
+            // Off screen taps reuse the nearest edge pixel, the edge fade covers the rest
+            let pixel = clamp(
+                sun_pixel + vec2<i32>(x, y) * step - OCCLUSION_RADIUS,
+                vec2<i32>(0),
+                vec2<i32>(dims) - 1,
+            );
+            visible += select(0.0, 1.0, textureLoad(depth_texture, pixel, 0) >= 1.0);
+        }
+    }
+    return visible / f32(OCCLUSION_TAPS * OCCLUSION_TAPS);
+}
+
+// Soft edged disc, `offset` is aspect corrected
+fn disc(offset: vec2f, radius: f32) -> f32 {
+    return smoothstep(radius, radius * 0.7, length(offset));
+}
+
+// Procedural stand-in for a flare atlas: a starburst at the sun, a halo ring
+// around the screen center and ghosts reflected through the center
+fn flare(uv: vec2f, sun_uv: vec2f, aspect: vec2f) -> vec3f {
+    let to_sun = (uv - sun_uv) * aspect;
+    let d = length(to_sun);
+    let angle = atan2(to_sun.y, to_sun.x);
+    let rays = pow(abs(cos(angle * STARBURST_RAYS * 0.5)), 12.0);
+    var color = vec3f(exp(-d * 12.0) + rays * exp(-d * 6.0) * 0.4);
+
+    // Halo ring, brightest on the side facing the sun
+    let from_center = (uv - 0.5) * aspect;
+    let sun_from_center = (sun_uv - 0.5) * aspect;
+    let halo_facing = max(dot(normalize(from_center + 1e-5), normalize(sun_from_center + 1e-5)), 0.0);
+    let ring = smoothstep(0.04, 0.0, abs(length(from_center) - 0.35));
+    color += vec3f(0.3, 0.25, 0.4) * ring * halo_facing * 0.3;
+
+    // Ghosts at fractions of the line from the sun through the center
+    let axis = vec2f(0.5) - sun_uv;
+    for (var i = 0u; i < settings.num_ghosts; i = i + 1u) {
+        let t = 2.0 * f32(i + 1u) / f32(settings.num_ghosts + 1u);
+        let seed = fract(f32(i) * 0.618034);
+        let radius = mix(0.02, 0.08, seed);
+        let tint = mix(vec3f(0.4, 0.6, 1.0), vec3f(1.0, 0.6, 0.3), seed);
+        let ghost = disc((uv - (sun_uv + axis * t)) * aspect, radius);
+        color += tint * ghost * 0.15;
+    }
+    return color;
+}
+
+@compute @workgroup_size(8, 8) // LENS_FLARE_WORKGROUP_SIZE in lens_flare.rs
+fn lens_flare_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let center = textureLoad(scene, pixel, 0);
+
+    // The sun is infinitely far, project its direction with w = 0
+    let light_dir = normalize(voxel_settings.light_direction.xyz);
+    let clip = camera.view_proj * vec4f(light_dir, 0.0);
+    if (clip.w <= 0.0) {
+        textureStore(output, pixel, center);
+        return;
+    }
+    let ndc = clip.xy / clip.w;
+    let sun_uv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+    // Fade toward the screen edges and behind terrain
+    let outside = max(max(-sun_uv, sun_uv - 1.0), vec2f(0.0));
+    let edge = 1.0 - saturate(max(outside.x, outside.y) / EDGE_FADE);
+    let visibility = sun_visibility(sun_uv, dims) * edge;
+    let fade = smoothstep(settings.visible_threshold, 1.0, visibility);
+    if (fade <= 0.0) {
+        textureStore(output, pixel, center);
+        return;
+    }
+
+    let uv = (vec2f(pixel) + 0.5) / vec2f(dims);
+    let aspect = vec2f(f32(dims.x) / f32(dims.y), 1.0);
+    let light = voxel_settings.light_color.rgb * voxel_settings.light_color.w;
+    let color = flare(uv, sun_uv, aspect) * light * settings.intensity * fade;
+    textureStore(output, pixel, vec4f(center.rgb + color, center.a));
+}
@@ -0,0 +1,138 @@
+#![feature(portable_simd)]
+
+mod app;
+
+mod input;
+use input::*;
+
+mod img_utils;
+use img_utils::*;
+
+mod vertex;
+
+mod wgpu_ctx;
+use wgpu_ctx::*;
+
+mod components;
+use components::*;
+
+mod systems;
+use systems::*;
+
+mod world;
+
+mod model;
+use model::*;
+
+mod bloom;
+use bloom::*;
+
+mod color_correction;
+use color_correction::*;
+
+mod shadow_map;
+use shadow_map::*;
+
+mod ssao;
+use ssao::*;
+
+mod dof;
+use dof::*;
+
+mod fog;
+use fog::*;
+
+mod shader_reload;
+use shader_reload::*;
+
+mod frustum;
+use frustum::*;
+
+mod time_of_day;
+use time_of_day::*;
+
+mod debug_draw;
+use debug_draw::*;
+
+mod render_graph;
+
+mod film_grain;
+use film_grain::*;
+
+mod skybox;
+use skybox::*;
+
+mod motion_blur;
+use motion_blur::*;
+
+mod procgen_terrain;
+use procgen_terrain::*;
+
+mod chunk_streaming;
+use chunk_streaming::*;
+
+mod indirect_cull;
+use indirect_cull::*;
+
+mod lens_flare;
+use lens_flare::*;
+
+mod voxel_editor;
+use voxel_editor::*;
+
+mod auto_exposure;
+use auto_exposure::*;
+
+mod chromatic_aberration;
+use chromatic_aberration::*;
+
+mod msaa;
+use msaa::*;
+
+mod perf_stats;
+use perf_stats::*;
+
+mod water_surface;
+use water_surface::*;
+
+mod frame_allocator;
+use frame_allocator::*;
+
+mod occlusion;
+use occlusion::*;
+
+mod fxaa;
+use fxaa::*;
+
+mod dynamic_resolution;
+use dynamic_resolution::*;
+
+mod snow_accumulation;
+use snow_accumulation::*;
+
+mod scene_serializer;
+use scene_serializer::*;
+
+mod ambient_occlusion_baker;
+use ambient_occlusion_baker::*;
+
+mod gbuffer;
+use gbuffer::*;
+
+mod mesh_bvh;
+use mesh_bvh::*;
+
+mod render_stats;
+use render_stats::*;
+
+#[cfg(feature = "gamepad")]
+mod gamepad;
+#[cfg(feature = "gamepad")]
+use gamepad::*;
+
+// The binary runs the App on the WgpuCtx renderer, the benches drive the camera system directly
+pub use app::App;
+pub use components::{Camera, CameraController, Transform};
+pub use input::Input;
+pub use systems::update_camera_system;
+pub use wgpu_ctx::WgpuCtx;
@@ -1,43 +1,9 @@
-#![feature(portable_simd)]
-
-use crate::app::App;
+use fps_engine::App;
 use winit::error::EventLoopError;
 use winit::event_loop::{ControlFlow, EventLoop};
 
-mod app;
-pub use app::*;
-
-mod input;
-pub use input::*;
-
-mod img_utils;
-pub use img_utils::*;
-
-mod vertex;
-pub use vertex::*;
-
-mod wgpu_ctx;
-pub use wgpu_ctx::*;
-
-mod components;
-pub use components::*;
-
-mod systems;
-pub use systems::*;
-
-mod world;
-pub use world::*;
-
-mod model;
-pub use model::*;
-
-mod bloom;
-pub use bloom::*;
-
-mod color_correction;
-pub use color_correction::*;
-
 fn main() -> Result<(), EventLoopError> {
+    tracing_subscriber::fmt::init();
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     let mut app = App::default();
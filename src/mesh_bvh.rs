@@ -0,0 +1,339 @@
+use crate::vertex::Vertex;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+
+// Triangles a leaf holds before the builder tries to split it
+const MAX_LEAF_TRIANGLES: usize = 4;
+// Centroid buckets per axis the SAH split candidates are taken from
+const SAH_BINS: usize = 12;
+// Cost of visiting a node relative to testing one triangle
+const TRAVERSAL_COST: f32 = 1.0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray3<S> {
+    pub origin: Point3<S>,
+    pub direction: Vector3<S>,
+}
+
+impl<S> Ray3<S> {
+    pub fn new(origin: Point3<S>, direction: Vector3<S>) -> Self {
+        Self { origin, direction }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32, // Along the normalized ray direction
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>, // Interpolated vertex normal
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BvhNode {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+    // Leaves hold triangles `first..first + count`, inner nodes have a count of 0 and
+    // their children at `first` and `first + 1`
+    pub first: u32,
+    pub count: u32,
+}
+
+/// Bounding volume hierarchy over a mesh's triangles for CPU ray casts, split by the
+/// surface area heuristic
+#[derive(Debug, Clone)]
+pub struct MeshBvh {
+    pub nodes: Vec<BvhNode>, // Root first
+    pub triangles: Vec<[Vertex; 3]>,
+}
+
+impl MeshBvh {
+    pub fn build(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let mut triangles: Vec<[Vertex; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|i| vertices[triangle[i] as usize]))
+            .collect();
+        let mut nodes = Vec::with_capacity(2 * triangles.len() / MAX_LEAF_TRIANGLES + 1);
+        if !triangles.is_empty() {
+            nodes.push(leaf(&triangles, 0, triangles.len()));
+            split(&mut nodes, &mut triangles, 0);
+        }
+        Self { nodes, triangles }
+    }
+
+    /// Closest hit of `ray` with the mesh, in the space the vertices are in
+    pub fn intersect(&self, ray: Ray3<f32>) -> Option<RayHit> {
+        if self.nodes.is_empty() || ray.direction.magnitude2() <= f32::EPSILON {
+            return None;
+        }
+        let origin = ray.origin.to_vec();
+        let direction = ray.direction.normalize();
+        let inv_direction = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+        let mut closest: Option<(f32, usize, f32, f32)> = None;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let max_distance = closest.map_or(f32::INFINITY, |(t, ..)| t);
+            if !slab_test(node, origin, inv_direction, max_distance) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+                continue;
+            }
+            let first = node.first as usize;
+            for i in first..first + node.count as usize {
+                if let Some((t, u, v)) = intersect_triangle(&self.triangles[i], origin, direction) {
+                    if t < closest.map_or(f32::INFINITY, |(t, ..)| t) {
+                        closest = Some((t, i, u, v));
+                    }
+                }
+            }
+        }
+
+        closest.map(|(t, i, u, v)| {
+            let [a, b, c] = &self.triangles[i];
+            let normal = Vector3::from(a.normal) * (1.0 - u - v)
+                + Vector3::from(b.normal) * u
+                + Vector3::from(c.normal) * v;
+            let normal = if normal.magnitude2() > f32::EPSILON {
+                normal.normalize()
+            } else {
+                face_normal(&self.triangles[i])
+            };
+            RayHit {
+                distance: t,
+                position: Point3::from_vec(origin + direction * t),
+                normal,
+            }
+        })
+    }
+}
+
+fn positions(triangle: &[Vertex; 3]) -> [Vector3<f32>; 3] {
+    triangle.map(|vertex| Vector3::from(vertex.position))
+}
+
+fn centroid(triangle: &[Vertex; 3]) -> Vector3<f32> {
+    let [a, b, c] = positions(triangle);
+    (a + b + c) / 3.0
+}
+
+fn face_normal(triangle: &[Vertex; 3]) -> Vector3<f32> {
+    let [a, b, c] = positions(triangle);
+    (b - a).cross(c - a).normalize()
+}
+
+fn component_min(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn component_max(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+fn surface_area(min: Vector3<f32>, max: Vector3<f32>) -> f32 {
+    let extent = max - min;
+    2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+}
+
+/// Leaf over `triangles[first..first + count]` with their bounds
+fn leaf(triangles: &[[Vertex; 3]], first: usize, count: usize) -> BvhNode {
+    let (min, max) = triangles[first..first + count]
+        .iter()
+        .flat_map(positions)
+        .fold(
+            (
+                Vector3::from([f32::INFINITY; 3]),
+                Vector3::from([f32::NEG_INFINITY; 3]),
+            ),
+            |(min, max), p| (component_min(min, p), component_max(max, p)),
+        );
+    BvhNode {
+        min,
+        max,
+        first: first as u32,
+        count: count as u32,
+    }
+}
+
+/// Splits the leaf at `index` where the binned SAH cost beats keeping it, recursing into
+/// both halves
+fn split(nodes: &mut Vec<BvhNode>, triangles: &mut [[Vertex; 3]], index: usize) {
+    let node = nodes[index];
+    let (first, count) = (node.first as usize, node.count as usize);
+    if count <= MAX_LEAF_TRIANGLES {
+        return;
+    }
+
+    let (centroid_min, centroid_max) = triangles[first..first + count].iter().map(centroid).fold(
+        (
+            Vector3::from([f32::INFINITY; 3]),
+            Vector3::from([f32::NEG_INFINITY; 3]),
+        ),
+        |(min, max), c| (component_min(min, c), component_max(max, c)),
+    );
+
+    // Best (cost, axis, split position) over the bin boundaries of every axis
+    let mut best: Option<(f32, usize, f32)> = None;
+    for axis in 0..3 {
+        let extent = centroid_max[axis] - centroid_min[axis];
+        if extent <= f32::EPSILON {
+            continue;
+        }
+        let bin_of = |c: Vector3<f32>| {
+            (((c[axis] - centroid_min[axis]) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+        };
+        let mut bins = [(
+            0usize,
+            Vector3::from([f32::INFINITY; 3]),
+            Vector3::from([f32::NEG_INFINITY; 3]),
+        ); SAH_BINS];
+        for triangle in &triangles[first..first + count] {
+            let bin = &mut bins[bin_of(centroid(triangle))];
+            bin.0 += 1;
+            for p in positions(triangle) {
+                bin.1 = component_min(bin.1, p);
+                bin.2 = component_max(bin.2, p);
+            }
+        }
+        // Triangle count and bounds of a run of bins
+        let side = |bins: &[(usize, Vector3<f32>, Vector3<f32>)]| {
+            bins.iter().fold(
+                (0, bins[0].1, bins[0].2),
+                |(count, min, max), &(n, bin_min, bin_max)| {
+                    (
+                        count + n,
+                        component_min(min, bin_min),
+                        component_max(max, bin_max),
+                    )
+                },
+            )
+        };
+        for boundary in 1..SAH_BINS {
+            let (left_count, left_min, left_max) = side(&bins[..boundary]);
+            let (right_count, right_min, right_max) = side(&bins[boundary..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = left_count as f32 * surface_area(left_min, left_max)
+                + right_count as f32 * surface_area(right_min, right_max);
+            if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                let position = centroid_min[axis] + extent * boundary as f32 / SAH_BINS as f32;
+                best = Some((cost, axis, position));
+            }
+        }
+    }
+
+    let parent_area = surface_area(node.min, node.max);
+    let Some((cost, axis, position)) = best else {
+        return;
+    };
+    // Both costs relative to the parent's area, splitting must beat testing every triangle
+    if parent_area > 0.0 && TRAVERSAL_COST + cost / parent_area >= count as f32 {
+        return;
+    }
+
+    let range = &mut triangles[first..first + count];
+    let mut left_count = 0;
+    for i in 0..range.len() {
+        if centroid(&range[i])[axis] < position {
+            range.swap(i, left_count);
+            left_count += 1;
+        }
+    }
+    // Centroids on a bin boundary can round to either side
+    if left_count == 0 || left_count == count {
+        return;
+    }
+
+    let left = nodes.len();
+    nodes.push(leaf(triangles, first, left_count));
+    nodes.push(leaf(triangles, first + left_count, count - left_count));
+    nodes[index].first = left as u32;
+    nodes[index].count = 0;
+    split(nodes, triangles, left);
+    split(nodes, triangles, left + 1);
+}
+
+fn slab_test(
+    node: &BvhNode,
+    origin: Vector3<f32>,
+    inv_direction: Vector3<f32>,
+    max_distance: f32,
+) -> bool {
+    let mut t_min = 0.0f32;
+    let mut t_max = max_distance;
+    for axis in 0..3 {
+        let t0 = (node.min[axis] - origin[axis]) * inv_direction[axis];
+        let t1 = (node.max[axis] - origin[axis]) * inv_direction[axis];
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+    t_min <= t_max
+}
+
+/// Möller-Trumbore, returns the distance and the barycentrics of `b` and `c`
+fn intersect_triangle(
+    triangle: &[Vertex; 3],
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+) -> Option<(f32, f32, f32)> {
+    let [a, b, c] = positions(triangle);
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() <= f32::EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inv_det;
+    (t > 0.0).then_some((t, u, v))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MeshBvh, Ray3};
+    use crate::vertex::Vertex;
+    use cgmath::{Point3, Vector3};
+
+    #[test]
+    pub fn test_ray_hits_closest_triangle() {
+        // A row of 16 unit quads facing +z, stepping back along -z
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..16 {
+            let z = -(i as f32);
+            let base = vertices.len() as u32;
+            for (x, y) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                vertices.push(Vertex {
+                    position: [x + i as f32 * 0.5, y, z],
+                    tex_uv: [0.0, 0.0],
+                    normal: [0.0, 0.0, 1.0],
+                    ao: 1.0,
+                });
+            }
+            indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        let bvh = MeshBvh::build(&vertices, &indices);
+        assert!(bvh.nodes.len() > 1);
+
+        let ray = Ray3::new(Point3::new(0.75, 0.5, 5.0), Vector3::new(0.0, 0.0, -2.0));
+        let hit = bvh.intersect(ray).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-4);
+        assert_eq!(hit.normal, Vector3::new(0.0, 0.0, 1.0));
+        let miss = Ray3::new(Point3::new(-5.0, 0.5, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(bvh.intersect(miss).is_none());
+    }
+}
@@ -1,11 +1,71 @@
-use crate::vertex::Vertex;
+use crate::img_utils::{generate_mipmaps, mip_level_count, RgbaImg};
+use crate::vertex::{SkinVertex, Vertex};
+use crate::{AmbientOcclusionBaker, BoundingBox, MeshBvh};
+use cgmath::{
+    InnerSpace, Matrix, Matrix3, Matrix4, Point3, Quaternion, SquareMatrix, Transform, Vector3,
+    VectorSpace,
+};
 use gltf::Gltf;
+use std::fmt;
 use std::path::Path;
 use wgpu::util::DeviceExt;
 
+const FALLBACK_TEXTURE_PATH: &str = "./assets/images/example-img.png";
+// Hemisphere rays per vertex for the baked ambient occlusion
+const AO_BAKE_RAYS: u32 = 16;
+// Meshes with more triangles than this skip the bake
+const AO_BAKE_MAX_TRIANGLES: usize = 8192;
+
+/// Handle of a loaded model, stays valid when other models are unloaded
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModelKey(pub(crate) u64);
+
+#[derive(Debug)]
+pub enum ModelLoadError {
+    GltfError(gltf::Error),
+    MissingBlob,           // A buffer points at the binary chunk but the file has none
+    MissingBuffer(String), // External buffer files aren't supported, holds the uri
+    TextureError(String),  // Name of the material whose base color couldn't be loaded
+}
+
+impl fmt::Display for ModelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GltfError(err) => write!(f, "invalid glTF: {}", err),
+            Self::MissingBlob => write!(f, "glTF has no binary blob"),
+            Self::MissingBuffer(uri) => write!(f, "unsupported external buffer {}", uri),
+            Self::TextureError(material) => {
+                write!(f, "no texture could be loaded for material {}", material)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::GltfError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for ModelLoadError {
+    fn from(err: gltf::Error) -> Self {
+        Self::GltfError(err)
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub instance_buffer: Option<wgpu::Buffer>, // Model matrices of all instances, grown on demand
+    pub bounding_box: BoundingBox,             // Model space bounds of all meshes
+    pub nodes: Vec<ModelNode>,
+    pub skeleton: Option<Skeleton>, // First skin of the file
+    pub animations: Vec<AnimationClip>,
+    pub joint_buffer: wgpu::Buffer, // Joint matrix palette, a single identity without a skin
+    pub joint_bind_group: Option<wgpu::BindGroup>,
 }
 
 pub struct Mesh {
@@ -14,202 +74,653 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material_index: Option<usize>,
+    pub skin_buffer: wgpu::Buffer,
+    pub bvh: Option<MeshBvh>, // Model space triangles for ray casts, built when requested
+}
+
+/// Local transform of a glTF node, animation channels override parts of it
+#[derive(Debug, Copy, Clone)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl NodeTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+pub struct ModelNode {
+    pub parent: Option<usize>,
+    pub transform: NodeTransform, // Rest pose
+}
+
+pub struct Skeleton {
+    pub joint_nodes: Vec<usize>,   // Node index of each joint
+    pub joints: Vec<Matrix4<f32>>, // Current joint matrices, bind pose included
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+pub enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    Rotations(Vec<Quaternion<f32>>),
+    Scales(Vec<Vector3<f32>>),
+}
+
+pub struct AnimationChannel {
+    pub node: usize,
+    pub step: bool, // Hold each keyframe instead of interpolating
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+impl AnimationChannel {
+    /// Keyframes around `time` and the blend factor between them
+    fn keyframes(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        let prev = next - 1;
+        if next == self.times.len() || self.step {
+            return (prev, prev, 0.0);
+        }
+        let span = self.times[next] - self.times[prev];
+        (prev, next, (time - self.times[prev]) / span)
+    }
+
+    fn apply(&self, time: f32, transform: &mut NodeTransform) {
+        let (a, b, f) = self.keyframes(time);
+        match &self.values {
+            ChannelValues::Translations(values) => {
+                transform.translation = values[a].lerp(values[b], f)
+            }
+            ChannelValues::Rotations(values) => {
+                // Blend along the shorter arc
+                let end = if values[a].dot(values[b]) < 0.0 {
+                    -values[b]
+                } else {
+                    values[b]
+                };
+                transform.rotation = values[a].nlerp(end, f);
+            }
+            ChannelValues::Scales(values) => transform.scale = values[a].lerp(values[b], f),
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32, // Seconds, the last keyframe of any channel
+    pub channels: Vec<AnimationChannel>,
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: crate::img_utils::RgbaImg,
+    pub diffuse_texture: RgbaImg,
     pub texture: Option<wgpu::Texture>, // Store the texture
     pub texture_view: Option<wgpu::TextureView>, // Store the view
+    pub normal_map: RgbaImg,            // Flat when the material has no normal texture
+    pub normal_texture: Option<wgpu::Texture>,
+    // Roughness in green, metallic in blue, with the material factors baked in
+    pub metallic_roughness: RgbaImg,
+    pub metallic_roughness_texture: Option<wgpu::Texture>,
+    pub emission_map: RgbaImg, // White when the material only has an emissive factor
+    pub emission_texture: Option<wgpu::Texture>,
+    pub emission_color: [f32; 3], // Linear, multiplies the emission map
+    pub emission_strength: f32,   // Scales past 1 for HDR glow that feeds bloom
     pub bind_group: Option<wgpu::BindGroup>,
 }
 
+/// Per material constants of the model shader, `emission.w` is the strength
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    emission: [f32; 4],
+}
+
 impl Model {
+    /// `load_with_bvh` builds a `MeshBvh` for every mesh, for picking and physics
     pub fn load<P: AsRef<Path>>(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         path: P,
-    ) -> Option<Self> {
+        load_with_bvh: bool,
+    ) -> Result<Self, ModelLoadError> {
         let path = path.as_ref();
-        let gltf = match Gltf::open(path) {
-            Ok(gltf) => gltf,
-            Err(err) => {
-                eprintln!("Failed to load GLTF from {}: {}", path.display(), err);
-                return None;
+        // Texture loads below are logged inside the model's span
+        let _span = tracing::info_span!("load_model", path = %path.display()).entered();
+        let gltf = Gltf::open(path)?;
+
+        // Readers below index straight into the blob, so every buffer has to live there
+        for buffer in gltf.buffers() {
+            match buffer.source() {
+                gltf::buffer::Source::Bin if gltf.blob.is_none() => {
+                    return Err(ModelLoadError::MissingBlob)
+                }
+                gltf::buffer::Source::Bin => {}
+                gltf::buffer::Source::Uri(uri) => {
+                    return Err(ModelLoadError::MissingBuffer(uri.to_string()))
+                }
             }
-        };
+        }
 
-        let mut meshes = Vec::new();
         let mut materials = Vec::new();
 
         // Process materials first
         for material in gltf.materials() {
             let name = material.name().unwrap_or("unnamed material").to_string();
 
-            // Get the base color texture
-            let diffuse_texture =
-                if let Some(pbr) = material.pbr_metallic_roughness().base_color_texture() {
-                    let texture = pbr.texture();
-                    let source = texture.source().source();
-
-                    match source {
-                        gltf::image::Source::Uri { uri, .. } => {
-                            let texture_path = path.parent().unwrap().join(uri);
-                            crate::img_utils::RgbaImg::new(texture_path.to_str().unwrap())
-                        }
-                        _ => {
-                            // Fall back to default texture for embedded or unsupported sources
-                            crate::img_utils::RgbaImg::new("./assets/images/example-img.png")
-                        }
-                    }
-                } else {
-                    // Fall back to default texture
-                    crate::img_utils::RgbaImg::new("./assets/images/example-img.png")
-                };
+            // Get the base color texture, falling back to the default texture for
+            // missing, embedded or unreadable sources
+            let pbr = material.pbr_metallic_roughness();
+            let diffuse_texture = pbr
+                .base_color_texture()
+                .and_then(|info| load_texture_image(path, &info.texture()))
+                .or_else(|| RgbaImg::new(FALLBACK_TEXTURE_PATH));
 
-            // Inside the Material handling code
-            let diffuse_texture = if let Some(pbr) =
-                material.pbr_metallic_roughness().base_color_texture()
-            {
-                let texture = pbr.texture();
-                let source = texture.source().source();
-
-                match source {
-                    gltf::image::Source::Uri { uri, .. } => {
-                        let texture_path = path.parent().unwrap().join(uri);
-                        match crate::img_utils::RgbaImg::new(texture_path.to_str().unwrap()) {
-                            Some(texture) => Some(texture),
-                            None => {
-                                eprintln!("Failed to load texture from {}, using fallback", uri);
-                                crate::img_utils::RgbaImg::new("./assets/images/example-img.png")
-                            }
-                        }
-                    }
-                    _ => {
-                        // Fall back to default texture for embedded or unsupported sources
-                        crate::img_utils::RgbaImg::new("./assets/images/example-img.png")
-                    }
-                }
-            } else {
-                // Fall back to default texture
-                crate::img_utils::RgbaImg::new("./assets/images/example-img.png")
+            // Missing normal maps sample as straight up, leaving the vertex normal as is
+            let normal_map = material
+                .normal_texture()
+                .and_then(|normal| load_texture_image(path, &normal.texture()))
+                .unwrap_or_else(|| RgbaImg::from_color([128, 128, 255, 255]));
+
+            let mut metallic_roughness = pbr
+                .metallic_roughness_texture()
+                .and_then(|info| load_texture_image(path, &info.texture()))
+                .unwrap_or_else(|| RgbaImg::from_color([255; 4]));
+            let (metallic, roughness) = (pbr.metallic_factor(), pbr.roughness_factor());
+            for texel in metallic_roughness.bytes.chunks_exact_mut(4) {
+                texel[1] = (texel[1] as f32 * roughness) as u8;
+                texel[2] = (texel[2] as f32 * metallic) as u8;
+            }
+
+            // The factor is linear, so it's applied in the shader rather than to the sRGB texels
+            let emission_map = material
+                .emissive_texture()
+                .and_then(|info| load_texture_image(path, &info.texture()))
+                .unwrap_or_else(|| RgbaImg::from_color([255; 4]));
+
+            // Primitives index materials by position, so a missing one can't be skipped
+            let Some(texture) = diffuse_texture else {
+                return Err(ModelLoadError::TextureError(name));
             };
+            materials.push(Material {
+                name,
+                diffuse_texture: texture,
+                bind_group: None,
+                texture: None,
+                texture_view: None,
+                normal_map,
+                normal_texture: None,
+                metallic_roughness,
+                metallic_roughness_texture: None,
+                emission_map,
+                emission_texture: None,
+                emission_color: material.emissive_factor(),
+                emission_strength: material.emissive_strength().unwrap_or(1.0),
+            });
+        }
 
-            // Only create a material if the texture exists
-            if let Some(texture) = diffuse_texture {
-                materials.push(Material {
-                    name,
-                    diffuse_texture: texture,
-                    bind_group: None,
-                    texture: None,
-                    texture_view: None,
-                });
-            } else {
-                eprintln!("Couldn't load any texture for material {}, skipping", name);
+        // Process meshes, walking the node hierarchy so node transforms are applied
+        let mut meshes = Vec::new();
+        let mut all_positions = Vec::new();
+        match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => {
+                for node in scene.nodes() {
+                    Self::load_node(
+                        &gltf,
+                        device,
+                        &node,
+                        Matrix4::identity(),
+                        load_with_bvh,
+                        &mut meshes,
+                        &mut all_positions,
+                    );
+                }
+            }
+            // Files without scenes have no node transforms, load the meshes as is
+            None => {
+                for mesh in gltf.meshes() {
+                    Self::load_mesh(
+                        &gltf,
+                        device,
+                        &mesh,
+                        Matrix4::identity(),
+                        load_with_bvh,
+                        &mut meshes,
+                        &mut all_positions,
+                    );
+                }
             }
         }
 
-        // Process meshes
-        for mesh in gltf.meshes() {
-            let name = mesh.name().unwrap_or("unnamed mesh").to_string();
+        let nodes = Self::load_nodes(&gltf);
+        let skeleton = gltf
+            .skins()
+            .next()
+            .map(|skin| Self::load_skin(&gltf, &skin, &nodes));
+        let animations = Self::load_animations(&gltf);
 
-            for primitive in mesh.primitives() {
-                // Get the material for this primitive
-                let material_index = primitive.material().index();
+        let palette: Vec<[[f32; 4]; 4]> = match &skeleton {
+            Some(skeleton) => skeleton.joints.iter().map(|&m| m.into()).collect(),
+            None => vec![Matrix4::identity().into()],
+        };
+        let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Joint Palette Buffer"),
+            contents: bytemuck::cast_slice(&palette),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
 
-                // Access vertex position attribute
-                let reader = primitive.reader(|buffer| {
-                    let buffer_data = gltf.blob.as_ref().unwrap();
-                    Some(&buffer_data[..])
-                });
+        tracing::info!(
+            path = %path.display(),
+            meshes = meshes.len(),
+            materials = materials.len(),
+            animations = animations.len(),
+            "Loaded model"
+        );
+        Ok(Model {
+            meshes,
+            materials,
+            instance_buffer: None,
+            bounding_box: BoundingBox::from_points(all_positions),
+            nodes,
+            skeleton,
+            animations,
+            joint_buffer,
+            joint_bind_group: None,
+        })
+    }
 
-                // Extract positions, normals, and texture coordinates
-                let positions = if let Some(iter) = reader.read_positions() {
-                    iter.collect::<Vec<_>>()
-                } else {
-                    continue; // Skip if no positions
-                };
+    fn load_nodes(gltf: &Gltf) -> Vec<ModelNode> {
+        let mut parents = vec![None; gltf.nodes().len()];
+        for node in gltf.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+            }
+        }
 
-                let normals = if let Some(iter) = reader.read_normals() {
-                    iter.collect::<Vec<_>>()
-                } else {
-                    vec![[0.0, 1.0, 0.0]; positions.len()] // Use up vector as default
-                };
+        gltf.nodes()
+            .map(|node| {
+                let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+                ModelNode {
+                    parent: parents[node.index()],
+                    transform: NodeTransform {
+                        translation: translation.into(),
+                        rotation: Quaternion::new(w, x, y, z),
+                        scale: scale.into(),
+                    },
+                }
+            })
+            .collect()
+    }
 
-                let tex_coords = if let Some(iter) = reader.read_tex_coords(0) {
-                    iter.into_f32().collect::<Vec<_>>()
-                } else {
-                    vec![[0.0, 0.0]; positions.len()] // Use default UV
-                };
+    fn load_skin(gltf: &Gltf, skin: &gltf::Skin, nodes: &[ModelNode]) -> Skeleton {
+        let joint_nodes: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let reader = skin.reader(|_| gltf.blob.as_deref());
+        let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+            Some(iter) => iter.map(Matrix4::from).collect(),
+            None => vec![Matrix4::identity(); joint_nodes.len()],
+        };
+
+        let mut skeleton = Skeleton {
+            joints: vec![Matrix4::identity(); joint_nodes.len()],
+            joint_nodes,
+            inverse_bind_matrices,
+        };
+        // Start out in the rest pose
+        let locals: Vec<NodeTransform> = nodes.iter().map(|node| node.transform).collect();
+        skeleton.pose(&global_matrices(nodes, &locals));
+        skeleton
+    }
 
-                // Combine data into our Vertex format
-                let vertices: Vec<Vertex> = positions
-                    .into_iter()
-                    .zip(tex_coords.into_iter())
-                    .zip(normals.into_iter())
-                    .map(|((pos, tex), norm)| Vertex {
-                        position: pos,
-                        tex_uv: tex,
-                        normal: norm,
+    fn load_animations(gltf: &Gltf) -> Vec<AnimationClip> {
+        gltf.animations()
+            .map(|animation| {
+                let channels: Vec<AnimationChannel> = animation
+                    .channels()
+                    .filter_map(|channel| {
+                        let reader = channel.reader(|_| gltf.blob.as_deref());
+                        let times: Vec<f32> = reader.read_inputs()?.collect();
+                        let interpolation = channel.sampler().interpolation();
+                        // Cubic spline keyframes are stored as (in tangent, value, out
+                        // tangent), keep only the values and interpolate linearly
+                        let keep = |i: usize| {
+                            interpolation != gltf::animation::Interpolation::CubicSpline
+                                || i % 3 == 1
+                        };
+                        let values = match reader.read_outputs()? {
+                            gltf::animation::util::ReadOutputs::Translations(iter) => {
+                                ChannelValues::Translations(
+                                    iter.enumerate()
+                                        .filter(|(i, _)| keep(*i))
+                                        .map(|(_, v)| v.into())
+                                        .collect(),
+                                )
+                            }
+                            gltf::animation::util::ReadOutputs::Rotations(iter) => {
+                                ChannelValues::Rotations(
+                                    iter.into_f32()
+                                        .enumerate()
+                                        .filter(|(i, _)| keep(*i))
+                                        .map(|(_, [x, y, z, w])| Quaternion::new(w, x, y, z))
+                                        .collect(),
+                                )
+                            }
+                            gltf::animation::util::ReadOutputs::Scales(iter) => {
+                                ChannelValues::Scales(
+                                    iter.enumerate()
+                                        .filter(|(i, _)| keep(*i))
+                                        .map(|(_, v)| v.into())
+                                        .collect(),
+                                )
+                            }
+                            // Morph targets aren't loaded, so their weights have nothing to drive
+                            gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => {
+                                return None
+                            }
+                        };
+                        Some(AnimationChannel {
+                            node: channel.target().node().index(),
+                            step: interpolation == gltf::animation::Interpolation::Step,
+                            times,
+                            values,
+                        })
                     })
                     .collect();
+                let duration = channels
+                    .iter()
+                    .filter_map(|channel| channel.times.last().copied())
+                    .fold(0.0, f32::max);
 
-                // Get indices
-                let indices = if let Some(indices) = reader.read_indices() {
-                    indices.into_u32().collect::<Vec<_>>()
-                } else {
-                    // If no indices, create sequential indices
-                    (0..vertices.len() as u32).collect()
-                };
+                AnimationClip {
+                    name: animation.name().unwrap_or("unnamed animation").to_string(),
+                    duration,
+                    channels,
+                }
+            })
+            .collect()
+    }
+
+    /// Poses the skeleton at `time` seconds into animation `clip_index`
+    pub fn animate(&mut self, clip_index: usize, time: f32) {
+        let (Some(skeleton), Some(clip)) = (&mut self.skeleton, self.animations.get(clip_index))
+        else {
+            return;
+        };
 
-                // Create buffers
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{} Vertex Buffer", name)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{} Index Buffer", name)),
-                    contents: bytemuck::cast_slice(&indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-
-                meshes.push(Mesh {
-                    name: name.clone(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: indices.len() as u32,
-                    material_index,
-                });
+        let mut locals: Vec<NodeTransform> = self.nodes.iter().map(|node| node.transform).collect();
+        for channel in &clip.channels {
+            if let Some(local) = locals.get_mut(channel.node) {
+                channel.apply(time, local);
             }
         }
+        skeleton.pose(&global_matrices(&self.nodes, &locals));
+    }
 
-        Some(Model { meshes, materials })
+    /// Uploads the current joint matrices for the vertex shader
+    pub fn upload_joints(&self, queue: &wgpu::Queue) {
+        if let Some(skeleton) = &self.skeleton {
+            let palette: Vec<[[f32; 4]; 4]> = skeleton.joints.iter().map(|&m| m.into()).collect();
+            queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&palette));
+        }
     }
 
-    // Create bind groups for all materials
-    pub fn create_bind_groups(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
-        for material in &mut self.materials {
-            let texture_size = wgpu::Extent3d {
-                width: material.diffuse_texture.width,
-                height: material.diffuse_texture.height,
-                depth_or_array_layers: 1,
+    /// Uploads the instance model matrices, growing the buffer when more instances
+    /// were spawned than it can hold. Returns the size of the written data
+    pub fn write_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[[[f32; 4]; 4]],
+    ) -> wgpu::BufferAddress {
+        let data: &[u8] = bytemuck::cast_slice(instances);
+        let size = data.len() as wgpu::BufferAddress;
+        if self
+            .instance_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Model Instance Buffer"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        queue.write_buffer(self.instance_buffer.as_ref().unwrap(), 0, data);
+        size
+    }
+
+    pub fn create_joint_bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        self.joint_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: self.joint_buffer.as_entire_binding(),
+            }],
+            label: Some("Joint Palette Bind Group"),
+        }));
+    }
+
+    /// Loads `node` and its children, composing each node's local transform with `parent`
+    fn load_node(
+        gltf: &Gltf,
+        device: &wgpu::Device,
+        node: &gltf::Node,
+        parent: Matrix4<f32>,
+        load_with_bvh: bool,
+        meshes: &mut Vec<Mesh>,
+        all_positions: &mut Vec<[f32; 3]>,
+    ) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            // Skinned meshes are placed by their joints, the node transform is ignored
+            let mesh_transform = if node.skin().is_some() {
+                Matrix4::identity()
+            } else {
+                transform
+            };
+            Self::load_mesh(
+                gltf,
+                device,
+                &mesh,
+                mesh_transform,
+                load_with_bvh,
+                meshes,
+                all_positions,
+            );
+        }
+        for child in node.children() {
+            Self::load_node(
+                gltf,
+                device,
+                &child,
+                transform,
+                load_with_bvh,
+                meshes,
+                all_positions,
+            );
+        }
+    }
+
+    fn load_mesh(
+        gltf: &Gltf,
+        device: &wgpu::Device,
+        mesh: &gltf::Mesh,
+        transform: Matrix4<f32>,
+        load_with_bvh: bool,
+        meshes: &mut Vec<Mesh>,
+        all_positions: &mut Vec<[f32; 3]>,
+    ) {
+        let name = mesh.name().unwrap_or("unnamed mesh").to_string();
+
+        // Normals need the inverse transpose to stay perpendicular under non-uniform scale
+        let linear = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal_matrix = linear
+            .invert()
+            .map(|m| m.transpose())
+            .unwrap_or(Matrix3::identity());
+
+        for primitive in mesh.primitives() {
+            // Get the material for this primitive
+            let material_index = primitive.material().index();
+
+            // Access vertex position attribute
+            // Buffers were checked to be in the blob when the file was opened
+            let reader = primitive.reader(|_| gltf.blob.as_deref());
+
+            // Extract positions, normals, and texture coordinates, baked into model space
+            let positions = if let Some(iter) = reader.read_positions() {
+                iter.map(|p| transform.transform_point(Point3::from(p)).into())
+                    .collect::<Vec<[f32; 3]>>()
+            } else {
+                continue; // Skip if no positions
+            };
+            all_positions.extend_from_slice(&positions);
+
+            let normals = if let Some(iter) = reader.read_normals() {
+                iter.map(|n| (normal_matrix * Vector3::from(n)).normalize().into())
+                    .collect::<Vec<[f32; 3]>>()
+            } else {
+                vec![[0.0, 1.0, 0.0]; positions.len()] // Use up vector as default
             };
 
-            let texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some(&format!("{} Texture", material.name)),
-                size: texture_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
+            let tex_coords = if let Some(iter) = reader.read_tex_coords(0) {
+                iter.into_f32().collect::<Vec<_>>()
+            } else {
+                vec![[0.0, 0.0]; positions.len()] // Use default UV
+            };
+
+            // Combine data into our Vertex format
+            let mut vertices: Vec<Vertex> = positions
+                .into_iter()
+                .zip(tex_coords)
+                .zip(normals)
+                .map(|((pos, tex), norm)| Vertex {
+                    position: pos,
+                    tex_uv: tex,
+                    normal: norm,
+                    ao: 1.0,
+                })
+                .collect();
+
+            let skin: Vec<SkinVertex> = match (reader.read_joints(0), reader.read_weights(0)) {
+                (Some(joints), Some(weights)) => joints
+                    .into_u16()
+                    .zip(weights.into_f32())
+                    .map(|(joints, weights)| SkinVertex {
+                        joints: joints.map(u32::from),
+                        weights,
+                    })
+                    .collect(),
+                _ => vec![SkinVertex::default(); vertices.len()],
+            };
+
+            // Get indices
+            let indices = if let Some(indices) = reader.read_indices() {
+                indices.into_u32().collect::<Vec<_>>()
+            } else {
+                // If no indices, create sequential indices
+                (0..vertices.len() as u32).collect()
+            };
+
+            // Larger meshes would stall loading, they stay unoccluded
+            if indices.len() / 3 <= AO_BAKE_MAX_TRIANGLES {
+                let bvh = MeshBvh::build(&vertices, &indices);
+                let ao = AmbientOcclusionBaker::bake(&vertices, &bvh, AO_BAKE_RAYS);
+                for (vertex, ao) in vertices.iter_mut().zip(ao) {
+                    vertex.ao = ao;
+                }
+            }
+
+            // Create buffers
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            let skin_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Skin Buffer", name)),
+                contents: bytemuck::cast_slice(&skin),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+            meshes.push(Mesh {
+                name: name.clone(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                material_index,
+                skin_buffer,
+                bvh: load_with_bvh.then(|| MeshBvh::build(&vertices, &indices)),
             });
+        }
+    }
 
+    // Create bind groups for all materials
+    pub fn create_bind_groups(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        for material in &mut self.materials {
+            let texture = create_material_texture(
+                device,
+                &format!("{} Texture", material.name),
+                &material.diffuse_texture,
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+            );
             let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+            // Normals and metallic/roughness are data, not colors, so they aren't sRGB
+            let normal_texture = create_material_texture(
+                device,
+                &format!("{} Normal Texture", material.name),
+                &material.normal_map,
+                wgpu::TextureFormat::Rgba8Unorm,
+            );
+            let normal_view = normal_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let metallic_roughness_texture = create_material_texture(
+                device,
+                &format!("{} Metallic Roughness Texture", material.name),
+                &material.metallic_roughness,
+                wgpu::TextureFormat::Rgba8Unorm,
+            );
+            let metallic_roughness_view =
+                metallic_roughness_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let emission_texture = create_material_texture(
+                device,
+                &format!("{} Emission Texture", material.name),
+                &material.emission_map,
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+            );
+            let emission_view =
+                emission_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let [r, g, b] = material.emission_color;
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Material Buffer", material.name)),
+                contents: bytemuck::cast_slice(&[MaterialUniform {
+                    emission: [r, g, b, material.emission_strength],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
             let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
                 address_mode_u: wgpu::AddressMode::Repeat,
                 address_mode_v: wgpu::AddressMode::Repeat,
@@ -222,6 +733,9 @@ impl Model {
 
             material.texture = Some(texture);
             material.texture_view = Some(texture_view.clone());
+            material.normal_texture = Some(normal_texture);
+            material.metallic_roughness_texture = Some(metallic_roughness_texture);
+            material.emission_texture = Some(emission_texture);
 
             material.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout,
@@ -234,6 +748,22 @@ impl Model {
                         binding: 1,
                         resource: wgpu::BindingResource::Sampler(&sampler),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&normal_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&metallic_roughness_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&emission_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
                 ],
                 label: Some(&format!("{} Bind Group", material.name)),
             }));
@@ -241,31 +771,127 @@ impl Model {
     }
 
     // Upload all textures to the GPU
-    pub fn upload_textures(&self, queue: &wgpu::Queue) {
+    /// Writes the base level of every material texture and generates the rest of the mips
+    pub fn upload_textures(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
         for material in &self.materials {
-            if let (Some(texture), Some(_)) = (&material.texture, &material.bind_group) {
-                let texture_size = wgpu::Extent3d {
-                    width: material.diffuse_texture.width,
-                    height: material.diffuse_texture.height,
-                    depth_or_array_layers: 1,
-                };
+            if material.bind_group.is_none() {
+                continue;
+            }
+            for (texture, image) in [
+                (&material.texture, &material.diffuse_texture),
+                (&material.normal_texture, &material.normal_map),
+                (
+                    &material.metallic_roughness_texture,
+                    &material.metallic_roughness,
+                ),
+                (&material.emission_texture, &material.emission_map),
+            ] {
+                if let Some(texture) = texture {
+                    write_material_texture(queue, texture, image);
+                    generate_mipmaps(device, queue, texture, texture.format());
+                }
+            }
+        }
+    }
+}
 
-                queue.write_texture(
-                    wgpu::TexelCopyTextureInfo {
-                        texture,
-                        mip_level: 0,
-                        origin: wgpu::Origin3d::ZERO,
-                        aspect: wgpu::TextureAspect::All,
-                    },
-                    &material.diffuse_texture.bytes,
-                    wgpu::TexelCopyBufferLayout {
-                        offset: 0,
-                        bytes_per_row: Some(4 * material.diffuse_texture.width),
-                        rows_per_image: Some(material.diffuse_texture.height),
-                    },
-                    texture_size,
-                );
+impl Skeleton {
+    /// Updates the joint matrices from the global transform of every node
+    fn pose(&mut self, globals: &[Matrix4<f32>]) {
+        for ((joint, node), inverse_bind) in self
+            .joints
+            .iter_mut()
+            .zip(&self.joint_nodes)
+            .zip(&self.inverse_bind_matrices)
+        {
+            *joint = globals[*node] * inverse_bind;
+        }
+    }
+}
+
+/// Composes each node's local matrix with its ancestors'
+fn global_matrices(nodes: &[ModelNode], locals: &[NodeTransform]) -> Vec<Matrix4<f32>> {
+    fn resolve(
+        index: usize,
+        nodes: &[ModelNode],
+        locals: &[NodeTransform],
+        globals: &mut [Option<Matrix4<f32>>],
+    ) -> Matrix4<f32> {
+        if let Some(global) = globals[index] {
+            return global;
+        }
+        let local = locals[index].matrix();
+        let global = match nodes[index].parent {
+            Some(parent) => resolve(parent, nodes, locals, globals) * local,
+            None => local,
+        };
+        globals[index] = Some(global);
+        global
+    }
+
+    let mut globals = vec![None; locals.len()];
+    (0..locals.len())
+        .map(|index| resolve(index, nodes, locals, &mut globals))
+        .collect()
+}
+
+/// Loads the image behind a glTF texture, only external files are supported
+fn load_texture_image(path: &Path, texture: &gltf::Texture) -> Option<RgbaImg> {
+    match texture.source().source() {
+        gltf::image::Source::Uri { uri, .. } => {
+            let image = RgbaImg::new(path.parent().unwrap().join(uri).to_str().unwrap());
+            if image.is_none() {
+                tracing::warn!(uri, "Failed to load texture, using fallback");
             }
+            image
         }
+        _ => None,
     }
 }
+
+fn create_material_texture(
+    device: &wgpu::Device,
+    label: &str,
+    image: &RgbaImg,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        },
+        // The levels below the base are rendered by `generate_mipmaps`
+        mip_level_count: mip_level_count(image.width, image.height),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    })
+}
+
+fn write_material_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &RgbaImg) {
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        &image.bytes,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width),
+            rows_per_image: Some(image.height),
+        },
+        wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
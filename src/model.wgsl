@@ -0,0 +1,144 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+    prev_view_proj: mat4x4<f32>, // Last frame's view_proj, for velocities
+};
+
+@group(1) @binding(0) var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0) var diffuse_texture: texture_2d<f32>;
+@group(2) @binding(1) var diffuse_sampler: sampler;
+// Tangent space normals, flat (0.5, 0.5, 1.0) for materials without a normal map
+@group(2) @binding(2) var normal_texture: texture_2d<f32>;
+// Roughness in green, metallic in blue
+@group(2) @binding(3) var metallic_roughness_texture: texture_2d<f32>;
+@group(2) @binding(4) var emission_texture: texture_2d<f32>;
+
+struct MaterialUniform {
+    emission: vec4f, // Linear color in rgb, strength in w
+};
+@group(2) @binding(5) var<uniform> material: MaterialUniform;
+
+// Joint matrices of the model's skeleton, a single identity for unskinned models
+@group(3) @binding(0) var<storage, read> joints: array<mat4x4<f32>>;
+
+// Matches the default voxel light direction
+const LIGHT_DIRECTION: vec3f = vec3f(0.507746, 0.716817, 0.477878);
+
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(1) tex_uv: vec2f,
+    @location(2) normal: vec3f,
+    @location(3) ao: f32,
+};
+
+// Per instance model matrix, one column per location
+struct InstanceInput {
+    @location(4) model_0: vec4f,
+    @location(5) model_1: vec4f,
+    @location(6) model_2: vec4f,
+    @location(7) model_3: vec4f,
+};
+
+// Unskinned meshes have all weights zero
+struct SkinInput {
+    @location(8) joints: vec4<u32>,
+    @location(9) weights: vec4f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) tex_uv: vec2f,
+    @location(1) normal: vec3f,
+    @location(2) world_position: vec3f,
+    @location(3) ao: f32,
+};
+
+struct FragmentOutput {
+    @location(0) color: vec4f,
+    @location(1) normal_depth: vec4f,
+    // Camera motion only, instances don't keep last frame's transform
+    @location(2) velocity: vec4f,
+};
+
+fn skin_matrix(skin: SkinInput) -> mat4x4<f32> {
+    if (dot(skin.weights, vec4f(1.0)) <= 0.0) {
+        return mat4x4<f32>(
+            vec4f(1.0, 0.0, 0.0, 0.0),
+            vec4f(0.0, 1.0, 0.0, 0.0),
+            vec4f(0.0, 0.0, 1.0, 0.0),
+            vec4f(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joints[skin.joints.x] * skin.weights.x
+        + joints[skin.joints.y] * skin.weights.y
+        + joints[skin.joints.z] * skin.weights.z
+        + joints[skin.joints.w] * skin.weights.w;
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput, skin: SkinInput) -> VertexOutput {
+    let instance_matrix = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let model = instance_matrix * skin_matrix(skin);
+    let world_position = model * vec4f(vertex.position, 1.0);
+
+    var output: VertexOutput;
+    output.position = camera.view_proj * world_position;
+    // The camera projection uses OpenGL depth (-1..1), remap to wgpu's 0..1 like the voxel pass
+    output.position.z = output.position.z * 0.5 + output.position.w * 0.5;
+    output.tex_uv = vertex.tex_uv;
+    output.normal = normalize((model * vec4f(vertex.normal, 0.0)).xyz);
+    output.world_position = world_position.xyz;
+    output.ao = vertex.ao;
+    return output;
+}
+
+// Meshes carry no tangents, so the TBN frame is rebuilt from screen space derivatives
+fn perturb_normal(n: vec3f, position: vec3f, uv: vec2f, tangent_normal: vec3f) -> vec3f {
+    let dp1 = dpdx(position);
+    let dp2 = dpdy(position);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+    let dp2_perp = cross(dp2, n);
+    let dp1_perp = cross(n, dp1);
+    let t = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let b = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    let inv_max = inverseSqrt(max(max(dot(t, t), dot(b, b)), 1e-20));
+    let tbn = mat3x3<f32>(t * inv_max, b * inv_max, n);
+    return normalize(tbn * tangent_normal);
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> FragmentOutput {
+    let albedo = textureSample(diffuse_texture, diffuse_sampler, input.tex_uv);
+    let tangent_normal = textureSample(normal_texture, diffuse_sampler, input.tex_uv).xyz * 2.0 - 1.0;
+    let metallic_roughness = textureSample(metallic_roughness_texture, diffuse_sampler, input.tex_uv);
+    let roughness = max(metallic_roughness.g, 0.05);
+    let metallic = metallic_roughness.b;
+
+    let n = perturb_normal(normalize(input.normal), input.world_position, input.tex_uv, tangent_normal);
+    let dif = max(dot(n, LIGHT_DIRECTION), 0.0);
+
+    // Blinn-Phong highlight, sharper for smooth surfaces and tinted for metals
+    let view_dir = normalize(camera.camera_position - input.world_position);
+    let half_dir = normalize(LIGHT_DIRECTION + view_dir);
+    let shininess = 2.0 / (roughness * roughness * roughness * roughness) - 2.0;
+    let spec = pow(max(dot(n, half_dir), 0.0), shininess) * dif;
+    let f0 = mix(vec3f(0.04), albedo.rgb, metallic);
+    let diffuse = albedo.rgb * (1.0 - metallic);
+
+    var output: FragmentOutput;
+    // Baked AO only darkens the ambient part, the direct light has its own shadowing
+    // Emission is added unlit, above 1 it spills into bloom
+    let emission = textureSample(emission_texture, diffuse_sampler, input.tex_uv).rgb
+        * material.emission.rgb * material.emission.w;
+    output.color = vec4f(diffuse * (dif * 0.6 + 0.4 * input.ao) + f0 * spec + emission, 1.0);
+    output.normal_depth = vec4f(n, distance(camera.camera_position, input.world_position));
+    let current = camera.view_proj * vec4f(input.world_position, 1.0);
+    let previous = camera.prev_view_proj * vec4f(input.world_position, 1.0);
+    output.velocity = vec4f(current.xy / current.w - previous.xy / previous.w, 0.0, 0.0);
+    return output;
+}
@@ -0,0 +1,287 @@
+use crate::bloom::{create_compute_pipeline, storage_format_source};
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Must match `@workgroup_size` of the entry points in motion_blur.wgsl
+const MOTION_BLUR_WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MotionBlurSettings {
+    pub samples: u32,
+    pub shutter_angle: f32, // Degrees, 360 blurs over the whole frame, 0 disables the effect
+    _padding: [f32; 2],
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            samples: 8,
+            shutter_angle: 0.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Inputs of `MotionBlurEffect::new`, the targets the blur reads
+pub struct MotionBlurParams<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub scene_texture_view: &'a wgpu::TextureView,
+    pub velocity_texture_view: &'a wgpu::TextureView,
+}
+
+/// Smears the scene along the per pixel screen space velocity written by the scene pass
+pub struct MotionBlurEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    settings: MotionBlurSettings,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group: wgpu::BindGroup,
+    output_texture: wgpu::Texture,
+    pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl MotionBlurEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        params: MotionBlurParams,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let MotionBlurParams {
+            width,
+            height,
+            format,
+            scene_texture_view,
+            velocity_texture_view,
+        } = params;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Motion Blur Shader"),
+            source: wgpu::ShaderSource::Wgsl(storage_format_source(
+                include_str!("motion_blur.wgsl"),
+                format,
+            )),
+        });
+
+        let settings = MotionBlurSettings::default();
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Motion Blur Settings Buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Group 0: settings uniform
+        let settings_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Motion Blur Settings Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            }],
+            label: Some("Motion Blur Settings Bind Group"),
+        });
+
+        // Group 1: scene color, velocity and the output
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Motion Blur Texture Bind Group Layout"),
+                entries: &[
+                    texture_entry(0),
+                    texture_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_bind_group_layout, &texture_bind_group_layout],
+            &shader,
+            "motion_blur_main",
+            "Motion Blur Pipeline",
+            pipeline_cache,
+        );
+
+        let output_texture = create_output_texture(&device, width, height, format);
+        let texture_bind_group = create_texture_bind_group(
+            &device,
+            &texture_bind_group_layout,
+            scene_texture_view,
+            velocity_texture_view,
+            &output_texture,
+        );
+
+        Self {
+            device,
+            queue,
+            settings,
+            settings_buffer,
+            settings_bind_group,
+            texture_bind_group_layout,
+            texture_bind_group,
+            output_texture,
+            pipeline,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        scene_texture_view: &wgpu::TextureView,
+        velocity_texture_view: &wgpu::TextureView,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.output_texture = create_output_texture(&self.device, width, height, self.format);
+        self.texture_bind_group = create_texture_bind_group(
+            &self.device,
+            &self.texture_bind_group_layout,
+            scene_texture_view,
+            velocity_texture_view,
+            &self.output_texture,
+        );
+    }
+
+    pub fn update_uniform(&mut self, settings: MotionBlurSettings) {
+        self.settings = settings;
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Blurs `scene_texture` in place, skipped entirely while the shutter is closed
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, scene_texture: &wgpu::Texture) {
+        if self.settings.shutter_angle <= 0.0 || self.settings.samples < 2 {
+            return;
+        }
+
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Motion Blur Compute Pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            cpass.set_bind_group(1, &self.texture_bind_group, &[]);
+            cpass.dispatch_workgroups(
+                self.width.div_ceil(MOTION_BLUR_WORKGROUP_SIZE),
+                self.height.div_ceil(MOTION_BLUR_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            scene_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for MotionBlurEffect {
+    /// Reads and writes the scene, declared as its single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].texture);
+    }
+}
+
+fn create_output_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Motion Blur Output Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scene_texture_view: &wgpu::TextureView,
+    velocity_texture_view: &wgpu::TextureView,
+    output_texture: &wgpu::Texture,
+) -> wgpu::BindGroup {
+    let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(scene_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(velocity_texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&output_view),
+            },
+        ],
+        label: Some("Motion Blur Texture Bind Group"),
+    })
+}
@@ -0,0 +1,49 @@
+struct MotionBlurSettings {
+    samples: u32,
+    shutter_angle: f32,
+    _padding: vec2f,
+};
+
+@group(0) @binding(0) var<uniform> settings: MotionBlurSettings;
+
+@group(1) @binding(0) var scene: texture_2d<f32>;
+// xy = NDC position this frame minus last frame
+@group(1) @binding(1) var velocity_texture: texture_2d<f32>;
+@group(1) @binding(2) var output: texture_storage_2d<rgba32float, write>;
+
+const MAX_SAMPLES: u32 = 32u;
+// Longest blur in pixels, also hides garbage velocities of points behind last frame's camera
+const MAX_BLUR: f32 = 64.0;
+
+@compute @workgroup_size(8, 8) // MOTION_BLUR_WORKGROUP_SIZE in motion_blur.rs
+fn motion_blur_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+
+    // NDC to pixels, y flipped, scaled by the fraction of the frame the shutter is open
+    let velocity = textureLoad(velocity_texture, pixel, 0).xy;
+    var blur = velocity * vec2f(0.5, -0.5) * vec2f(dims) * (settings.shutter_angle / 360.0);
+    let blur_length = length(blur);
+    if (blur_length > MAX_BLUR) {
+        blur *= MAX_BLUR / blur_length;
+    }
+
+    // Samples trail behind the pixel, closer ones weigh more
+    let samples = clamp(settings.samples, 1u, MAX_SAMPLES);
+    var color = vec3f(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < samples; i = i + 1u) {
+        let t = f32(i) / f32(samples);
+        let offset = vec2<i32>(round(-blur * t));
+        let sample_pixel = clamp(pixel + offset, vec2<i32>(0), vec2<i32>(dims) - 1);
+        let weight = 1.0 - t;
+        color += textureLoad(scene, sample_pixel, 0).rgb * weight;
+        total_weight += weight;
+    }
+
+    let center = textureLoad(scene, pixel, 0);
+    textureStore(output, pixel, vec4f(color / total_weight, center.a));
+}
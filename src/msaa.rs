@@ -0,0 +1,225 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+// Format of the velocity attachment, scene color and normal/depth use the render format
+const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Multisampled attachments of the scene pass. The color attachments resolve into the
+/// single sampled scene, normal/depth and velocity textures the post-processing reads,
+/// depth can't be resolved by a render pass so `resolve_depth` copies it over
+pub struct MsaaTargets {
+    device: Arc<wgpu::Device>,
+    sample_count: u32,
+    format: wgpu::TextureFormat, // Of the scene color and normal/depth attachments
+    color_views: [wgpu::TextureView; 3],
+    depth_view: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl MsaaTargets {
+    /// Whether the scene pass attachments can be rendered with `sample_count` samples
+    /// and resolved. Rgba32Float only multisamples with adapter specific format features
+    pub fn supported(
+        adapter: &wgpu::Adapter,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> bool {
+        if !adapter
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            return false;
+        }
+        let color_formats = [format, format, VELOCITY_FORMAT];
+        let color_supported = color_formats.iter().all(|&format| {
+            let flags = adapter.get_texture_format_features(format).flags;
+            flags.sample_count_supported(sample_count)
+                && flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+        });
+        color_supported
+            && adapter
+                .get_texture_format_features(DEPTH_FORMAT)
+                .flags
+                .sample_count_supported(sample_count)
+    }
+
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("MSAA Depth Resolve Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("msaa.wgsl"))),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MSAA Depth Resolve Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MSAA Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("MSAA Depth Resolve Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (color_views, depth_view) = create_views(&device, format, sample_count, width, height);
+        let bind_group = create_bind_group(&device, &bind_group_layout, &depth_view);
+
+        Self {
+            device,
+            sample_count,
+            format,
+            color_views,
+            depth_view,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let (color_views, depth_view) =
+            create_views(&self.device, self.format, self.sample_count, width, height);
+        self.bind_group = create_bind_group(&self.device, &self.bind_group_layout, &depth_view);
+        self.color_views = color_views;
+        self.depth_view = depth_view;
+    }
+
+    /// Scene, normal/depth and velocity attachments, in that order
+    pub fn color_views(&self) -> &[wgpu::TextureView; 3] {
+        &self.color_views
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    /// Writes the nearest sample of every pixel into the single sampled `target_view`
+    pub fn resolve_depth(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("MSAA Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+fn create_views(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    width: u32,
+    height: u32,
+) -> ([wgpu::TextureView; 3], wgpu::TextureView) {
+    let create_view = |label: &str, format: wgpu::TextureFormat, usage: wgpu::TextureUsages| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+    let labels = [
+        "MSAA Render Texture",
+        "MSAA Normal Depth Texture",
+        "MSAA Velocity Texture",
+    ];
+    let color_formats = [format, format, VELOCITY_FORMAT];
+    let color_views = [0, 1, 2].map(|i| {
+        create_view(
+            labels[i],
+            color_formats[i],
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+    });
+    let depth_view = create_view(
+        "MSAA Depth Texture",
+        DEPTH_FORMAT,
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    );
+    (color_views, depth_view)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    depth_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("MSAA Depth Resolve Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(depth_view),
+        }],
+    })
+}
@@ -0,0 +1,23 @@
+@group(0) @binding(0) var msaa_depth: texture_depth_multisampled_2d;
+
+// Fullscreen triangle, the corners past the screen are clipped
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let positions = array<vec2<f32>, 3>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(3.0, -1.0),
+        vec2<f32>(-1.0, 3.0)
+    );
+    return vec4<f32>(positions[vertex_index], 0.0, 1.0);
+}
+
+// Keeps the nearest sample so edges resolve to the surface in front
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let pixel = vec2<i32>(position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < i32(textureNumSamples(msaa_depth)); i++) {
+        depth = min(depth, textureLoad(msaa_depth, pixel, i));
+    }
+    return depth;
+}
@@ -0,0 +1,309 @@
+use crate::vertex::{create_vertex_buffer_layout, INDICIES_SQUARE, VERTICES_CUBE};
+use crate::{BoundingBox, CullBounds};
+use cgmath::Point3;
+use hecs::Entity;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use wgpu::util::DeviceExt;
+
+// Objects past this many in a frame are never culled
+const MAX_OCCLUSION_QUERIES: u32 = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum QueryState {
+    Idle,    // Free to record this frame
+    Copied,  // Resolved into the staging buffer, mapped once that frame was submitted
+    Mapping, // Waiting for the map callback
+}
+
+struct QueryReadback {
+    state: QueryState,
+    results: Option<Vec<u64>>, // Set by the map callback, taken by `poll`
+}
+
+/// One occlusion query per proxy box, resolved and read back a frame or two late
+pub struct OcclusionQuerySet {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    pub results: Vec<u64>, // Samples that passed the depth test, per query of the last readback
+    readback: Arc<Mutex<QueryReadback>>,
+}
+
+impl OcclusionQuerySet {
+    fn new(device: &wgpu::Device, count: u32) -> Self {
+        let size = count as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Occlusion Query Set"),
+                ty: wgpu::QueryType::Occlusion,
+                count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Occlusion Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            staging_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Occlusion Staging Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            results: Vec::new(),
+            readback: Arc::new(Mutex::new(QueryReadback {
+                state: QueryState::Idle,
+                results: None,
+            })),
+        }
+    }
+
+    /// Whether the last frame was read back and taken, so a new one can be recorded
+    fn ready(&self) -> bool {
+        let readback = self.readback.lock().unwrap();
+        readback.state == QueryState::Idle && readback.results.is_none()
+    }
+
+    /// Copies the first `count` queries towards the staging buffer
+    fn resolve(&self, encoder: &mut wgpu::CommandEncoder, count: u32) {
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.staging_buffer,
+            0,
+            count as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress,
+        );
+        self.readback.lock().unwrap().state = QueryState::Copied;
+    }
+
+    /// Starts mapping a resolved frame, returns true once its results were moved
+    /// into `results`
+    fn poll(&mut self, count: usize) -> bool {
+        let mut readback = self.readback.lock().unwrap();
+        if let Some(results) = readback.results.take() {
+            self.results = results;
+            return true;
+        }
+        if readback.state != QueryState::Copied {
+            return false;
+        }
+        readback.state = QueryState::Mapping;
+        drop(readback);
+
+        let buffer = self.staging_buffer.clone();
+        let readback = self.readback.clone();
+        let size = count as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress;
+        self.staging_buffer
+            .slice(..size)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let mut readback = readback.lock().unwrap();
+                readback.state = QueryState::Idle;
+                if result.is_err() {
+                    readback.results = Some(Vec::new());
+                    return;
+                }
+                {
+                    let data = buffer.slice(..size).get_mapped_range();
+                    readback.results = Some(bytemuck::cast_slice(&data).to_vec());
+                }
+                buffer.unmap();
+            });
+        false
+    }
+}
+
+/// Draws the world space bounds of the frustum visible models against the scene depth
+/// and culls the ones none of whose samples passed on the next readback
+pub struct OcclusionCullPass {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    bounds_buffer: Option<wgpu::Buffer>, // Grown to the most proxies drawn in a frame
+    queries: OcclusionQuerySet,
+    queried: Vec<Entity>, // Entity of each query in the frame being read back
+    occluded: HashSet<Entity>,
+}
+
+impl OcclusionCullPass {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        time_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Occlusion Proxy Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("occlusion.wgsl"))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Occlusion Proxy Pipeline Layout"),
+            bind_group_layouts: &[time_bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let bounds_layout = wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<CullBounds>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &wgpu::vertex_attr_array![4 => Float32x4, 5 => Float32x4],
+        };
+        // Depth only, nothing is written so the proxies never show up in the scene
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Occlusion Proxy Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[create_vertex_buffer_layout(), bounds_layout],
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occlusion Proxy Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES_CUBE),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occlusion Proxy Index Buffer"),
+            contents: bytemuck::cast_slice(INDICIES_SQUARE),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let queries = OcclusionQuerySet::new(&device, MAX_OCCLUSION_QUERIES);
+        Self {
+            device,
+            queue,
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            bounds_buffer: None,
+            queries,
+            queried: Vec::new(),
+            occluded: HashSet::new(),
+        }
+    }
+
+    /// Entities whose bounds were hidden in the last frame read back
+    pub fn occluded(&self) -> &HashSet<Entity> {
+        &self.occluded
+    }
+
+    /// Maps the queries of the last recorded frame, call before recording the next one
+    /// so that frame has been submitted
+    pub fn poll(&mut self) {
+        if !self.queries.poll(self.queried.len()) {
+            return;
+        }
+        self.occluded = self
+            .queried
+            .iter()
+            .zip(&self.queries.results)
+            .filter(|(_, &samples)| samples == 0)
+            .map(|(&entity, _)| entity)
+            .collect();
+    }
+
+    /// Draws one proxy per object into `depth_view`, which has to hold this frame's
+    /// scene depth. Skipped while an earlier frame is still being read back
+    pub fn record(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        time_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        camera_position: Point3<f32>,
+        objects: &[(Entity, BoundingBox)],
+    ) {
+        if !self.queries.ready() {
+            return;
+        }
+        // The near plane clips the faces of boxes around the camera, those stay visible
+        let contains_camera = |bounds: &BoundingBox| {
+            (0..3).all(|axis| {
+                bounds.min[axis] <= camera_position[axis]
+                    && camera_position[axis] <= bounds.max[axis]
+            })
+        };
+        self.queried.clear();
+        let mut bounds = Vec::new();
+        for (entity, aabb) in objects {
+            if bounds.len() == MAX_OCCLUSION_QUERIES as usize {
+                break;
+            }
+            if !contains_camera(aabb) {
+                self.queried.push(*entity);
+                bounds.push(CullBounds::from(*aabb));
+            }
+        }
+        if bounds.is_empty() {
+            self.occluded.clear();
+            return;
+        }
+
+        let size = std::mem::size_of_val(bounds.as_slice()) as wgpu::BufferAddress;
+        if self
+            .bounds_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.bounds_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Occlusion Bounds Buffer"),
+                size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let bounds_buffer = self.bounds_buffer.as_ref().unwrap();
+        self.queue
+            .write_buffer(bounds_buffer, 0, bytemuck::cast_slice(&bounds));
+
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Occlusion Proxy Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: Some(&self.queries.query_set),
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, time_bind_group, &[]);
+            rpass.set_bind_group(1, camera_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            rpass.set_vertex_buffer(1, bounds_buffer.slice(..size));
+            rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            for i in 0..bounds.len() as u32 {
+                rpass.begin_occlusion_query(i);
+                rpass.draw_indexed(0..INDICIES_SQUARE.len() as u32, 0, i..i + 1);
+                rpass.end_occlusion_query();
+            }
+        }
+        self.queries.resolve(encoder, bounds.len() as u32);
+    }
+}
@@ -0,0 +1,20 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+@group(1) @binding(0) var<uniform> camera: CameraUniform;
+
+// Stretches the unit cube over the world space bounds of the instance
+@vertex
+fn vs_main(
+    @location(0) position: vec3f,
+    @location(4) bounds_min: vec4f,
+    @location(5) bounds_max: vec4f,
+) -> @builtin(position) vec4f {
+    let world_position = mix(bounds_min.xyz, bounds_max.xyz, position + 0.5);
+    return camera.view_proj * vec4f(world_position, 1.0);
+}
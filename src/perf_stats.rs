@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Frame times kept for the plot
+const MAX_FRAME_TIMES: usize = 128;
+// Frames averaged for the FPS readout
+const FPS_FRAMES: usize = 60;
+
+/// Rolling frame timings and GPU memory for the performance HUD
+#[derive(Debug, Default)]
+pub struct PerfStats {
+    pub frame_times: VecDeque<f32>, // Milliseconds, oldest first
+    pub gpu_memory_used: u64,       // Bytes, 0 where the backend has no allocator report
+}
+
+impl PerfStats {
+    pub fn push_frame_time(&mut self, dt: Duration) {
+        if self.frame_times.len() == MAX_FRAME_TIMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt.as_secs_f32() * 1000.0);
+    }
+
+    /// Only Vulkan and DX12 report their allocations
+    pub fn update_gpu_memory(&mut self, device: &wgpu::Device) {
+        self.gpu_memory_used = device
+            .generate_allocator_report()
+            .map_or(0, |report| report.total_allocated_bytes);
+    }
+
+    pub fn min_frame_time(&self) -> f32 {
+        self.frame_times
+            .iter()
+            .copied()
+            .reduce(f32::min)
+            .unwrap_or(0.0)
+    }
+
+    pub fn max_frame_time(&self) -> f32 {
+        self.frame_times.iter().copied().fold(0.0, f32::max)
+    }
+
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /// Frames per second over the last `FPS_FRAMES` frames
+    pub fn fps(&self) -> f32 {
+        let recent = self.frame_times.iter().rev().take(FPS_FRAMES);
+        let (count, total) = recent.fold((0, 0.0), |(count, total), &ms| (count + 1, total + ms));
+        if total > 0.0 {
+            count as f32 * 1000.0 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PerfStats, MAX_FRAME_TIMES};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_frame_times_are_capped() {
+        let mut stats = PerfStats::default();
+        for i in 0..MAX_FRAME_TIMES + 10 {
+            stats.push_frame_time(Duration::from_millis(i as u64));
+        }
+        assert_eq!(stats.frame_times.len(), MAX_FRAME_TIMES);
+        assert_eq!(stats.min_frame_time(), 10.0);
+    }
+
+    #[test]
+    pub fn test_fps_of_steady_frames() {
+        let mut stats = PerfStats::default();
+        for _ in 0..100 {
+            stats.push_frame_time(Duration::from_millis(20));
+        }
+        assert!((stats.fps() - 50.0).abs() < 0.01);
+    }
+}
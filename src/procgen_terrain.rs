@@ -0,0 +1,117 @@
+use half::f16;
+use noise::{NoiseFn, Perlin};
+
+// Noise periods across the volume, higher gives smaller hills
+const NOISE_FREQUENCY: f64 = 4.0;
+const NOISE_AMPLITUDE: f32 = 0.25;
+
+/// A CPU side signed distance volume the voxel shader can blend with its
+/// analytic terrain. Distances are in volume units, 1.0 spans the whole cube
+pub struct ProcGenTerrain {
+    pub size: u32,
+    pub sdf: Vec<f32>, // x fastest, then y, then z
+}
+
+impl ProcGenTerrain {
+    pub fn new(size: u32, seed: u64) -> Self {
+        Self {
+            size,
+            sdf: Self::generate(size, seed),
+        }
+    }
+
+    /// Hills around the middle of the volume, the ground plane displaced by Perlin noise
+    pub fn generate(size: u32, seed: u64) -> Vec<f32> {
+        Self::generate_chunk(size, seed, [0.0; 3])
+    }
+
+    /// The volume at `origin`, in volume units. Neighbouring origins one unit apart
+    /// tile seamlessly, so `generate` is the chunk at the origin
+    pub fn generate_chunk(size: u32, seed: u64, origin: [f64; 3]) -> Vec<f32> {
+        let perlin = Perlin::new(seed as u32);
+        let scale = 1.0 / size.max(1) as f64;
+        let mut sdf = Vec::with_capacity((size * size * size) as usize);
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let p = [
+                        origin[0] + (x as f64 + 0.5) * scale,
+                        origin[1] + (y as f64 + 0.5) * scale,
+                        origin[2] + (z as f64 + 0.5) * scale,
+                    ];
+                    let n = perlin.get(p.map(|v| v * NOISE_FREQUENCY)) as f32;
+                    sdf.push(p[1] as f32 - 0.5 + NOISE_AMPLITUDE * n);
+                }
+            }
+        }
+        sdf
+    }
+
+    /// Edits a single voxel, out of bounds positions are ignored
+    pub fn set(&mut self, x: u32, y: u32, z: u32, distance: f32) {
+        if x < self.size && y < self.size && z < self.size {
+            self.sdf[((z * self.size + y) * self.size + x) as usize] = distance;
+        }
+    }
+
+    /// `R32Float`, or `R16Float` where the device can't filter 32 bit floats
+    pub fn texture_format(device: &wgpu::Device) -> wgpu::TextureFormat {
+        if device
+            .features()
+            .contains(wgpu::Features::FLOAT32_FILTERABLE)
+        {
+            wgpu::TextureFormat::R32Float
+        } else {
+            wgpu::TextureFormat::R16Float
+        }
+    }
+
+    /// Creates a 3D texture holding the distances, in `texture_format`
+    pub fn upload(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+        let format = Self::texture_format(device);
+        let data = if format == wgpu::TextureFormat::R32Float {
+            bytemuck::cast_slice(&self.sdf).to_vec()
+        } else {
+            let sdf: Vec<u16> = self
+                .sdf
+                .iter()
+                .map(|&d| f16::from_f32(d).to_bits())
+                .collect();
+            bytemuck::cast_slice(&sdf).to_vec()
+        };
+        let size = wgpu::Extent3d {
+            width: self.size,
+            height: self.size,
+            depth_or_array_layers: self.size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ProcGen Terrain Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format,
+            // Streamed chunks are copied on into the chunk atlas
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(format.block_copy_size(None).unwrap() * self.size),
+                rows_per_image: Some(self.size),
+            },
+            size,
+        );
+        texture
+    }
+}
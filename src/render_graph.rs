@@ -0,0 +1,215 @@
+/// A texture a node reads or writes, looked up by resource name at execution
+#[derive(Copy, Clone)]
+pub struct RenderTarget<'a> {
+    pub texture: &'a wgpu::Texture,
+    pub view: &'a wgpu::TextureView,
+}
+
+/// One step of the post-processing chain. `inputs` and `outputs` are in the
+/// order the node was declared with in `RenderGraph::add_node`
+pub trait RenderNode {
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    );
+}
+
+struct GraphNode {
+    name: &'static str,
+    inputs: Vec<&'static str>,
+    outputs: Vec<&'static str>,
+}
+
+/// Declares which named resources each pass reads and writes, and runs the
+/// passes in an order that respects those dependencies
+#[derive(Default)]
+pub struct RenderGraph {
+    nodes: Vec<GraphNode>,
+    order: Vec<usize>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node. It runs after the nodes writing its inputs, whatever order they
+    /// were added in. In place effects on one resource like "scene" chain in the
+    /// order they were added
+    pub fn add_node(
+        &mut self,
+        name: &'static str,
+        inputs: &[&'static str],
+        outputs: &[&'static str],
+    ) -> &mut Self {
+        self.nodes.push(GraphNode {
+            name,
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec(),
+        });
+        self.order = self.sort();
+        self
+    }
+
+    pub fn remove_node(&mut self, name: &str) {
+        self.nodes.retain(|node| node.name != name);
+        self.order = self.sort();
+    }
+
+    /// Node names in execution order
+    #[cfg(test)]
+    pub fn order(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.order.iter().map(|&index| self.nodes[index].name)
+    }
+
+    /// Topological sort over the resources. For every resource, the nodes only writing
+    /// it run first, then the nodes changing it in place, then the nodes only reading it.
+    /// Writers and in place nodes of one resource keep the order they were added in
+    fn sort(&self) -> Vec<usize> {
+        let count = self.nodes.len();
+        let mut dependents = vec![Vec::new(); count];
+        let mut pending = vec![0; count];
+        let mut resources: Vec<&str> = Vec::new();
+        for node in &self.nodes {
+            for &resource in node.inputs.iter().chain(&node.outputs) {
+                if !resources.contains(&resource) {
+                    resources.push(resource);
+                }
+            }
+        }
+        for resource in resources {
+            let stage = |node: &GraphNode| match (
+                node.inputs.contains(&resource),
+                node.outputs.contains(&resource),
+            ) {
+                (false, true) => Some(0),
+                (true, true) => Some(1),
+                (true, false) => Some(2),
+                (false, false) => None,
+            };
+            let users: Vec<(usize, u32)> = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(index, node)| stage(node).map(|stage| (index, stage)))
+                .collect();
+            for &(before, before_stage) in &users {
+                for &(after, after_stage) in &users {
+                    let runs_before = before_stage < after_stage
+                        || (before_stage == after_stage && before_stage < 2 && before < after);
+                    if runs_before && !dependents[before].contains(&after) {
+                        dependents[before].push(after);
+                        pending[after] += 1;
+                    }
+                }
+            }
+        }
+
+        // Kahn's algorithm, picking the earliest added ready node keeps the order stable
+        let mut order = Vec::with_capacity(count);
+        let mut ready: Vec<usize> = (0..count).filter(|&i| pending[i] == 0).collect();
+        while let Some(position) = ready
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &index)| index)
+            .map(|(position, _)| position)
+        {
+            let index = ready.swap_remove(position);
+            order.push(index);
+            for &dependent in &dependents[index] {
+                pending[dependent] -= 1;
+                if pending[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        assert_eq!(order.len(), count, "render graph has a dependency cycle");
+        order
+    }
+
+    /// Records every node. Nodes without an implementation in `nodes`, or with a
+    /// resource missing from `resources`, are skipped
+    pub fn execute(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        nodes: &[(&str, &dyn RenderNode)],
+        resources: &[(&str, RenderTarget)],
+    ) {
+        let lookup = |names: &[&str]| -> Option<Vec<RenderTarget>> {
+            names
+                .iter()
+                .map(|name| {
+                    resources
+                        .iter()
+                        .find(|(resource, _)| resource == name)
+                        .map(|(_, target)| *target)
+                })
+                .collect()
+        };
+
+        for &index in &self.order {
+            let node = &self.nodes[index];
+            let Some((_, render_node)) = nodes.iter().find(|(name, _)| *name == node.name) else {
+                continue;
+            };
+            let (Some(inputs), Some(outputs)) = (lookup(&node.inputs), lookup(&node.outputs))
+            else {
+                continue;
+            };
+            render_node.record(encoder, &inputs, &outputs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RenderGraph;
+
+    #[test]
+    pub fn test_consumer_added_before_producer() {
+        let mut graph = RenderGraph::new();
+        graph
+            .add_node("color_correction", &["post_process"], &["surface"])
+            .add_node("bloom", &["scene"], &["post_process"])
+            .add_node("ssao", &["scene"], &["scene"]);
+        assert_eq!(
+            graph.order().collect::<Vec<_>>(),
+            ["ssao", "bloom", "color_correction"]
+        );
+    }
+
+    #[test]
+    pub fn test_in_place_nodes_keep_insertion_order() {
+        let mut graph = RenderGraph::new();
+        graph
+            .add_node("film_grain", &["surface"], &["surface"])
+            .add_node("fxaa", &["post_process"], &["post_process"])
+            .add_node("color_correction", &["post_process"], &["surface"])
+            .add_node("chromatic_aberration", &["post_process"], &["post_process"]);
+        assert_eq!(
+            graph.order().collect::<Vec<_>>(),
+            [
+                "fxaa",
+                "chromatic_aberration",
+                "color_correction",
+                "film_grain"
+            ]
+        );
+        graph.remove_node("fxaa");
+        assert_eq!(
+            graph.order().collect::<Vec<_>>(),
+            ["chromatic_aberration", "color_correction", "film_grain"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    pub fn test_cycle_panics() {
+        let mut graph = RenderGraph::new();
+        graph
+            .add_node("a", &["x"], &["y"])
+            .add_node("b", &["y"], &["x"]);
+    }
+}
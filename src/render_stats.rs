@@ -0,0 +1,85 @@
+/// Counts of the last frame for the render stats overlay, reset at the start of every draw
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub triangles: u32,     // Before GPU culling for indirect draws
+    pub vertex_bytes: u64,  // Of the vertex buffers bound per draw
+    pub texture_bytes: u64, // Of the textures WgpuCtx owns, models included
+    pub bloom_mip_bytes: u64,
+}
+
+impl RenderStats {
+    pub fn record_draw(&mut self, index_count: u32, instances: u32, vertex_bytes: u64) {
+        self.draw_calls += 1;
+        self.triangles = self
+            .triangles
+            .saturating_add((index_count / 3).saturating_mul(instances));
+        self.vertex_bytes += vertex_bytes;
+    }
+}
+
+/// Bytes `texture` takes over all its mips, layers and samples
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    texture_size_bytes(
+        texture.format(),
+        texture.size(),
+        texture.dimension(),
+        texture.mip_level_count(),
+        texture.sample_count(),
+    )
+}
+
+pub fn texture_size_bytes(
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    dimension: wgpu::TextureDimension,
+    mip_level_count: u32,
+    sample_count: u32,
+) -> u64 {
+    // Combined depth stencil formats have no single copy size
+    let block_size = format
+        .block_copy_size(None)
+        .or_else(|| format.target_pixel_byte_cost())
+        .unwrap_or(4) as u64;
+    let (block_width, block_height) = format.block_dimensions();
+    (0..mip_level_count)
+        .map(|level| {
+            let mip = size.mip_level_size(level, dimension).physical_size(format);
+            let blocks = (mip.width / block_width) as u64
+                * (mip.height / block_height) as u64
+                * mip.depth_or_array_layers as u64;
+            blocks * block_size * sample_count as u64
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::texture_size_bytes;
+
+    #[test]
+    pub fn test_texture_size_covers_mips_and_blocks() {
+        let size = wgpu::Extent3d {
+            width: 4,
+            height: 4,
+            depth_or_array_layers: 1,
+        };
+        let rgba = texture_size_bytes(
+            wgpu::TextureFormat::Rgba8Unorm,
+            size,
+            wgpu::TextureDimension::D2,
+            3,
+            1,
+        );
+        assert_eq!(rgba, 64 + 16 + 4);
+        // 4x4 texel blocks of 8 bytes, the 2x2 and 1x1 mips still take a whole block
+        let bc1 = texture_size_bytes(
+            wgpu::TextureFormat::Bc1RgbaUnorm,
+            size,
+            wgpu::TextureDimension::D2,
+            3,
+            1,
+        );
+        assert_eq!(bc1, 8 * 3);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{Camera, CameraController, Transform, VoxelSettings};
+use hecs::World;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Length of the "Recent Files" list
+const MAX_RECENT_SCENES: usize = 5;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "scene file error: {}", err),
+            Self::Json(err) => write!(f, "invalid scene: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Json(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SceneEntity {
+    transform: Transform,
+    camera: Camera,
+    camera_controller: Option<CameraController>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    voxel_settings: VoxelSettings,
+    entities: Vec<SceneEntity>,
+}
+
+/// Saves the cameras of a world together with the voxel settings as JSON
+pub struct SceneSerializer;
+
+impl SceneSerializer {
+    pub fn save(
+        world: &World,
+        voxel_settings: &VoxelSettings,
+        path: &Path,
+    ) -> Result<(), SceneError> {
+        let mut query = world.query::<(&Transform, &Camera, Option<&CameraController>)>();
+        let entities = query
+            .iter()
+            .map(|(_, (transform, camera, camera_controller))| SceneEntity {
+                transform: *transform,
+                camera: *camera,
+                camera_controller: camera_controller.copied(),
+            })
+            .collect();
+        let scene = SceneFile {
+            voxel_settings: *voxel_settings,
+            entities,
+        };
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &scene)?;
+        Ok(())
+    }
+
+    /// Spawns the saved cameras into a new world. Settings missing from the file keep
+    /// their defaults
+    pub fn load(path: &Path) -> Result<(World, VoxelSettings), SceneError> {
+        let reader = BufReader::new(File::open(path)?);
+        let scene: SceneFile = serde_json::from_reader(reader)?;
+        let mut world = World::new();
+        for entity in scene.entities {
+            let spawned = world.spawn((entity.transform, entity.camera));
+            if let Some(camera_controller) = entity.camera_controller {
+                world.insert_one(spawned, camera_controller).unwrap();
+            }
+        }
+        let mut voxel_settings = scene.voxel_settings;
+        voxel_settings.validate();
+        Ok((world, voxel_settings))
+    }
+}
+
+/// Scene file Ctrl+S and Ctrl+O use, and the last saved ones
+#[derive(Debug)]
+pub struct SceneFiles {
+    pub path: PathBuf,
+    pub requested_load: Option<PathBuf>, // Picked from the recent files, loaded next frame
+    recent: Vec<PathBuf>,                // Most recent first
+}
+
+impl Default for SceneFiles {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("./scene.json"),
+            requested_load: None,
+            recent: Vec::new(),
+        }
+    }
+}
+
+impl SceneFiles {
+    pub fn push_recent(&mut self, path: &Path) {
+        self.recent.retain(|recent| recent != path);
+        self.recent.insert(0, path.to_path_buf());
+        self.recent.truncate(MAX_RECENT_SCENES);
+    }
+
+    pub fn recent(&self) -> &[PathBuf] {
+        &self.recent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SceneFiles, MAX_RECENT_SCENES};
+    use std::path::Path;
+
+    #[test]
+    pub fn test_recent_files_are_unique_and_bounded() {
+        let mut scene_files = SceneFiles::default();
+        for i in 0..MAX_RECENT_SCENES + 2 {
+            scene_files.push_recent(Path::new(&format!("scene{}.json", i)));
+        }
+        scene_files.push_recent(Path::new("scene3.json"));
+        assert_eq!(scene_files.recent().len(), MAX_RECENT_SCENES);
+        assert_eq!(scene_files.recent()[0], Path::new("scene3.json"));
+        assert_eq!(
+            scene_files
+                .recent()
+                .iter()
+                .filter(|path| path.as_path() == Path::new("scene3.json"))
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Polls a shader file's modification time so it can be recompiled while the app runs
+pub struct ShaderWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let last_modified = modified_time(&path);
+        Self {
+            path,
+            last_modified,
+        }
+    }
+
+    /// Returns the new source if the file changed since the last poll
+    pub fn poll(&mut self) -> Option<String> {
+        let modified = modified_time(&self.path)?;
+        if self.last_modified == Some(modified) {
+            return None;
+        }
+        self.last_modified = Some(modified);
+
+        match fs::read_to_string(&self.path) {
+            Ok(source) => Some(source),
+            Err(err) => {
+                tracing::error!(path = %self.path.display(), %err, "Failed to read shader");
+                None
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Runs `f` inside a validation error scope, so a broken shader is reported
+/// instead of hitting the device's uncaptured error handler
+pub fn with_validation<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err.to_string()),
+        None => Ok(value),
+    }
+}
@@ -0,0 +1,306 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+pub const SHADOW_MAP_SIZE: u32 = 1024;
+
+// Half extent and depth of the orthographic volume rendered from the light, in world units
+const SHADOW_EXTENT: f32 = 64.0;
+const SHADOW_DEPTH: f32 = 256.0;
+
+// cgmath produces OpenGL clip space (z in -1..1), wgpu expects z in 0..1
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    pub light_view_proj: [[f32; 4]; 4],
+    pub inv_light_view_proj: [[f32; 4]; 4],
+    pub map_size: f32,
+    pub bias: f32,
+    _padding: [f32; 2],
+}
+
+impl Default for ShadowUniform {
+    fn default() -> Self {
+        Self {
+            light_view_proj: Matrix4::identity().into(),
+            inv_light_view_proj: Matrix4::identity().into(),
+            map_size: SHADOW_MAP_SIZE as f32,
+            bias: 0.002,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Renders the voxel terrain depth from the directional light into a shadow map
+pub struct ShadowMapPass {
+    queue: Arc<wgpu::Queue>,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMapPass {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        time_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        terrain_bind_group_layout: &wgpu::BindGroupLayout,
+        voxel_settings_buffer: &wgpu::Buffer,
+        voxel_shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map Texture"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Comparison sampler used for PCF in the voxel fragment shader
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Map Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniform = ShadowUniform::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // The shadow pass can't bind the shadow map it renders into, so it gets its own
+        // group 2 layout with only the voxel settings and the shadow uniform
+        let settings_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow Settings Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Settings Bind Group"),
+            layout: &settings_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: voxel_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Map Pipeline Layout"),
+            bind_group_layouts: &[
+                time_bind_group_layout,
+                camera_bind_group_layout,
+                terrain_bind_group_layout,
+                &settings_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_shadow_pipeline(&device, &pipeline_layout, voxel_shader);
+
+        Self {
+            queue,
+            view,
+            sampler,
+            uniform,
+            uniform_buffer,
+            settings_bind_group,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    /// Builds the shadow pipeline from a (possibly reloaded) voxel shader
+    pub fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        voxel_shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        create_shadow_pipeline(device, &self.pipeline_layout, voxel_shader)
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: wgpu::RenderPipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Recomputes the light projection so the shadow volume is centered on `focus`
+    pub fn update_light(&mut self, light_direction: [f32; 4], focus: Point3<f32>) {
+        let light_view_proj = light_view_projection(light_direction, focus);
+        self.uniform.light_view_proj = light_view_proj.into();
+        self.uniform.inv_light_view_proj = light_view_proj
+            .invert()
+            .unwrap_or(Matrix4::identity())
+            .into();
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        time_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        terrain_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Map Render Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, time_bind_group, &[]);
+        rpass.set_bind_group(1, camera_bind_group, &[]);
+        rpass.set_bind_group(2, terrain_bind_group, &[]);
+        rpass.set_bind_group(3, &self.settings_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+fn create_shadow_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    voxel_shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Map Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: voxel_shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: voxel_shader,
+            entry_point: Some("fs_shadow"),
+            compilation_options: Default::default(),
+            targets: &[],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Orthographic view-projection looking along `-light_direction` at `focus`
+pub fn light_view_projection(light_direction: [f32; 4], focus: Point3<f32>) -> Matrix4<f32> {
+    let dir = Vector3::new(light_direction[0], light_direction[1], light_direction[2]);
+    let dir = if dir.magnitude2() > 0.0 {
+        dir.normalize()
+    } else {
+        Vector3::unit_y()
+    };
+    // Avoid a degenerate basis when the light points straight up or down
+    let up = if dir.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+
+    let eye = Point3::from_vec(focus.to_vec() + dir * (SHADOW_DEPTH * 0.5));
+    let view = Matrix4::look_at_rh(eye, focus, up);
+    let proj = cgmath::ortho(
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        0.0,
+        SHADOW_DEPTH,
+    );
+    OPENGL_TO_WGPU_MATRIX * proj * view
+}
@@ -0,0 +1,274 @@
+use crate::vertex::{create_vertex_buffer_layout, INDICIES_SQUARE, VERTICES_CUBE};
+use crate::{RenderStats, RgbaImg};
+use std::borrow::Cow;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+/// Cube map loaded with `WgpuCtx::load_skybox`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SkyboxHandle(usize);
+
+/// Draws a cube map behind the scene, covering only pixels the voxel pass left at
+/// the far plane
+pub struct SkyboxPass {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_groups: Vec<wgpu::BindGroup>, // Indexed by `SkyboxHandle`
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl SkyboxPass {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        time_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("skybox.wgsl"))),
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES_CUBE),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Index Buffer"),
+            contents: bytemuck::cast_slice(INDICIES_SQUARE),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Skybox Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Skybox Texture Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[
+                time_bind_group_layout,
+                camera_bind_group_layout,
+                &texture_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(&device, &pipeline_layout, &shader, format, sample_count);
+
+        Self {
+            device,
+            queue,
+            vertex_buffer,
+            index_buffer,
+            sampler,
+            texture_bind_group_layout,
+            texture_bind_groups: Vec::new(),
+            shader,
+            pipeline_layout,
+            format,
+            pipeline,
+        }
+    }
+
+    /// Rebuilds the pipeline to match a scene pass with a new MSAA sample count
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.pipeline = create_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Uploads the six faces in +X, -X, +Y, -Y, +Z, -Z order. Faces must be square and
+    /// of equal size
+    pub fn load(&mut self, faces: &[RgbaImg; 6]) -> Option<SkyboxHandle> {
+        let size = faces[0].width;
+        if faces
+            .iter()
+            .any(|face| face.width != size || face.height != size)
+        {
+            let sizes: Vec<_> = faces.iter().map(|face| (face.width, face.height)).collect();
+            tracing::error!(?sizes, "Skybox faces must be square and of equal size");
+            return None;
+        }
+
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Skybox Texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, face) in faces.iter().enumerate() {
+            self.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &face.bytes,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..extent
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        self.texture_bind_groups
+            .push(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.texture_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+                label: Some("Skybox Texture Bind Group"),
+            }));
+        Some(SkyboxHandle(self.texture_bind_groups.len() - 1))
+    }
+
+    /// Draws into the scene pass, after the voxels so the depth test can find the sky
+    pub fn render(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        time_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        skybox: SkyboxHandle,
+        stats: &mut RenderStats,
+    ) {
+        let Some(texture_bind_group) = self.texture_bind_groups.get(skybox.0) else {
+            return;
+        };
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, time_bind_group, &[]);
+        rpass.set_bind_group(1, camera_bind_group, &[]);
+        rpass.set_bind_group(2, texture_bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        rpass.draw_indexed(0..INDICIES_SQUARE.len() as u32, 0, 0..1);
+        stats.record_draw(INDICIES_SQUARE.len() as u32, 1, self.vertex_buffer.size());
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Skybox Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[create_vertex_buffer_layout()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            // Same attachments as the scene pass
+            targets: &[
+                Some(format.into()),
+                Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::empty(),
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::empty(),
+                }),
+            ],
+        }),
+        // Seen from inside, so both windings must be drawn
+        primitive: wgpu::PrimitiveState {
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,44 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+@group(1) @binding(0) var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0) var cube_texture: texture_cube<f32>;
+@group(2) @binding(1) var cube_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) direction: vec3f,
+};
+
+// Normal depth and velocity are left untouched, the voxel pass already wrote the sky there
+struct FragmentOutput {
+    @location(0) color: vec4f,
+    @location(1) normal_depth: vec4f,
+    @location(2) velocity: vec4f,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3f) -> VertexOutput {
+    var output: VertexOutput;
+    // Centered on the camera so the box never gets closer
+    output.position = camera.view_proj * vec4f(camera.camera_position + position, 1.0);
+    // Pin the depth to the far plane, only pixels nothing else covered pass LessEqual
+    output.position.z = output.position.w;
+    output.direction = position;
+    return output;
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> FragmentOutput {
+    var output: FragmentOutput;
+    output.color = vec4f(textureSampleLevel(cube_texture, cube_sampler, input.direction, 0.0).rgb, 1.0);
+    output.normal_depth = vec4f(0.0);
+    output.velocity = vec4f(0.0);
+    return output;
+}
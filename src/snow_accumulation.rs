@@ -0,0 +1,41 @@
+use crate::VoxelSettings;
+use std::time::Duration;
+
+/// Lowers the snow line of `VoxelSettings` while it snows, so the snow settles further
+/// down the terrain the longer it falls
+#[derive(Debug)]
+pub struct SnowAccumulation {
+    pub snowfall: bool,
+    pub snow_speed: f32,   // World units per second the snow line drops
+    pub start_height: f32, // Snow line when the snowfall (re)starts
+    pub min_height: f32,   // The snow line stops here
+    snow_height: f32,
+}
+
+impl Default for SnowAccumulation {
+    fn default() -> Self {
+        let start_height = VoxelSettings::default().snow_height;
+        Self {
+            snowfall: false,
+            snow_speed: 0.1,
+            start_height,
+            min_height: VoxelSettings::default().water_height,
+            snow_height: start_height,
+        }
+    }
+}
+
+impl SnowAccumulation {
+    pub fn restart(&mut self) {
+        self.snow_height = self.start_height;
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        self.snow_height =
+            (self.snow_height - self.snow_speed * dt.as_secs_f32()).max(self.min_height);
+    }
+
+    pub fn snow_height(&self) -> f32 {
+        self.snow_height
+    }
+}
@@ -0,0 +1,489 @@
+use crate::bloom::{create_compute_pipeline, storage_format_source};
+use crate::render_graph::{RenderNode, RenderTarget};
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Must match `@workgroup_size` of the entry points in ssao.wgsl
+const SSAO_WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SsaoSettings {
+    pub radius: f32,
+    pub bias: f32,
+    pub samples: u32,
+    pub strength: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            bias: 0.025,
+            samples: 16,
+            strength: 1.0,
+        }
+    }
+}
+
+/// Inputs of `SsaoEffect::new`, the camera, the sample noise and the targets the occlusion
+/// is computed from and composited into
+pub struct SsaoParams<'a> {
+    pub camera_buffer: &'a wgpu::Buffer,
+    pub noise_view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub scene_texture_view: &'a wgpu::TextureView,
+    pub normal_depth_view: &'a wgpu::TextureView,
+}
+
+pub struct SsaoEffect {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    noise_view: wgpu::TextureView,
+    settings_buffer: wgpu::Buffer,
+    settings_bind_group: wgpu::BindGroup,
+    layouts: Layouts,
+    ao_view: wgpu::TextureView,
+    blur_view: wgpu::TextureView,
+    output_texture: wgpu::Texture,
+    ao_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup,
+    blur_vertical_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+    ssao_pipeline: wgpu::ComputePipeline,
+    blur_horizontal_pipeline: wgpu::ComputePipeline,
+    blur_vertical_pipeline: wgpu::ComputePipeline,
+    composite_pipeline: wgpu::ComputePipeline,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat, // Of the scene the composite writes
+}
+
+impl SsaoEffect {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        params: SsaoParams,
+        pipeline_cache: Option<&wgpu::PipelineCache>,
+    ) -> Self {
+        let SsaoParams {
+            camera_buffer,
+            noise_view,
+            width,
+            height,
+            format,
+            scene_texture_view,
+            normal_depth_view,
+        } = params;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(storage_format_source(
+                include_str!("ssao.wgsl"),
+                format,
+            )),
+        });
+
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Settings Buffer"),
+            contents: bytemuck::cast_slice(&[SsaoSettings::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Group 0: settings and camera uniforms
+        let settings_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Settings Bind Group Layout"),
+            entries: &[uniform_entry(0), uniform_entry(1)],
+        });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("SSAO Settings Bind Group"),
+        });
+
+        // Group 1: per stage inputs and outputs
+        let ao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Bind Group Layout"),
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(1, true),
+                storage_entry(2, wgpu::TextureFormat::R32Float),
+            ],
+        });
+        let blur_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Blur Bind Group Layout"),
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(3, false),
+                storage_entry(4, wgpu::TextureFormat::R32Float),
+            ],
+        });
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Composite Bind Group Layout"),
+            entries: &[
+                texture_entry(5, false),
+                texture_entry(6, false),
+                storage_entry(7, format),
+            ],
+        });
+
+        let ssao_pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_layout, &ao_layout],
+            &shader,
+            "ssao_main",
+            "SSAO Pipeline",
+            pipeline_cache,
+        );
+        let blur_horizontal_pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_layout, &blur_layout],
+            &shader,
+            "blur_horizontal_main",
+            "SSAO Horizontal Blur Pipeline",
+            pipeline_cache,
+        );
+        let blur_vertical_pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_layout, &blur_layout],
+            &shader,
+            "blur_vertical_main",
+            "SSAO Vertical Blur Pipeline",
+            pipeline_cache,
+        );
+        let composite_pipeline = create_compute_pipeline(
+            &device,
+            &[&settings_layout, &composite_layout],
+            &shader,
+            "composite_main",
+            "SSAO Composite Pipeline",
+            pipeline_cache,
+        );
+
+        let layouts = Layouts {
+            ao: ao_layout,
+            blur: blur_layout,
+            composite: composite_layout,
+        };
+        let targets = Targets::new(&device, width, height, format);
+        let bind_groups = BindGroups::new(
+            &device,
+            &layouts,
+            &targets,
+            &noise_view,
+            scene_texture_view,
+            normal_depth_view,
+        );
+
+        Self {
+            device,
+            queue,
+            noise_view,
+            settings_buffer,
+            settings_bind_group,
+            layouts,
+            ao_view: targets.ao_view,
+            blur_view: targets.blur_view,
+            output_texture: targets.output_texture,
+            ao_bind_group: bind_groups.ao,
+            blur_horizontal_bind_group: bind_groups.blur_horizontal,
+            blur_vertical_bind_group: bind_groups.blur_vertical,
+            composite_bind_group: bind_groups.composite,
+            ssao_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            composite_pipeline,
+            width,
+            height,
+            format,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        scene_texture_view: &wgpu::TextureView,
+        normal_depth_view: &wgpu::TextureView,
+    ) {
+        self.width = width;
+        self.height = height;
+
+        let targets = Targets::new(&self.device, width, height, self.format);
+        let bind_groups = BindGroups::new(
+            &self.device,
+            &self.layouts,
+            &targets,
+            &self.noise_view,
+            scene_texture_view,
+            normal_depth_view,
+        );
+
+        self.ao_view = targets.ao_view;
+        self.blur_view = targets.blur_view;
+        self.output_texture = targets.output_texture;
+        self.ao_bind_group = bind_groups.ao;
+        self.blur_horizontal_bind_group = bind_groups.blur_horizontal;
+        self.blur_vertical_bind_group = bind_groups.blur_vertical;
+        self.composite_bind_group = bind_groups.composite;
+    }
+
+    pub fn update_uniform(&self, settings: SsaoSettings) {
+        self.queue
+            .write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    /// Computes AO for the scene and multiplies it into `scene_texture`
+    pub fn apply(&self, encoder: &mut wgpu::CommandEncoder, scene_texture: &wgpu::Texture) {
+        let dispatch_x = self.width.div_ceil(SSAO_WORKGROUP_SIZE);
+        let dispatch_y = self.height.div_ceil(SSAO_WORKGROUP_SIZE);
+
+        let passes = [
+            (
+                "SSAO Compute Pass",
+                &self.ssao_pipeline,
+                &self.ao_bind_group,
+            ),
+            (
+                "SSAO Horizontal Blur Compute Pass",
+                &self.blur_horizontal_pipeline,
+                &self.blur_horizontal_bind_group,
+            ),
+            (
+                "SSAO Vertical Blur Compute Pass",
+                &self.blur_vertical_pipeline,
+                &self.blur_vertical_bind_group,
+            ),
+            (
+                "SSAO Composite Compute Pass",
+                &self.composite_pipeline,
+                &self.composite_bind_group,
+            ),
+        ];
+        for (label, pipeline, bind_group) in passes {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(label),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(pipeline);
+            cpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            cpass.set_bind_group(1, bind_group, &[]);
+            cpass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
+        }
+
+        // Write the occluded scene back so the rest of the chain is unaffected
+        encoder.copy_texture_to_texture(
+            self.output_texture.as_image_copy(),
+            scene_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+impl RenderNode for SsaoEffect {
+    /// Reads and writes the scene, declared as its single output
+    fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        _inputs: &[RenderTarget],
+        outputs: &[RenderTarget],
+    ) {
+        self.apply(encoder, outputs[0].texture);
+    }
+}
+
+struct Targets {
+    ao_view: wgpu::TextureView,
+    blur_view: wgpu::TextureView,
+    output_texture: wgpu::Texture,
+    output_view: wgpu::TextureView,
+}
+
+impl Targets {
+    fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        // R8Unorm is not a portable storage format, so AO is stored as R32Float
+        let ao_texture = create_texture(
+            device,
+            width,
+            height,
+            wgpu::TextureFormat::R32Float,
+            wgpu::TextureUsages::empty(),
+            "SSAO Texture",
+        );
+        let blur_texture = create_texture(
+            device,
+            width,
+            height,
+            wgpu::TextureFormat::R32Float,
+            wgpu::TextureUsages::empty(),
+            "SSAO Blur Texture",
+        );
+        let output_texture = create_texture(
+            device,
+            width,
+            height,
+            format,
+            wgpu::TextureUsages::COPY_SRC,
+            "SSAO Output Texture",
+        );
+        Self {
+            ao_view: ao_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            blur_view: blur_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            output_view: output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            output_texture,
+        }
+    }
+}
+
+// Group 1 layouts, kept to rebuild the bind groups on resize
+struct Layouts {
+    ao: wgpu::BindGroupLayout,
+    blur: wgpu::BindGroupLayout,
+    composite: wgpu::BindGroupLayout,
+}
+
+struct BindGroups {
+    ao: wgpu::BindGroup,
+    blur_horizontal: wgpu::BindGroup,
+    blur_vertical: wgpu::BindGroup,
+    composite: wgpu::BindGroup,
+}
+
+impl BindGroups {
+    fn new(
+        device: &wgpu::Device,
+        layouts: &Layouts,
+        targets: &Targets,
+        noise_view: &wgpu::TextureView,
+        scene_texture_view: &wgpu::TextureView,
+        normal_depth_view: &wgpu::TextureView,
+    ) -> Self {
+        let view_entry = |binding, view| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+
+        let ao = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.ao,
+            entries: &[
+                view_entry(0, normal_depth_view),
+                view_entry(1, noise_view),
+                view_entry(2, &targets.ao_view),
+            ],
+            label: Some("SSAO Bind Group"),
+        });
+        let blur_horizontal = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.blur,
+            entries: &[
+                view_entry(0, normal_depth_view),
+                view_entry(3, &targets.ao_view),
+                view_entry(4, &targets.blur_view),
+            ],
+            label: Some("SSAO Horizontal Blur Bind Group"),
+        });
+        let blur_vertical = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.blur,
+            entries: &[
+                view_entry(0, normal_depth_view),
+                view_entry(3, &targets.blur_view),
+                view_entry(4, &targets.ao_view),
+            ],
+            label: Some("SSAO Vertical Blur Bind Group"),
+        });
+        let composite = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.composite,
+            entries: &[
+                view_entry(5, scene_texture_view),
+                view_entry(6, &targets.ao_view),
+                view_entry(7, &targets.output_view),
+            ],
+            label: Some("SSAO Composite Bind Group"),
+        });
+
+        Self {
+            ao,
+            blur_horizontal,
+            blur_vertical,
+            composite,
+        }
+    }
+}
+
+fn create_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    extra_usage: wgpu::TextureUsages,
+    label: &str,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | extra_usage,
+        view_formats: &[],
+    })
+}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(binding: u32, filterable: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+    }
+}
+
+fn storage_entry(binding: u32, format: wgpu::TextureFormat) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
@@ -0,0 +1,153 @@
+struct SsaoSettings {
+    radius: f32,
+    bias: f32,
+    samples: u32,
+    strength: f32,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    camera_position: vec3f,
+    _padding: f32,
+};
+
+@group(0) @binding(0) var<uniform> settings: SsaoSettings;
+@group(0) @binding(1) var<uniform> camera: CameraUniform;
+
+// xyz = world normal, w = distance from the camera along the view ray
+@group(1) @binding(0) var normal_depth: texture_2d<f32>;
+@group(1) @binding(1) var noise_texture: texture_2d<f32>;
+@group(1) @binding(2) var ao_output: texture_storage_2d<r32float, write>;
+
+// Bilateral blur
+@group(1) @binding(3) var blur_input: texture_2d<f32>;
+@group(1) @binding(4) var blur_output: texture_storage_2d<r32float, write>;
+
+// Composite
+@group(1) @binding(5) var scene: texture_2d<f32>;
+@group(1) @binding(6) var ao_texture: texture_2d<f32>;
+@group(1) @binding(7) var composite_output: texture_storage_2d<rgba32float, write>;
+
+fn hash31(p: f32) -> vec3f {
+    var p3 = fract(vec3f(p) * vec3f(0.1031, 0.1030, 0.0973));
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.xxy + p3.yzz) * p3.zyx);
+}
+
+struct Ray {
+    origin: vec3f,
+    dir: vec3f,
+};
+
+// Same construction as the voxel pass, also valid for orthographic cameras
+fn view_ray(pixel: vec2<i32>, dims: vec2<u32>) -> Ray {
+    let uv = (vec2f(pixel) + 0.5) / vec2f(dims);
+    let near_pos = camera.inv_view_proj * vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, -1.0, 1.0);
+    let far_pos = camera.inv_view_proj * vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+    let near_point = near_pos.xyz / near_pos.w;
+    let dir = normalize(far_pos.xyz / far_pos.w - near_point);
+    return Ray(near_point - dir * dot(near_point - camera.camera_position, dir), dir);
+}
+
+@compute @workgroup_size(8, 8) // SSAO_WORKGROUP_SIZE in ssao.rs
+fn ssao_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(ao_output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let center = textureLoad(normal_depth, pixel, 0);
+    let n = center.xyz;
+
+    // Sky and other pixels without geometry are never occluded
+    if (dot(n, n) < 0.5) {
+        textureStore(ao_output, pixel, vec4f(1.0));
+        return;
+    }
+
+    let ray = view_ray(pixel, dims);
+    let pos = ray.origin + ray.dir * center.w;
+
+    // Random rotation of the sample kernel around the normal
+    let noise_dims = vec2<i32>(textureDimensions(noise_texture));
+    let noise = textureLoad(noise_texture, pixel % noise_dims, 0).xyz * 2.0 - 1.0;
+    let tangent = normalize(noise - n * dot(noise, n) + vec3f(1e-4, 0.0, 0.0));
+    let bitangent = cross(n, tangent);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < settings.samples; i = i + 1u) {
+        let fi = f32(i);
+        let h = hash31(fi + 1.0);
+        var dir = normalize(vec3f(h.x * 2.0 - 1.0, h.y * 2.0 - 1.0, h.z + 0.05));
+        // Cluster samples closer to the shaded point
+        let scale = fi / f32(settings.samples);
+        dir *= mix(0.1, 1.0, scale * scale);
+        let s = pos + (tangent * dir.x + bitangent * dir.y + n * dir.z) * settings.radius;
+
+        let clip = camera.view_proj * vec4f(s, 1.0);
+        if (clip.w <= 0.0) {
+            continue;
+        }
+        let ndc = clip.xy / clip.w;
+        let suv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(suv < vec2f(0.0)) || any(suv > vec2f(1.0))) {
+            continue;
+        }
+        let spixel = vec2<i32>(suv * vec2f(dims));
+        let scene_depth = textureLoad(normal_depth, spixel, 0).w;
+        let sample_depth = distance(view_ray(spixel, dims).origin, s);
+
+        let range = smoothstep(0.0, 1.0, settings.radius / max(abs(center.w - scene_depth), 1e-4));
+        occlusion += select(0.0, 1.0, scene_depth < sample_depth - settings.bias) * range;
+    }
+
+    let ao = clamp(1.0 - settings.strength * occlusion / f32(max(settings.samples, 1u)), 0.0, 1.0);
+    textureStore(ao_output, pixel, vec4f(ao));
+}
+
+// Separable bilateral blur, weights drop off across depth discontinuities
+fn bilateral_blur(id: vec2<u32>, axis: vec2<i32>) {
+    let dims = textureDimensions(blur_output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id);
+    let center_depth = textureLoad(normal_depth, pixel, 0).w;
+
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -4; i <= 4; i = i + 1) {
+        let coord = clamp(pixel + axis * i, vec2<i32>(0), vec2<i32>(dims) - 1);
+        let depth = textureLoad(normal_depth, coord, 0).w;
+        let spatial = exp(-f32(i * i) / 8.0);
+        let range = exp(-abs(depth - center_depth) / max(center_depth * 0.05, 1e-3));
+        let w = spatial * range;
+        sum += textureLoad(blur_input, coord, 0).r * w;
+        weight_sum += w;
+    }
+    textureStore(blur_output, pixel, vec4f(sum / max(weight_sum, 1e-4)));
+}
+
+@compute @workgroup_size(8, 8) // SSAO_WORKGROUP_SIZE in ssao.rs
+fn blur_horizontal_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    bilateral_blur(id.xy, vec2<i32>(1, 0));
+}
+
+@compute @workgroup_size(8, 8) // SSAO_WORKGROUP_SIZE in ssao.rs
+fn blur_vertical_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    bilateral_blur(id.xy, vec2<i32>(0, 1));
+}
+
+@compute @workgroup_size(8, 8) // SSAO_WORKGROUP_SIZE in ssao.rs
+fn composite_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(composite_output);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+    let pixel = vec2<i32>(id.xy);
+    let color = textureLoad(scene, pixel, 0);
+    let ao = textureLoad(ao_texture, pixel, 0).r;
+    textureStore(composite_output, pixel, vec4f(color.rgb * ao, color.a));
+}
@@ -1,66 +1,164 @@
 use crate::*;
 use cgmath::Rotation3;
-use cgmath::{perspective, InnerSpace, Matrix4, Quaternion, Rad, Vector3, Zero};
-use hecs::World;
+use cgmath::{
+    ortho, perspective, EuclideanSpace, InnerSpace, Matrix4, Quaternion, Rad, Vector3, Zero,
+};
+use hecs::{Entity, World};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
+// Right stick turn rate at full deflection, in radians per second
+const GAMEPAD_LOOK_SPEED: f32 = 2.5;
+// Extra speed multiple with a trigger fully pulled
+const GAMEPAD_BOOST: f32 = 3.0;
+// Cameras per rayon task with the `parallel_systems` feature
+#[cfg(feature = "parallel_systems")]
+const CAMERA_BATCH_SIZE: usize = 8;
+
 pub fn update_camera_system(world: &mut World, input: &Input, dt: Duration) {
-    for (_, (transform, camera, controller)) in
-        world.query_mut::<(&mut Transform, &mut Camera, &mut CameraController)>()
+    let dt = dt.as_secs_f32();
+
+    // Cameras don't read each other, so batches of them can update on the rayon pool.
+    // The component borrows are collected first, they are Send where hecs' query
+    // iterators aren't. Input is only read and plain data, a shared reference is enough
+    #[cfg(feature = "parallel_systems")]
     {
-        let dt = dt.as_secs_f32();
+        use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+        let cameras: Vec<_> = world
+            .query_mut::<(
+                &mut Transform,
+                &Camera,
+                &mut CameraController,
+                Option<&mut CameraAnimation>,
+            )>()
+            .into_iter()
+            .map(|(_, components)| components)
+            .collect();
+        cameras
+            .into_par_iter()
+            .with_min_len(CAMERA_BATCH_SIZE)
+            .for_each(|(transform, camera, controller, animation)| {
+                update_camera(transform, camera, controller, animation, input, dt);
+            });
+    }
 
-        // Update move speed multiplier with scroll
-        controller.move_speed_mult +=
-            (controller.move_speed_mult * input.scroll_delta() as f32 * dt * 5.0) as f32;
+    // Camera is only read for its up vector, keep it a shared borrow
+    #[cfg(not(feature = "parallel_systems"))]
+    for (_, (transform, camera, controller, animation)) in world.query_mut::<(
+        &mut Transform,
+        &Camera,
+        &mut CameraController,
+        Option<&mut CameraAnimation>,
+    )>() {
+        update_camera(transform, camera, controller, animation, input, dt);
+    }
+}
 
-        // Handle rotation using separate pitch and yaw
-        if input.is_mouse_button_down(winit::event::MouseButton::Left) {
-            let mouse_delta = input.mouse_delta();
+fn update_camera(
+    transform: &mut Transform,
+    camera: &Camera,
+    controller: &mut CameraController,
+    animation: Option<&mut CameraAnimation>,
+    input: &Input,
+    dt: f32,
+) {
+    // A playing fly-through overrides the input
+    if let Some(animation) = animation.filter(|animation| animation.playing) {
+        animation.advance(dt);
+        if let Some((position, rotation)) = animation.sample(animation.time) {
+            transform.position = position;
+            transform.rotation = rotation;
+            // Mouse look continues from the path's orientation afterwards
+            let forward = rotation * -Vector3::unit_z();
+            controller.yaw = Rad((-forward.x).atan2(-forward.z));
+            controller.pitch = Rad(forward.y.clamp(-1.0, 1.0).asin());
+        }
+        return;
+    }
 
-            // Update yaw and pitch, with pitch clamping to prevent camera flipping
-            controller.yaw -= Rad(mouse_delta.0 as f32 * controller.look_speed);
-            controller.pitch -= Rad(mouse_delta.1 as f32 * controller.look_speed);
+    // Update move speed multiplier with scroll, each notch scales it by the same factor
+    controller.move_speed_mult *=
+        (1.0 + controller.scroll_sensitivity).powf(input.scroll_delta() as f32);
 
-            // Clamp pitch to prevent camera flipping
-            controller.pitch = controller.pitch;
+    // Handle rotation using separate pitch and yaw, the right stick takes over
+    // while the mouse isn't looking
+    let look = if input.is_mouse_button_down(winit::event::MouseButton::Left) {
+        let mouse_delta = input.mouse_delta();
+        Some((
+            mouse_delta.0 as f32 * controller.look_speed,
+            mouse_delta.1 as f32 * controller.look_speed,
+        ))
+    } else {
+        let stick_x = input.gamepad_axis(GamepadAxis::RightStickX);
+        let stick_y = input.gamepad_axis(GamepadAxis::RightStickY);
+        (stick_x != 0.0 || stick_y != 0.0).then(|| {
+            (
+                stick_x * GAMEPAD_LOOK_SPEED * dt,
+                -stick_y * GAMEPAD_LOOK_SPEED * dt,
+            )
+        })
+    };
+    if let Some((look_x, look_y)) = look {
+        // Update yaw and pitch, with pitch clamping to prevent camera flipping
+        controller.yaw -= Rad(look_x);
+        controller.pitch -= Rad(look_y);
 
-            // Recreate rotation from yaw and pitch
-            transform.rotation = Quaternion::from_axis_angle(Vector3::unit_y(), controller.yaw)
-                * Quaternion::from_axis_angle(Vector3::unit_x(), controller.pitch);
-        }
+        // Clamp pitch to prevent camera flipping
+        controller.pitch = controller.pitch;
 
-        // Calculate movement vectors using current rotation
-        let forward = transform.rotation * -Vector3::unit_z();
-        let right = transform.rotation * Vector3::unit_x();
-        let up = camera.up_vector;
+        // Recreate rotation from yaw and pitch
+        transform.rotation = Quaternion::from_axis_angle(Vector3::unit_y(), controller.yaw)
+            * Quaternion::from_axis_angle(Vector3::unit_x(), controller.pitch);
+    }
 
-        // Handle movement
-        let mut movement = Vector3::zero();
-        if input.is_key_down(winit::keyboard::KeyCode::KeyW) {
-            movement += forward;
-        }
-        if input.is_key_down(winit::keyboard::KeyCode::KeyS) {
-            movement -= forward;
-        }
-        if input.is_key_down(winit::keyboard::KeyCode::KeyA) {
-            movement -= right;
-        }
-        if input.is_key_down(winit::keyboard::KeyCode::KeyD) {
-            movement += right;
-        }
-        if input.is_key_down(winit::keyboard::KeyCode::Space) {
-            movement += up;
-        }
-        if input.is_key_down(winit::keyboard::KeyCode::ShiftLeft) {
-            movement -= up;
-        }
+    // Calculate movement vectors using current rotation
+    let forward = transform.rotation * -Vector3::unit_z();
+    let right = transform.rotation * Vector3::unit_x();
+    let up = camera.up_vector;
+
+    // Handle movement
+    let mut movement = Vector3::zero();
+    if input.is_key_down(winit::keyboard::KeyCode::KeyW) {
+        movement += forward;
+    }
+    if input.is_key_down(winit::keyboard::KeyCode::KeyS) {
+        movement -= forward;
+    }
+    if input.is_key_down(winit::keyboard::KeyCode::KeyA) {
+        movement -= right;
+    }
+    if input.is_key_down(winit::keyboard::KeyCode::KeyD) {
+        movement += right;
+    }
+    if input.is_key_down(winit::keyboard::KeyCode::Space) {
+        movement += up;
+    }
+    if input.is_key_down(controller.descend_key) {
+        movement -= up;
+    }
 
-        // Apply movement
-        if movement != Vector3::zero() {
-            movement =
-                movement.normalize() * controller.move_speed * controller.move_speed_mult * dt;
-            transform.position += movement;
+    // Apply movement
+    if movement != Vector3::zero() {
+        let sprint = if input.is_key_down(controller.sprint_key) {
+            controller.sprint_multiplier
+        } else {
+            1.0
+        };
+        movement =
+            movement.normalize() * controller.move_speed * controller.move_speed_mult * sprint * dt;
+        transform.position += movement;
+    } else {
+        // Left stick moves while the keyboard is idle, keeping its analog magnitude,
+        // either trigger boosts the speed
+        let stick = forward * input.gamepad_axis(GamepadAxis::LeftStickY)
+            + right * input.gamepad_axis(GamepadAxis::LeftStickX);
+        if stick != Vector3::zero() {
+            let trigger = input
+                .gamepad_axis(GamepadAxis::LeftTrigger)
+                .max(input.gamepad_axis(GamepadAxis::RightTrigger));
+            let boost = 1.0 + GAMEPAD_BOOST * trigger;
+            let speed = controller.move_speed * controller.move_speed_mult * boost * dt;
+            transform.position += stick.normalize() * stick.magnitude().min(1.0) * speed;
         }
     }
 }
@@ -76,10 +174,123 @@ pub fn calculate_view_matrix(transform: &Transform) -> Matrix4<f32> {
 
 pub fn calculate_view_projection(transform: &Transform, camera: &Camera) -> Matrix4<f32> {
     let view = calculate_view_matrix(transform);
-    let proj = perspective(camera.fov, camera.aspect, camera.near, camera.far);
+    let proj = match camera.projection {
+        CameraProjection::Perspective { fov } => {
+            perspective(fov, camera.aspect, camera.near, camera.far)
+        }
+        CameraProjection::Orthographic { width, height } => ortho(
+            -width * 0.5,
+            width * 0.5,
+            -height * 0.5,
+            height * 0.5,
+            camera.near,
+            camera.far,
+        ),
+    };
     proj * view
 }
 
 pub fn calculate_view(transform: &Transform) -> Matrix4<f32> {
     calculate_view_matrix(transform)
 }
+
+pub fn calculate_model_matrix(transform: &Transform) -> Matrix4<f32> {
+    Matrix4::from_translation(transform.position.to_vec())
+        * Matrix4::from(transform.rotation)
+        * Matrix4::from_nonuniform_scale(transform.scale.x, transform.scale.y, transform.scale.z)
+}
+
+/// Advances playing animations and uploads the posed joint palettes. The palette
+/// belongs to the model, so all instances of a model share the last processed pose
+pub fn update_animation_system(
+    world: &mut World,
+    models: &mut HashMap<ModelKey, Model>,
+    queue: &wgpu::Queue,
+    dt: Duration,
+) {
+    for (_, (controller, instance)) in
+        world.query_mut::<(&mut AnimationController, &ModelInstance)>()
+    {
+        let Some(model) = models.get_mut(&instance.model) else {
+            continue;
+        };
+        let Some(clip) = model.animations.get(controller.clip_index) else {
+            continue;
+        };
+        if controller.playing && clip.duration > 0.0 {
+            controller.time = (controller.time + dt.as_secs_f32()).rem_euclid(clip.duration);
+        }
+        model.animate(controller.clip_index, controller.time);
+        model.upload_joints(queue);
+    }
+}
+
+/// What `render_model_instances` shares across the models of one scene pass
+pub struct ModelPassContext<'a> {
+    pub frustum: &'a Frustum,
+    pub occluded: &'a HashSet<Entity>, // Hidden by the previous frame's depth
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub stats: &'a mut RenderStats,
+}
+
+/// Draws every visible `ModelInstance` of `model_key` with one instanced draw call per mesh.
+/// Instances use their own `BoundingBox` if they have one, otherwise the model's bounds.
+/// Expects the model pipeline and camera bind group to already be set on `rpass`.
+pub fn render_model_instances(
+    world: &World,
+    model_key: ModelKey,
+    model: &mut Model,
+    rpass: &mut wgpu::RenderPass,
+    ctx: &mut ModelPassContext,
+) {
+    let ModelPassContext {
+        frustum,
+        occluded,
+        device,
+        queue,
+        stats,
+    } = ctx;
+    let instances: Vec<[[f32; 4]; 4]> = world
+        .query::<(&Transform, &ModelInstance, Option<&BoundingBox>)>()
+        .iter()
+        .filter(|(entity, (_, instance, _))| {
+            instance.model == model_key && !occluded.contains(entity)
+        })
+        .filter_map(|(_, (transform, _, bounds))| {
+            let matrix = calculate_model_matrix(transform);
+            let bounds = bounds.unwrap_or(&model.bounding_box).transformed(&matrix);
+            frustum.contains_aabb(&bounds).then(|| matrix.into())
+        })
+        .collect();
+    if instances.is_empty() {
+        return;
+    }
+
+    let size = model.write_instances(device, queue, &instances);
+    let instance_buffer = model.instance_buffer.as_ref().unwrap();
+
+    rpass.set_vertex_buffer(1, instance_buffer.slice(..size));
+    if let Some(joint_bind_group) = &model.joint_bind_group {
+        rpass.set_bind_group(3, joint_bind_group, &[]);
+    }
+    for mesh in &model.meshes {
+        let Some(bind_group) = mesh
+            .material_index
+            .and_then(|index| model.materials.get(index))
+            .and_then(|material| material.bind_group.as_ref())
+        else {
+            continue;
+        };
+        rpass.set_bind_group(2, bind_group, &[]);
+        rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        rpass.set_vertex_buffer(2, mesh.skin_buffer.slice(..));
+        rpass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        rpass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len() as u32);
+        stats.record_draw(
+            mesh.num_elements,
+            instances.len() as u32,
+            mesh.vertex_buffer.size() + mesh.skin_buffer.size(),
+        );
+    }
+}
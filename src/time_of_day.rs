@@ -0,0 +1,62 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::time::Duration;
+
+// Sun color keyframes over the day as (hour, rgb + intensity), wrapping at midnight
+const SUN_COLORS: [(f32, [f32; 4]); 8] = [
+    (0.0, [0.2, 0.25, 0.45, 0.1]),
+    (5.0, [0.2, 0.25, 0.45, 0.1]),
+    (6.5, [1.0, 0.55, 0.2, 1.2]),
+    (9.0, [1.0, 0.9, 0.75, 2.0]),
+    (12.0, [1.0, 1.0, 1.0, 2.2]),
+    (16.0, [1.0, 0.9, 0.75, 2.0]),
+    (18.0, [1.0, 0.3, 0.15, 1.2]),
+    (19.5, [0.2, 0.25, 0.45, 0.1]),
+];
+
+/// 24 hour day/night cycle driving the sun light in `VoxelSettings`
+#[derive(Debug)]
+pub struct TimeOfDay {
+    pub time_of_day: f32, // Hours, 0..24
+    pub speed: f32,       // In game hours per real second
+    pub enabled: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            time_of_day: 9.0,
+            speed: 0.1,
+            enabled: true,
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn advance(&mut self, dt: Duration) {
+        self.time_of_day = (self.time_of_day + self.speed * dt.as_secs_f32()).rem_euclid(24.0);
+    }
+
+    /// Sun rises at 6:00 and is overhead at noon
+    pub fn light_direction(&self) -> [f32; 4] {
+        let angle = self.time_of_day / 24.0 * TAU - FRAC_PI_2;
+        [angle.cos(), angle.sin(), 0.3, 0.0]
+    }
+
+    /// Linearly interpolates the sun color keyframes
+    pub fn light_color(&self) -> [f32; 4] {
+        let t = self.time_of_day.rem_euclid(24.0);
+        let next = SUN_COLORS
+            .iter()
+            .position(|(hour, _)| *hour > t)
+            .unwrap_or(SUN_COLORS.len());
+        let (start_hour, start) = SUN_COLORS[next - 1];
+        let (end_hour, end) = if next < SUN_COLORS.len() {
+            SUN_COLORS[next]
+        } else {
+            (SUN_COLORS[0].0 + 24.0, SUN_COLORS[0].1)
+        };
+
+        let f = (t - start_hour) / (end_hour - start_hour);
+        std::array::from_fn(|i| start[i] + (end[i] - start[i]) * f)
+    }
+}
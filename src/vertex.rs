@@ -4,6 +4,7 @@ pub struct Vertex {
     pub position: [f32; 3],
     pub tex_uv: [f32; 2],
     pub normal: [f32; 3],
+    pub ao: f32, // Baked ambient occlusion, 1 is unoccluded
 }
 
 pub const VERTICES_SQUARE: &[Vertex] = &[
@@ -12,21 +13,25 @@ pub const VERTICES_SQUARE: &[Vertex] = &[
         position: [-0.5, -0.5, 0.0],
         tex_uv: [0.0, 0.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, 0.0],
         tex_uv: [1.0, 0.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, 0.0],
         tex_uv: [1.0, 1.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, 0.0],
         tex_uv: [0.0, 1.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
 ];
 
@@ -44,126 +49,150 @@ pub const VERTICES_CUBE: &[Vertex] = &[
         position: [-0.5, -0.5, 0.5],
         tex_uv: [0.0, 0.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, 0.5],
         tex_uv: [1.0, 0.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, 0.5],
         tex_uv: [1.0, 1.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, 0.5],
         tex_uv: [0.0, 1.0],
         normal: [0.0, 0.0, 1.0],
+        ao: 1.0,
     },
     // Back face
     Vertex {
         position: [-0.5, -0.5, -0.5],
         tex_uv: [1.0, 0.0],
         normal: [0.0, 0.0, -1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, -0.5],
         tex_uv: [1.0, 1.0],
         normal: [0.0, 0.0, -1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, -0.5],
         tex_uv: [0.0, 1.0],
         normal: [0.0, 0.0, -1.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, -0.5],
         tex_uv: [0.0, 0.0],
         normal: [0.0, 0.0, -1.0],
+        ao: 1.0,
     },
     // Left face
     Vertex {
         position: [-0.5, -0.5, -0.5],
         tex_uv: [0.0, 0.0],
         normal: [-1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, -0.5, 0.5],
         tex_uv: [1.0, 0.0],
         normal: [-1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, 0.5],
         tex_uv: [1.0, 1.0],
         normal: [-1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, -0.5],
         tex_uv: [0.0, 1.0],
         normal: [-1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     // Right face
     Vertex {
         position: [0.5, -0.5, 0.5],
         tex_uv: [0.0, 0.0],
         normal: [1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, -0.5],
         tex_uv: [1.0, 0.0],
         normal: [1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, -0.5],
         tex_uv: [1.0, 1.0],
         normal: [1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, 0.5],
         tex_uv: [0.0, 1.0],
         normal: [1.0, 0.0, 0.0],
+        ao: 1.0,
     },
     // Top face
     Vertex {
         position: [-0.5, 0.5, 0.5],
         tex_uv: [0.0, 0.0],
         normal: [0.0, 1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, 0.5],
         tex_uv: [1.0, 0.0],
         normal: [0.0, 1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, 0.5, -0.5],
         tex_uv: [1.0, 1.0],
         normal: [0.0, 1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, 0.5, -0.5],
         tex_uv: [0.0, 1.0],
         normal: [0.0, 1.0, 0.0],
+        ao: 1.0,
     },
     // Bottom face
     Vertex {
         position: [-0.5, -0.5, -0.5],
         tex_uv: [0.0, 0.0],
         normal: [0.0, -1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, -0.5],
         tex_uv: [1.0, 0.0],
         normal: [0.0, -1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [0.5, -0.5, 0.5],
         tex_uv: [1.0, 1.0],
         normal: [0.0, -1.0, 0.0],
+        ao: 1.0,
     },
     Vertex {
         position: [-0.5, -0.5, 0.5],
         tex_uv: [0.0, 1.0],
         normal: [0.0, -1.0, 0.0],
+        ao: 1.0,
     },
 ];
 
@@ -176,6 +205,67 @@ pub const INDICIES_SQUARE: &[u16] = &[
     20, 21, 22, 22, 23, 20, // Bottom
 ];
 
+/// Joint influences of a skinned vertex, kept apart from `Vertex` so the
+/// shared geometry stays unchanged. Unskinned meshes use zero weights
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+/// Joint indices and weights at locations 8 and 9
+pub fn create_skin_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+    use std::mem::size_of;
+    wgpu::VertexBufferLayout {
+        array_stride: size_of::<SkinVertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 8,
+                format: wgpu::VertexFormat::Uint32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<[u32; 4]>() as wgpu::BufferAddress,
+                shader_location: 9,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+/// Per instance model matrix, passed as four vec4 columns at locations 4..=7
+pub fn create_instance_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+    use std::mem::size_of;
+    wgpu::VertexBufferLayout {
+        array_stride: size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                shader_location: 5,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                shader_location: 6,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                shader_location: 7,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
 pub fn create_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
     use std::mem::size_of;
     wgpu::VertexBufferLayout {
@@ -200,6 +290,12 @@ pub fn create_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
                 shader_location: 2,
                 format: wgpu::VertexFormat::Float32x3,
             },
+            wgpu::VertexAttribute {
+                // Ambient occlusion
+                offset: size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32,
+            },
         ],
     }
 }
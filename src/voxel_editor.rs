@@ -0,0 +1,322 @@
+use crate::{
+    calculate_view_projection, Camera, Input, ModifierKey, Transform, VoxelSettings, WgpuCtx,
+};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, SquareMatrix, Vector3, Vector4};
+use std::collections::HashMap;
+use winit::event::MouseButton;
+
+/// Cell states in a `VoxelGrid`, cells without an entry keep the terrain
+pub const VOXEL_EMPTY: u8 = 1;
+pub const VOXEL_SOLID: u8 = 2;
+
+// Cells per side of the GPU edit grid, must be a multiple of 4 and match voxels.wgsl
+pub const VOXEL_EDIT_GRID_SIZE: i32 = 128;
+// Farthest a click can edit, in world units
+const EDIT_RANGE: f32 = 100.0;
+const EDIT_MAX_STEPS: u32 = 256;
+// Half cell steps taken through the surface band looking for the solid cell
+const SURFACE_SEARCH_STEPS: u32 = 8;
+
+/// Voxel edits on top of the terrain, keyed by cell in units of the current voxel size
+#[derive(Default)]
+pub struct VoxelGrid {
+    pub cells: HashMap<Vector3<i32>, u8>,
+}
+
+impl VoxelGrid {
+    pub fn cell_at(position: Point3<f32>, voxel_size: f32) -> Vector3<i32> {
+        Vector3::new(
+            (position.x / voxel_size).floor() as i32,
+            (position.y / voxel_size).floor() as i32,
+            (position.z / voxel_size).floor() as i32,
+        )
+    }
+
+    pub fn cell_center(cell: Vector3<i32>, voxel_size: f32) -> Point3<f32> {
+        Point3::new(
+            (cell.x as f32 + 0.5) * voxel_size,
+            (cell.y as f32 + 0.5) * voxel_size,
+            (cell.z as f32 + 0.5) * voxel_size,
+        )
+    }
+
+    /// Smallest and largest edited cell, None without edits
+    pub fn bounds(&self) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let mut cells = self.cells.keys();
+        let first = *cells.next()?;
+        Some(cells.fold((first, first), |(min, max), c| {
+            (
+                Vector3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+                Vector3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+            )
+        }))
+    }
+
+    /// Applies the edit of the cell holding `position` to a terrain distance, like `map` in voxels.wgsl
+    pub fn apply(&self, distance: f32, position: Point3<f32>, voxel_size: f32) -> f32 {
+        let cell = Self::cell_at(position, voxel_size);
+        let Some(&state) = self.cells.get(&cell) else {
+            return distance;
+        };
+        let cell_distance = box_distance(
+            position - Self::cell_center(cell, voxel_size),
+            voxel_size * 0.5,
+        );
+        match state {
+            VOXEL_SOLID => distance.min(cell_distance),
+            VOXEL_EMPTY => distance.max(-cell_distance),
+            _ => distance,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelEditHeader {
+    origin: [i32; 4],     // First cell of the grid
+    bounds_min: [i32; 4], // Edited cells, min > max while there are none
+    bounds_max: [i32; 4],
+}
+
+const CELLS_OFFSET: wgpu::BufferAddress =
+    std::mem::size_of::<VoxelEditHeader>() as wgpu::BufferAddress;
+
+/// Mirrors a `VoxelGrid` into a storage buffer the voxel shader reads to override
+/// the terrain. Covers `VOXEL_EDIT_GRID_SIZE` cells per side, packed 4 to a u32
+pub struct VoxelEditBuffer {
+    pub buffer: wgpu::Buffer,
+    origin: Vector3<i32>,
+}
+
+impl VoxelEditBuffer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let cells = (VOXEL_EDIT_GRID_SIZE as u64).pow(3);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Voxel Edit Buffer"),
+            size: CELLS_OFFSET + cells,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let edit_buffer = Self {
+            buffer,
+            origin: Vector3::new(1, 1, 1) * -(VOXEL_EDIT_GRID_SIZE / 2),
+        };
+        edit_buffer.write_header(queue, None);
+        edit_buffer
+    }
+
+    fn contains(&self, cell: Vector3<i32>) -> bool {
+        let local = cell - self.origin;
+        [local.x, local.y, local.z]
+            .iter()
+            .all(|v| (0..VOXEL_EDIT_GRID_SIZE).contains(v))
+    }
+
+    fn write_header(&self, queue: &wgpu::Queue, bounds: Option<(Vector3<i32>, Vector3<i32>)>) {
+        let (min, max) = bounds.unwrap_or((Vector3::new(1, 1, 1), Vector3::new(0, 0, 0)));
+        let header = VoxelEditHeader {
+            origin: [self.origin.x, self.origin.y, self.origin.z, 0],
+            bounds_min: [min.x, min.y, min.z, 0],
+            bounds_max: [max.x, max.y, max.z, 0],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[header]));
+    }
+
+    /// Uploads the u32 holding `cell`, false if the cell is outside the grid.
+    /// The grid recenters on the cell when it's the only edit
+    pub fn write_cell(
+        &mut self,
+        queue: &wgpu::Queue,
+        grid: &VoxelGrid,
+        cell: Vector3<i32>,
+    ) -> bool {
+        if !self.contains(cell) {
+            if grid.cells.keys().any(|&other| other != cell) {
+                return false;
+            }
+            self.origin = cell - Vector3::new(1, 1, 1) * (VOXEL_EDIT_GRID_SIZE / 2);
+            let zeros = vec![0u8; (VOXEL_EDIT_GRID_SIZE as usize).pow(3)];
+            queue.write_buffer(&self.buffer, CELLS_OFFSET, &zeros);
+        }
+
+        let local = cell - self.origin;
+        let n = VOXEL_EDIT_GRID_SIZE;
+        let index = ((local.z * n + local.y) * n + (local.x & !3)) as wgpu::BufferAddress;
+        let word: [u8; 4] = std::array::from_fn(|i| {
+            let neighbour = Vector3::new(self.origin.x + (local.x & !3) + i as i32, cell.y, cell.z);
+            grid.cells.get(&neighbour).copied().unwrap_or(0)
+        });
+        queue.write_buffer(&self.buffer, CELLS_OFFSET + index, &word);
+        self.write_header(queue, grid.bounds());
+        true
+    }
+}
+
+/// Right click removes the voxel under the cursor, shift + right click places one
+/// on the face that was clicked. Clicking an edited cell again undoes the edit
+#[derive(Default)]
+pub struct VoxelEditorSystem {
+    pub grid: VoxelGrid,
+}
+
+impl VoxelEditorSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(
+        &mut self,
+        input: &Input,
+        wgpu_ctx: &mut WgpuCtx,
+        transform: &Transform,
+        camera: &Camera,
+        viewport: (u32, u32),
+    ) {
+        if !input.is_mouse_button_just_pressed(MouseButton::Right) {
+            return;
+        }
+        let Some(inv_view_proj) = calculate_view_projection(transform, camera).invert() else {
+            return;
+        };
+
+        // Cursor y grows downward, NDC y upward
+        let [x, y] = input.mouse_position_normalized(viewport.0, viewport.1);
+        let unproject = |z: f32| {
+            let p = inv_view_proj * Vector4::new(x, -y, z, 1.0);
+            Point3::from_vec(p.truncate() / p.w)
+        };
+        let near = unproject(-1.0);
+        let direction = (unproject(1.0) - near).normalize();
+
+        let voxel_size = wgpu_ctx.voxel_size();
+        let Some((hit, front)) = self.raycast(wgpu_ctx, near, direction, voxel_size) else {
+            return;
+        };
+
+        let (cell, state) = if input.is_modifier_down(ModifierKey::Shift) {
+            (front, VOXEL_SOLID)
+        } else {
+            (hit, VOXEL_EMPTY)
+        };
+        let previous = self.grid.cells.get(&cell).copied();
+        match previous {
+            Some(_) => self.grid.cells.remove(&cell),
+            None => self.grid.cells.insert(cell, state),
+        };
+
+        if !wgpu_ctx.write_voxel_edit(&self.grid, cell) {
+            match previous {
+                Some(previous) => self.grid.cells.insert(cell, previous),
+                None => self.grid.cells.remove(&cell),
+            };
+            tracing::warn!(?cell, "Voxel edit is outside the edit grid");
+        }
+    }
+
+    /// Marches the ray until the edited terrain is within a voxel, then walks through
+    /// the surface band for the first solid cell. Returns it and the cell in front of it
+    fn raycast(
+        &self,
+        wgpu_ctx: &WgpuCtx,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        voxel_size: f32,
+    ) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let distance =
+            |p: Point3<f32>| self.grid.apply(wgpu_ctx.terrain_distance(p), p, voxel_size);
+        let bounds = self.grid.bounds();
+
+        let mut t = 0.0;
+        for _ in 0..EDIT_MAX_STEPS {
+            let p = origin + direction * t;
+            let d = distance(p);
+            if d.abs() < voxel_size {
+                let start = (t - voxel_size).max(0.0);
+                let mut front =
+                    VoxelGrid::cell_at(origin + direction * (start - voxel_size * 0.5), voxel_size);
+                for i in 0..SURFACE_SEARCH_STEPS {
+                    let cell = VoxelGrid::cell_at(
+                        origin + direction * (start + i as f32 * voxel_size * 0.5),
+                        voxel_size,
+                    );
+                    if distance(VoxelGrid::cell_center(cell, voxel_size)) < 0.0 {
+                        return Some((cell, front));
+                    }
+                    front = cell;
+                }
+            }
+
+            // Edited cells aren't part of the terrain distance, cross them a cell at a time
+            let step = match bounds {
+                Some((min, max)) => {
+                    let center = VoxelGrid::cell_center(min, voxel_size)
+                        .midpoint(VoxelGrid::cell_center(max, voxel_size));
+                    let half_size = (max - min).cast::<f32>().unwrap() * (voxel_size * 0.5)
+                        + Vector3::new(1.0, 1.0, 1.0) * (voxel_size * 0.5);
+                    let to_bounds = box_distance_3(p - center, half_size);
+                    d.abs().min(to_bounds.max(voxel_size))
+                }
+                None => d.abs(),
+            };
+            t += step.max(voxel_size * 0.5);
+            if t > EDIT_RANGE {
+                break;
+            }
+        }
+        None
+    }
+}
+
+/// The terrain part of `map` in voxels.wgsl evaluated on the CPU, `noise` is the
+/// 32x32x32 volume sampled like the repeating, linearly filtered terrain sampler.
+/// The ProcGenTerrain blend only exists on the GPU and is not included
+pub fn analytic_terrain_distance(noise: &[u8], settings: &VoxelSettings, p: Point3<f32>) -> f32 {
+    const SC: f32 = 0.3;
+    let q = p.to_vec() * (SC / 32.0) - Vector3::new(0.003, -0.006, 0.0);
+    let mut d = sample_noise(noise, q) * 0.5;
+    d += sample_noise(noise, q * 2.0 + Vector3::new(0.3, 0.3, 0.3)) * 0.25;
+    d += sample_noise(noise, q * 4.0 + Vector3::new(0.7, 0.7, 0.7)) * 0.125;
+    d = (d / 0.875 - settings.surface_factor) / SC;
+    if settings.erosion_enabled != 0 {
+        let layers = settings.erosion_layers as f32 / settings.max_height;
+        d += (p.y * layers).rem_euclid(1.0) * settings.erosion_hardness;
+    }
+    smax(d, p.y - settings.max_height, 0.6)
+}
+
+fn sample_noise(noise: &[u8], uvw: Vector3<f32>) -> f32 {
+    const SIZE: i32 = 32;
+    let texel = |x: i32, y: i32, z: i32| {
+        let index = (z.rem_euclid(SIZE) * SIZE + y.rem_euclid(SIZE)) * SIZE + x.rem_euclid(SIZE);
+        noise[index as usize] as f32 / 255.0
+    };
+    // Texel centers sit at half coordinates
+    let p = uvw * SIZE as f32 - Vector3::new(0.5, 0.5, 0.5);
+    let base = Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let f = p - base;
+    let (x, y, z) = (base.x as i32, base.y as i32, base.z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let plane = |z: i32| {
+        lerp(
+            lerp(texel(x, y, z), texel(x + 1, y, z), f.x),
+            lerp(texel(x, y + 1, z), texel(x + 1, y + 1, z), f.x),
+            f.y,
+        )
+    };
+    lerp(plane(z), plane(z + 1), f.z)
+}
+
+fn smax(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = (0.5 - 0.5 * (d2 - d1) / k).clamp(0.0, 1.0);
+    d2 + (d1 - d2) * h + k * h * (1.0 - h)
+}
+
+fn box_distance(p: Vector3<f32>, half_size: f32) -> f32 {
+    box_distance_3(p, Vector3::new(half_size, half_size, half_size))
+}
+
+fn box_distance_3(p: Vector3<f32>, half_size: Vector3<f32>) -> f32 {
+    let q = Vector3::new(p.x.abs(), p.y.abs(), p.z.abs()) - half_size;
+    let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+    outside.magnitude() + q.x.max(q.y).max(q.z).min(0.0)
+}
@@ -24,29 +24,113 @@ struct VoxelSettings {
     show_normals: i32,
     show_steps: i32,
     visualize_distance_field: i32,
+    show_shadows: i32,
+    
+    ambient_color: vec4f,
+    ambient_strength: f32,
+
+    sun_size: f32,
+    sun_bloom_strength: f32,
+    procgen_blend: f32,
+    sky_horizon_color: vec4f,
+    sky_zenith_color: vec4f,
+
+    erosion_enabled: i32,
+    erosion_layers: i32,
+    erosion_hardness: f32,
+
+    cave_darkness: f32,
+    cave_ambient: vec4f,
+
+    snow_height: f32,
+    snow_blend_range: f32,
+    rainbow_mode: i32,
+    rainbow_period: f32,
+    snow_color: vec4f,
+
+    sss_color: vec4f,
+    sss_strength: f32,
+    sss_radius: f32,
+
+    detail_map_scale: f32,
+    detail_map_strength: f32,
 
-    _padding: i32,
-    // _padding2: i32,
+    gbuffer_debug: i32,
+
+    fog_density: f32,
+    _padding0: f32,
+    _padding1: f32,
+    fog_color: vec4f,
+
+    procedural_sky: i32,
+    turbidity: f32,
+    sky_exposure: f32,
+    transparent_background: i32,
+};
+
+struct ShadowUniform {
+    light_view_proj: mat4x4<f32>,
+    inv_light_view_proj: mat4x4<f32>,
+    map_size: f32,
+    bias: f32,
 };
 
 const PI = 3.141592;
+const PROCGEN_EXTENT: f32 = 64.0;
+// Must match voxel_editor.rs
+const VOXEL_EDIT_GRID_SIZE: i32 = 128;
+const VOXEL_EMPTY: u32 = 1u;
+const VOXEL_SOLID: u32 = 2u;
 
 // Bindings
-@group(0) @binding(0) var<uniform> camera: CameraUniform;
-@group(1) @binding(0) var noise0_texture: texture_2d<f32>; // iChannel0
-@group(1) @binding(1) var noise1_texture: texture_3d<f32>; // iChannel1
-@group(1) @binding(2) var grain_texture: texture_2d<f32>;  // iChannel2
-@group(1) @binding(3) var dirt_texture: texture_2d<f32>;   // iChannel3
-@group(1) @binding(4) var terrain_sampler: sampler; // Must use repeat mode
-@group(2) @binding(0) var<uniform> settings: VoxelSettings;
+@group(0) @binding(0) var<uniform> time: TimeUniform;
+@group(1) @binding(0) var<uniform> camera: CameraUniform;
+@group(2) @binding(0) var noise0_texture: texture_2d<f32>; // iChannel0
+@group(2) @binding(1) var noise1_texture: texture_3d<f32>; // iChannel1
+@group(2) @binding(2) var grain_texture: texture_2d<f32>;  // iChannel2
+@group(2) @binding(3) var dirt_texture: texture_2d<f32>;   // iChannel3
+@group(2) @binding(4) var terrain_sampler: sampler; // Must use repeat mode
+@group(2) @binding(5) var procgen_sdf: texture_3d<f32>; // ProcGenTerrain, in volume units
+@group(2) @binding(6) var<storage, read> voxel_edits: VoxelEdits;
+@group(2) @binding(7) var chunk_atlas: texture_3d<f32>; // ChunkAtlas, in chunk units
+@group(2) @binding(8) var<storage, read> voxel_chunks: VoxelChunks;
+@group(3) @binding(0) var<uniform> settings: VoxelSettings;
+@group(3) @binding(1) var shadow_map: texture_depth_2d;
+@group(3) @binding(2) var shadow_sampler: sampler_comparison;
+@group(3) @binding(3) var<uniform> shadow: ShadowUniform;
 
 // Structures
+// Cells of voxel_size edited with the VoxelEditorSystem, 4 cells per u32 with x fastest
+struct VoxelEdits {
+    origin: vec4<i32>,
+    bounds_min: vec4<i32>, // min > max while there are no edits
+    bounds_max: vec4<i32>,
+    cells: array<u32>,
+};
+
+// Slots of the chunk atlas, a chunk lives in the slot at its position modulo `slots`
+struct VoxelChunks {
+    slots: i32,      // Per side of the atlas
+    resolution: i32, // Voxels per slot side
+    chunk_size: f32,
+    _padding: f32,
+    entries: array<vec4<i32>>, // Chunk position and voxels per side, 0 voxels while empty
+};
+
 struct CameraUniform {
     view_proj: mat4x4<f32>,
     inv_view_proj: mat4x4<f32>,
     view: mat4x4<f32>,
     camera_position: vec3f,
-    time: f32,
+    _padding: f32,
+    prev_view_proj: mat4x4<f32>, // Last frame's view_proj, for velocities
+};
+
+struct TimeUniform {
+    elapsed: f32, // Seconds since startup
+    delta: f32,   // Seconds since the last frame
+    frame: u32,
+    _padding: u32,
 };
 
 struct VertexInput {
@@ -62,6 +146,11 @@ struct VertexOutput {
     @location(2) world_position: vec3f,
 };
 
+struct CameraRay {
+    ro: vec3f,
+    rd: vec3f,
+};
+
 struct HitInfo {
     is_hit: bool,
     t: f32,
@@ -81,6 +170,11 @@ fn smax(d1: f32, d2: f32, k: f32) -> f32 {
     return mix(d2, d1, h) + k * h * (1.0 - h);
 }
 
+fn sd_box(p: vec3f, b: vec3f) -> f32 {
+    let q = abs(p) - b;
+    return length(max(q, vec3f(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
 fn hash13(p: vec3f) -> f32 {
     var p3 = fract(p * 0.1031);
     p3 += dot(p3, p3.yzx + 33.33);
@@ -125,8 +219,19 @@ fn map(p: vec3f) -> f32 {
     var tp = smoothstep(50.0, -6.0, p.y);
     tp = tp * tp;
     d = (d/0.875 - settings.surface_factor) / sc;
+    d += erosion(p.y);
     d = smax(d, p.y - settings.max_height, 0.6);
+
+    if (settings.procgen_blend > 0.0) {
+        // The volume spans PROCGEN_EXTENT around the origin, clamped instead of repeating.
+        // Streamed chunks replace it wherever they're loaded
+        let uv = clamp(p / PROCGEN_EXTENT + vec3f(0.5), vec3f(0.0), vec3f(1.0));
+        let sampled = textureSample(procgen_sdf, terrain_sampler, uv).r * PROCGEN_EXTENT;
+        d = mix(d, chunk_distance(p, sampled), settings.procgen_blend);
+    }
     
+    d = apply_voxel_edits(p, d);
+
     // let camera_pos = camera.camera_position;
     // let camera_distance = length(p - camera_pos);
     
@@ -136,6 +241,67 @@ fn map(p: vec3f) -> f32 {
     return d;
 }
 
+// Distance to the streamed chunk terrain, `fallback` where p's chunk isn't loaded
+fn chunk_distance(p: vec3f, fallback: f32) -> f32 {
+    let local = p / voxel_chunks.chunk_size;
+    let chunk = vec3<i32>(floor(local));
+    let n = voxel_chunks.slots;
+    let slot = ((chunk % n) + n) % n;
+    let entry = voxel_chunks.entries[(slot.z * n + slot.y) * n + slot.x];
+    if (entry.w == 0 || any(entry.xyz != chunk)) {
+        return fallback;
+    }
+    // Texel position in the slot, kept half a texel inside so filtering stays in the chunk
+    let size = f32(entry.w);
+    let texel = clamp((local - vec3f(chunk)) * size, vec3f(0.5), vec3f(size - 0.5));
+    let uv = (vec3f(slot * voxel_chunks.resolution) + texel) / f32(n * voxel_chunks.resolution);
+    return textureSampleLevel(chunk_atlas, terrain_sampler, uv, 0.0).r * voxel_chunks.chunk_size;
+}
+
+// Sawtooth on the distance, each rock layer is cut back further towards its top edge
+fn erosion(y: f32) -> f32 {
+    if settings.erosion_enabled == 0 {
+        return 0.0;
+    }
+    let layers = f32(settings.erosion_layers) / settings.max_height;
+    return fract(y * layers) * settings.erosion_hardness;
+}
+
+fn apply_voxel_edits(p: vec3f, terrain: f32) -> f32 {
+    if voxel_edits.bounds_min.x > voxel_edits.bounds_max.x {
+        return terrain;
+    }
+    let s = settings.voxel_size;
+    var d = terrain;
+    let cell = vec3<i32>(floor(p / s));
+    let local = cell - voxel_edits.origin.xyz;
+    if all(local >= vec3<i32>(0)) && all(local < vec3<i32>(VOXEL_EDIT_GRID_SIZE)) {
+        let n = u32(VOXEL_EDIT_GRID_SIZE);
+        let index = (u32(local.z) * n + u32(local.y)) * n + u32(local.x);
+        let state = (voxel_edits.cells[index / 4u] >> ((index % 4u) * 8u)) & 0xffu;
+        let cell_d = sd_box(p - (vec3f(cell) + 0.5) * s, vec3f(s * 0.5));
+        if state == VOXEL_SOLID {
+            d = min(d, cell_d);
+        } else if state == VOXEL_EMPTY {
+            d = max(d, -cell_d);
+        }
+    }
+    return d;
+}
+
+// Placed voxels aren't part of the distance further out, so the trace crosses
+// the edited region at most a cell at a time
+fn voxel_edit_step(p: vec3f, d: f32) -> f32 {
+    if voxel_edits.bounds_min.x > voxel_edits.bounds_max.x {
+        return d;
+    }
+    let s = settings.voxel_size;
+    let lo = vec3f(voxel_edits.bounds_min.xyz) * s;
+    let hi = vec3f(voxel_edits.bounds_max.xyz + vec3<i32>(1)) * s;
+    let to_bounds = sd_box(p - (lo + hi) * 0.5, (hi - lo) * 0.5);
+    return min(d, max(to_bounds, s));
+}
+
 fn grad(p: vec3f) -> vec3f {
     let e = vec2f(0.0, 0.1);
     return (map(p) - vec3f(
@@ -162,7 +328,7 @@ fn trace(ro: vec3f, rd: vec3f, tmax: f32) -> HitInfo {
     var prd = vec3f(0.0);
     for (var i = 0; i < settings.steps; i = i + 1) {
         let pos = ro + rd * t;
-        let d = map(select(pos, vpos, voxel));
+        let d = voxel_edit_step(pos, map(select(pos, vpos, voxel)));
         if !voxel {
             t += d;
             if d < sd {
@@ -202,6 +368,25 @@ fn trace(ro: vec3f, rd: vec3f, tmax: f32) -> HitInfo {
     return HitInfo(false, tmax, vec3f(0.0), vec3f(0.0), settings.steps);
 }
 
+// Percentage closer filtered lookup into the light's shadow map, 1.0 = fully lit
+fn shadow_factor(p: vec3f) -> f32 {
+    let lp = shadow.light_view_proj * vec4f(p, 1.0);
+    let ndc = lp.xyz / lp.w;
+    let uv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let texel = 1.0 / shadow.map_size;
+    var sum = 0.0;
+    for (var x = -1; x <= 1; x = x + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            let offset = vec2f(f32(x), f32(y)) * texel;
+            sum += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z - shadow.bias);
+        }
+    }
+    return sum / 9.0;
+}
+
 fn triplanar(p: vec3f, n: vec3f, k: f32, tex_index: i32) -> vec3f {
     let n_pow = pow(abs(n), vec3f(k));
     let n_norm = n_pow / dot(n_pow, vec3f(1.0));
@@ -248,27 +433,101 @@ fn getAlbedo(vpos: vec3f, gn: vec3f, lod: f32) -> vec3f {
     dcol = mix(dcol, vec3f(0.8, 0.65, 0.4), biome.x);
     dcol = mix(dcol, vec3f(0.2, 0.6, 0.8), biome.y);
     alb = mix(alb, alb * dcol, (1.0 - wk) * mix(1.0 - smoothstep(0.3, 0.25, k), 1.0, max(biome.x, biome.y)));
+    // Finer grain on top, centered on 1 so it adds detail without shifting the tone
+    let detail_lod = lod + log2(max(settings.detail_map_scale, 1.0));
+    let detail = triplanarLod(vpos * 0.08 * settings.detail_map_scale, gn, 4.0, 2, detail_lod).r;
+    alb = mix(alb, alb * detail * 2.0, settings.detail_map_strength);
     return alb;
 }
 
+// Indirect light from the sky, keeps surfaces facing away from the sun from going black
+fn ambient_light() -> vec3f {
+    return settings.ambient_color.rgb * settings.ambient_strength;
+}
+
+// 0 in the open, towards 1 the more rock there is straight above `p`
+fn cave_factor(p: vec3f) -> f32 {
+    var solid = 0.0;
+    for (var i = 1; i <= 3; i++) {
+        let h = f32(i * i) * 2.0;
+        solid += smoothstep(0.0, -1.0, map(p + vec3f(0.0, h, 0.0)));
+    }
+    return solid / 3.0;
+}
+
+// Replaces the surface lighting of `col` with the cave ambient inside the rock
+fn apply_cave_lighting(col: vec3f, albedo: vec3f, pos: vec3f) -> vec3f {
+    if settings.cave_darkness <= 0.0 {
+        return col;
+    }
+    let cave = cave_factor(pos) * settings.cave_darkness;
+    return mix(col, albedo * settings.cave_ambient.rgb, cave);
+}
+
+// cos(30°), steeper surfaces don't hold snow
+const SNOW_MIN_UP: f32 = 0.866;
+
+// Blends `albedo` toward the snow color on upward facing surfaces above the snow line
+fn apply_snow(albedo: vec3f, n: vec3f, pos: vec3f) -> vec3f {
+    let upward = smoothstep(SNOW_MIN_UP, SNOW_MIN_UP + 0.05, n.y);
+    let height = smoothstep(settings.snow_height - settings.snow_blend_range, settings.snow_height, pos.y);
+    return mix(albedo, settings.snow_color.rgb, upward * height);
+}
+
+// The light color, or in rainbow mode a fully saturated hue at its brightness that
+// goes around the spectrum once per rainbow_period. A hue rotation alone would barely
+// tint the near white sun
+fn light_color() -> vec3f {
+    let base = settings.light_color.rgb;
+    if settings.rainbow_mode == 0 {
+        return base;
+    }
+    let hue = fract(time.elapsed / settings.rainbow_period);
+    let spectrum = saturate(abs(fract(hue + vec3f(0.0, 2.0, 1.0) / 3.0) * 6.0 - 3.0) - 1.0);
+    return spectrum * max(base.r, max(base.g, base.b));
+}
+
+// Steps of the thickness ray, spread over four subsurface radii
+const SSS_STEPS: i32 = 8;
+
+// Light transmitted through the rock behind a surface facing away from the sun. The
+// thickness is how far a ray toward the light travels before it leaves the rock
+fn subsurface_light(albedo: vec3f, n: vec3f, pos: vec3f, ldir: vec3f) -> vec3f {
+    let backlit = saturate(-dot(n, ldir));
+    if settings.sss_strength <= 0.0 || backlit <= 0.0 {
+        return vec3f(0.0);
+    }
+    let step_size = settings.sss_radius * 4.0 / f32(SSS_STEPS);
+    var thickness = step_size * f32(SSS_STEPS);
+    for (var i = 1; i <= SSS_STEPS; i++) {
+        let t = step_size * f32(i);
+        if map(pos + ldir * t) > 0.0 {
+            thickness = t;
+            break;
+        }
+    }
+    let transmission = exp(-thickness / settings.sss_radius) * settings.sss_strength * backlit;
+    return albedo * settings.sss_color.rgb * light_color() * transmission;
+}
+
 fn shade(pos: vec3f, ldir: vec3f, lod: f32, hit: HitInfo) -> vec3f {
     let vpos = hit.id;
     let g = grad(vpos);
     let gn = g / length(g);
     let n = hit.n;
     var dif = max(dot(n, ldir), 0.0);
-    if dif > 0.0 {
-        let hitL = trace(pos + n * 1e-3, ldir, 12.0);
-        if hitL.is_hit { dif = 0.0; }
+    if dif > 0.0 && settings.show_shadows != 0 {
+        dif *= shadow_factor(pos + n * 1e-3);
     }
-    var col = getAlbedo(vpos, gn, lod);
+    let albedo = apply_snow(getAlbedo(vpos, gn, lod), gn, pos);
+    var col = albedo;
     let ao = smoothstep(-0.08, 0.04, map(pos) / length(grad(pos)));
     let hao = smoothstep(settings.water_height - 12.0, settings.water_height, pos.y);
     col *= dot(abs(n), vec3f(0.8, 1.0, 0.9));
     // Fixed: Added missing * operators
-    col *= (dif * 0.6 + 0.4) * settings.light_color.rgb;
+    col *= (dif * 0.6 + 0.4) * light_color() + ambient_light();
     col *= (ao * 0.6 + 0.4) * (hao * 0.6 + 0.4);
-    return col;
+    return apply_cave_lighting(col, albedo, pos) + subsurface_light(albedo, n, pos, ldir);
 }
 
 fn shade2(pos: vec3f, ldir: vec3f, lod: f32, hit: HitInfo) -> vec3f {
@@ -278,25 +537,80 @@ fn shade2(pos: vec3f, ldir: vec3f, lod: f32, hit: HitInfo) -> vec3f {
     let n = hit.n;
     let dif = max(dot(n, ldir), 0.0);
     
-    var col = getAlbedo(vpos, gn, lod);
+    let albedo = apply_snow(getAlbedo(vpos, gn, lod), gn, pos);
+    var col = albedo;
     let ao = smoothstep(-0.08, 0.04, map(pos) / length(grad(pos)));
     let hao = smoothstep(settings.water_height - 12.0, settings.water_height, pos.y);
     
     col *= dot(abs(n), vec3f(0.8, 1.0, 0.9));
-    col *= (dif * 0.6 + 0.4) * settings.light_color.rgb;
+    col *= (dif * 0.6 + 0.4) * light_color() + ambient_light();
     col *= ao * 0.6 + 0.4;
     col *= hao * 0.6 + 0.4;
     
-    return col;
+    return apply_cave_lighting(col, albedo, pos) + subsurface_light(albedo, n, pos, ldir);
+}
+
+// Perez et al. distribution of one sky quantity over the view zenith angle theta and the
+// angle gamma to the sun, with coefficients a and b in ab and c, d and e in cde
+fn perez(cos_theta: f32, gamma: f32, ab: vec2f, cde: vec3f) -> f32 {
+    let cos_gamma = cos(gamma);
+    return (1.0 + ab.x * exp(ab.y / max(cos_theta, 0.01)))
+        * (1.0 + cde.x * exp(cde.y * gamma) + cde.z * cos_gamma * cos_gamma);
+}
+
+// Preetham, Shirley and Smits 1999, "A Practical Analytic Model for Daylight". Luminance
+// and chromaticity come from the fit to the turbidity, then xyY goes to linear sRGB
+fn preethamSky(rd: vec3f, sun: vec3f) -> vec3f {
+    let t = settings.turbidity;
+    // Sun below the horizon is held on it, the model isn't fitted for night skies
+    let theta_s = acos(clamp(sun.y, 0.0, 1.0));
+    let cos_theta = max(rd.y, 0.0);
+    let gamma = acos(clamp(dot(rd, sun), -1.0, 1.0));
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let zenith_Y = (4.0453 * t - 4.9710) * tan(chi) - 0.2155 * t + 2.4192;
+    let th = vec3f(theta_s * theta_s * theta_s, theta_s * theta_s, theta_s);
+    let zenith_x = t * t * dot(vec3f(0.00166, -0.00375, 0.00209), th)
+        + t * (dot(vec3f(-0.02903, 0.06377, -0.03202), th) + 0.00394)
+        + dot(vec3f(0.11693, -0.21196, 0.06052), th) + 0.25886;
+    let zenith_y = t * t * dot(vec3f(0.00275, -0.00610, 0.00317), th)
+        + t * (dot(vec3f(-0.04214, 0.08970, -0.04153), th) + 0.00516)
+        + dot(vec3f(0.15346, -0.26756, 0.06670), th) + 0.26688;
+
+    let Y_ab = vec2f(0.1787 * t - 1.4630, -0.3554 * t + 0.4275);
+    let Y_cde = vec3f(-0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703);
+    let x_ab = vec2f(-0.0193 * t - 0.2592, -0.0665 * t + 0.0008);
+    let x_cde = vec3f(-0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452);
+    let y_ab = vec2f(-0.0167 * t - 0.2608, -0.0950 * t + 0.0092);
+    let y_cde = vec3f(-0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529);
+
+    // Relative to the zenith, whose values the fit gives directly
+    let Y = zenith_Y * perez(cos_theta, gamma, Y_ab, Y_cde) / perez(1.0, theta_s, Y_ab, Y_cde);
+    let x = zenith_x * perez(cos_theta, gamma, x_ab, x_cde) / perez(1.0, theta_s, x_ab, x_cde);
+    let y = zenith_y * perez(cos_theta, gamma, y_ab, y_cde) / perez(1.0, theta_s, y_ab, y_cde);
+
+    // Kilocandela per square meter, roughly 0.1 brings a clear noon sky near the gradient's
+    let luminance = max(Y, 0.0) * 0.1 * settings.sky_exposure;
+    let xyz = vec3f(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+    let rgb = vec3f(
+        dot(vec3f(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3f(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3f(0.0557, -0.2040, 1.0570), xyz),
+    );
+    return max(rgb, vec3f(0.0));
 }
 
 fn getSky(rd: vec3f) -> vec3f {
-    let skyCol = vec3f(0.353, 0.611, 1.0);
-    let skyCol2 = vec3f(0.8, 0.9, 1.0);
-    var col = mix(skyCol2, skyCol, smoothstep(0.0, 0.2, rd.y)) * 1.2;
-    let sunCost = cos(0.52 * PI / 180.0);
-    let cost = max(dot(rd, settings.light_direction.xyz), 0.0);
+    var col = mix(settings.sky_horizon_color.rgb, settings.sky_zenith_color.rgb, smoothstep(0.0, 0.2, rd.y)) * 1.2;
+    if (settings.procedural_sky != 0) {
+        col = preethamSky(rd, normalize(settings.light_direction.xyz));
+    }
+    let sunCost = cos(settings.sun_size * PI / 180.0);
+    let cost = max(dot(rd, normalize(settings.light_direction.xyz)), 0.0);
     let dist = cost - sunCost;
+    let disc = step(0.0, dist);
+    let glow = pow(cost, 256.0) * settings.sun_bloom_strength;
+    col += light_color() * (disc + glow);
     return col;
 }
 
@@ -323,17 +637,53 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     return output;
 }
 
+struct FragmentOutput {
+    @location(0) color: vec4f,
+    // xyz = world normal, w = distance along the view ray, read by the SSAO pass
+    @location(1) normal_depth: vec4f,
+    // xy = NDC position minus last frame's, read by the motion blur pass
+    @location(2) velocity: vec4f,
+    // Depth of the traced hit, so rasterized models are occluded by the terrain
+    @builtin(frag_depth) depth: f32,
+};
+
+// Screen space motion of a world point since last frame, the camera is the only thing moving
+fn ndc_velocity(p: vec3f) -> vec2f {
+    let current = camera.view_proj * vec4f(p, 1.0);
+    let previous = camera.prev_view_proj * vec4f(p, 1.0);
+    return current.xy / current.w - previous.xy / previous.w;
+}
+
+// The camera projection uses OpenGL depth (-1..1), remapped here to wgpu's 0..1
+fn view_depth(p: vec3f) -> f32 {
+    let clip = camera.view_proj * vec4f(p, 1.0);
+    return clamp(clip.z / clip.w * 0.5 + 0.5, 0.0, 1.0);
+}
+
+// Unproject the near and far plane points so orthographic projections get
+// parallel rays, the origin is moved back onto the camera plane
+fn camera_ray(tex_uv: vec2f) -> CameraRay {
+    let near_pos = camera.inv_view_proj * vec4f(tex_uv * 2.0 - 1.0, -1.0, 1.0);
+    let far_pos = camera.inv_view_proj * vec4f(tex_uv * 2.0 - 1.0, 1.0, 1.0);
+    let near_point = near_pos.xyz / near_pos.w;
+    let rd = normalize(far_pos.xyz / far_pos.w - near_point);
+    return CameraRay(near_point - rd * dot(near_point - camera.camera_position, rd), rd);
+}
+
 @fragment
-fn fs_main(input: VertexOutput) -> @location(0) vec4f {
-    let ro = camera.camera_position;
-    let ndc = vec4f(input.tex_uv * 2.0 - 1.0, 1.0, 1.0);
-    let world_pos = camera.inv_view_proj * ndc;
-    let rd = normalize(world_pos.xyz / world_pos.w - ro);
+fn fs_main(input: VertexOutput) -> FragmentOutput {
+    var output: FragmentOutput;
+    let ray = camera_ray(input.tex_uv);
+    let ro = ray.ro;
+    let rd = ray.rd;
     
     if settings.visualize_distance_field != 0 {
         let pos = ro + rd * 10.0;
         let d = map(pos);
-        return vec4f(vec3f(d * 0.1 + 0.5), 1.0);
+        output.color = vec4f(vec3f(d * 0.1 + 0.5), 1.0);
+        output.normal_depth = vec4f(0.0, 0.0, 0.0, settings.max_dist);
+        output.depth = 1.0;
+        return output;
     }
     
     let hit = trace(ro, rd, settings.max_dist);
@@ -344,9 +694,11 @@ fn fs_main(input: VertexOutput) -> @location(0) vec4f {
         let pos = ro + rd * hit.t;
         let lod = clamp(log2(distance(ro, hit.id)) - 2.0, 0.0, 6.0);
         col = shade(pos, settings.light_direction.xyz, lod, hit);
+        output.normal_depth = vec4f(hit.n, hit.t);
     } else {
         col = getSky(rd);
         t = settings.max_dist;
+        output.normal_depth = vec4f(0.0, 0.0, 0.0, settings.max_dist);
     }
     
     let pt = -(ro.y - settings.water_height) / rd.y;
@@ -372,7 +724,7 @@ fn fs_main(input: VertexOutput) -> @location(0) vec4f {
         
         let e = 0.001;
         let wnstr = 1500.0;
-        let wo = vec2f(1.0, 0.8) * camera.time * 0.01;
+        let wo = vec2f(1.0, 0.8) * time.elapsed * 0.01;
         let wuv = wpos.xz * 0.08 + wo;
         let wh = textureSample(grain_texture, terrain_sampler, wuv).r;
         let whdx = textureSample(grain_texture, terrain_sampler, wuv + vec2f(e, 0.0)).r;
@@ -413,12 +765,19 @@ fn fs_main(input: VertexOutput) -> @location(0) vec4f {
             // Foam effect
             let wp = wpos + wn * vec3f(1.0, 0.0, 1.0) * 0.2;
             let wd = map(wp) / length(grad(wp));
-            let foam = sin((wd - camera.time * 0.03) * 60.0);
+            let foam = sin((wd - time.elapsed * 0.03) * 60.0);
             let foam_mask = smoothstep(0.22, 0.0, wd + foam * 0.03 + (wh - 0.5) * 0.12);
             col = mix(col, col + vec3f(1.0), foam_mask * 0.4);
+            output.normal_depth = vec4f(0.0, 1.0, 0.0, pt);
         }
     }
     
+    // Distance haze, normal_depth.w is the terrain or water distance
+    if hit.is_hit || output.normal_depth.w < settings.max_dist {
+        let fog = exp(-settings.fog_density * output.normal_depth.w);
+        col = mix(settings.fog_color.rgb, col, fog);
+    }
+    
     // let cost = max(dot(rd, settings.light_direction.xyz), 0.0);
     // col += 0.12 * settings.light_color.rgb * pow(cost, 6.0);
     
@@ -430,5 +789,58 @@ fn fs_main(input: VertexOutput) -> @location(0) vec4f {
         col = vec3f(f32(hit.i) / f32(settings.steps));
     }
     
-    return vec4f(col, 1.0);
-}
\ No newline at end of file
+    // Nothing but sky in front of the camera
+    let background = !hit.is_hit && output.normal_depth.w >= settings.max_dist;
+    if settings.transparent_background != 0 && background {
+        output.color = vec4f(0.0);
+    } else {
+        output.color = vec4f(col, 1.0);
+    }
+    output.depth = view_depth(ro + rd * output.normal_depth.w);
+    output.velocity = vec4f(ndc_velocity(ro + rd * output.normal_depth.w), 0.0, 0.0);
+    return output;
+}
+
+// w = 1 where the terrain was hit, 0 for the sky
+struct GBufferOutput {
+    @location(0) albedo: vec4f,
+    @location(1) normal: vec4f,
+    @location(2) position: vec4f,
+};
+
+// G-buffer pass: the terrain's unlit surface, water and models aren't included
+@fragment
+fn fs_gbuffer(input: VertexOutput) -> GBufferOutput {
+    var output: GBufferOutput;
+    let ray = camera_ray(input.tex_uv);
+    let hit = trace(ray.ro, ray.rd, settings.max_dist);
+    if hit.is_hit {
+        let pos = ray.ro + ray.rd * hit.t;
+        let lod = clamp(log2(distance(ray.ro, hit.id)) - 2.0, 0.0, 6.0);
+        let g = grad(hit.id);
+        let gn = g / length(g);
+        output.albedo = vec4f(apply_snow(getAlbedo(hit.id, gn, lod), gn, pos), 1.0);
+        output.normal = vec4f(hit.n, 1.0);
+        output.position = vec4f(pos, 1.0);
+    }
+    return output;
+}
+
+// Shadow pass: march the terrain from the light's orthographic near plane and write its depth
+@fragment
+fn fs_shadow(@builtin(position) frag_coord: vec4f) -> @builtin(frag_depth) f32 {
+    let uv = frag_coord.xy / shadow.map_size;
+    let ndc = vec2f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let near = shadow.inv_light_view_proj * vec4f(ndc, 0.0, 1.0);
+    let far = shadow.inv_light_view_proj * vec4f(ndc, 1.0, 1.0);
+    let ro = near.xyz / near.w;
+    let re = far.xyz / far.w;
+    let rd = normalize(re - ro);
+
+    let hit = trace(ro, rd, length(re - ro));
+    if !hit.is_hit {
+        return 1.0;
+    }
+    let lp = shadow.light_view_proj * vec4f(ro + rd * hit.t, 1.0);
+    return clamp(lp.z / lp.w, 0.0, 1.0);
+}
@@ -0,0 +1,39 @@
+use crate::VoxelSettings;
+use std::time::Duration;
+
+/// Bobs the water level of `VoxelSettings` up and down with a sine wave
+#[derive(Debug)]
+pub struct WaterSurface {
+    pub amplitude: f32,    // World units above and below the base height
+    pub frequency: f32,    // Radians per second
+    pub phase_offset: f32, // Radians
+    pub paused: bool,
+    pub base_water_height: f32, // The level the wave is centered on
+    elapsed: f32,               // Seconds, only advances while not paused
+}
+
+impl Default for WaterSurface {
+    fn default() -> Self {
+        Self {
+            amplitude: 0.1,
+            frequency: 0.5,
+            phase_offset: 0.0,
+            paused: false,
+            base_water_height: VoxelSettings::default().water_height,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl WaterSurface {
+    pub fn advance(&mut self, dt: Duration) {
+        if !self.paused {
+            self.elapsed += dt.as_secs_f32();
+        }
+    }
+
+    pub fn water_height(&self) -> f32 {
+        self.base_water_height
+            + self.amplitude * (self.frequency * self.elapsed + self.phase_offset).sin()
+    }
+}
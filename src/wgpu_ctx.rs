@@ -1,12 +1,34 @@
-use crate::vertex::{create_vertex_buffer_layout, INDICES_SQUARE, VERTICES_SQUARE};
+use crate::app::UiState;
+use crate::render_graph::{RenderGraph, RenderNode, RenderTarget};
+use crate::vertex::{
+    create_instance_vertex_buffer_layout, create_skin_vertex_buffer_layout,
+    create_vertex_buffer_layout, INDICES_SQUARE, VERTICES_SQUARE,
+};
+use crate::with_validation;
 use crate::{
-    BloomEffect, ColorCorrectionEffect, ColorCorrectionUniform, Model, ModelInstance, RgbaImg,
-    Transform,
+    analytic_terrain_distance, calculate_model_matrix, load_texture, render_model_instances,
+    texture_bytes, update_animation_system, AnimationController, AutoExposureEffect,
+    AutoExposureSettings, BloomEffect, BloomParams, BloomSettings, BoundingBox, Camera,
+    CameraAnimation, CameraController, CameraProjection, ChromaticAberrationEffect, ChunkAtlas,
+    ColorCorrectionEffect, ColorCorrectionUniform, CullBounds, DebugDraw, DepthOfFieldEffect,
+    DepthOfFieldParams, DofSettings, FilmGrainEffect, FogParams, FogSettings, FrameAllocator,
+    Frustum, FxaaEffect, GBufferDebug, GBufferPass, IndirectCullPass, LensFlareEffect,
+    LensFlareParams, LensFlareSettings, Model, ModelInstance, ModelKey, ModelLoadError,
+    ModelPassContext, MotionBlurEffect, MotionBlurParams, MotionBlurSettings, MsaaTargets,
+    OcclusionCullPass, PerfStats, ProcGenTerrain, RenderStats, RgbaImg, ShaderWatcher,
+    ShadowMapPass, Skybox, SkyboxHandle, SkyboxPass, SsaoEffect, SsaoParams, SsaoSettings,
+    Transform, VolumetricFogEffect, VoxelEditBuffer, VoxelGrid, GBUFFER_DEBUG_MODES,
 };
-use cgmath::{Matrix4, SquareMatrix};
-use hecs::World;
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3, Vector4};
+use hecs::{Entity, World};
 use std::borrow::Cow;
-use std::{path::Path, sync::Arc, time::Instant};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::{MemoryHints, SamplerDescriptor, ShaderSource};
 use winit::window::Window;
@@ -15,8 +37,121 @@ use imgui::*;
 use imgui_wgpu::{Renderer, RendererConfig};
 use imgui_winit_support::WinitPlatform;
 
+/// Adapter and surface options chosen at startup
+#[derive(Debug, Copy, Clone)]
+pub struct WgpuCtxConfig {
+    pub power_preference: wgpu::PowerPreference,
+    pub present_mode: wgpu::PresentMode,
+    pub antialiasing: AntialiasingMode,
+    pub render_texture_format: RenderTextureFormat,
+    // PreMultiplied or PostMultiplied leave the sky transparent on compositing window
+    // managers, the window has to be created transparent too
+    pub alpha_mode: wgpu::CompositeAlphaMode,
+}
+
+impl WgpuCtxConfig {
+    pub fn transparent(&self) -> bool {
+        is_transparent(self.alpha_mode)
+    }
+}
+
+impl Default for WgpuCtxConfig {
+    fn default() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            present_mode: wgpu::PresentMode::Fifo,
+            antialiasing: AntialiasingMode::None,
+            render_texture_format: RenderTextureFormat::F32,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        }
+    }
+}
+
+/// Precision of the HDR scene, normal/depth, bloom and post-processing textures
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderTextureFormat {
+    F32, // Rgba32Float, falls back to F16 where it can't be rendered to or filtered
+    F16, // Rgba16Float, half the memory
+}
+
+impl RenderTextureFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::F32 => wgpu::TextureFormat::Rgba32Float,
+            Self::F16 => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+/// How edges of the scene are smoothed, only one mode is active at a time
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AntialiasingMode {
+    None,
+    Msaa4x, // Falls back to None where the adapter can't resolve 4x multisampled targets
+    Fxaa,   // Post-process filter, also blurs text and UI elements drawn into the scene
+}
+
+impl AntialiasingMode {
+    pub fn msaa_samples(self) -> u32 {
+        match self {
+            Self::Msaa4x => 4,
+            Self::None | Self::Fxaa => 1,
+        }
+    }
+}
+
+// Antialiasing modes selectable in the settings panel
+const ANTIALIASING_MODES: [AntialiasingMode; 3] = [
+    AntialiasingMode::None,
+    AntialiasingMode::Msaa4x,
+    AntialiasingMode::Fxaa,
+];
+
+/// FXAA runs as its own node of the post-processing graph, present only while it's
+/// the antialiasing mode
+fn set_fxaa_node(render_graph: &mut RenderGraph, antialiasing: AntialiasingMode) {
+    render_graph.remove_node("fxaa");
+    if antialiasing == AntialiasingMode::Fxaa {
+        render_graph.add_node("fxaa", &["post_process"], &["post_process"]);
+    }
+}
+
+// Present modes selectable in the settings panel
+const PRESENT_MODES: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+// Mip levels of the bloom blur chain, each is one texture binding of the composite
+const BLOOM_MAX_LEVEL: u32 = 8;
+
+// World units visible vertically when switching the camera to orthographic
+const ORTHOGRAPHIC_HEIGHT: f32 = 20.0;
+
+// 32x32x32 R8 noise volume after a 20 byte header
+const NOISE1_PATH: &str = "./assets/images/textures/graynoise_32x32x32_cube.bin";
+
+// Cube map face file names in the directory of a `Skybox`, in layer order
+const SKYBOX_FACES: [&str; 6] = ["px.png", "nx.png", "py.png", "ny.png", "pz.png", "nz.png"];
+
+// GPU culled model draws need both, per instance draws start at their instance
+const MULTI_DRAW_INDIRECT_FEATURES: wgpu::Features =
+    wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+const INDIRECT_ARGS_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+
+// World matrix and bounds override of each visible instance, grouped by model
+type ModelInstances = HashMap<ModelKey, Vec<(Matrix4<f32>, Option<BoundingBox>)>>;
+
+// Shaders are embedded with include_str!, this path is only used to watch them for hot reload
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(
+    Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize,
+)]
+#[serde(default)]
 pub struct VoxelSettings {
     // Constants from shader
     pub max: f32,
@@ -38,20 +173,74 @@ pub struct VoxelSettings {
 
     // Light settings
     pub light_color: [f32; 4],     // Using vec4 for alignment
-    pub light_direction: [f32; 4], // Using vec4 for alignment
+    pub light_direction: [f32; 4], // Unit length, write through `set_light_direction`
 
     // Debug flags (using i32 as bools for uniform compatibility)
     pub show_normals: i32,
     pub show_steps: i32,
     pub visualize_distance_field: i32,
-
-    _padding: u32,
-    // // Padding to ensure 16-byte alignment
-    // _padding: [u8; 8],
-    // _padding: u32,
-    // _padding2: u8
+    pub show_shadows: i32,
+
+    // Ambient light settings
+    pub ambient_color: [f32; 4], // Using vec4 for alignment
+    pub ambient_strength: f32,
+
+    // Sky settings
+    pub sun_size: f32,           // Angular radius of the sun disc in degrees
+    pub sun_bloom_strength: f32, // Glow around the sun disc
+    pub procgen_blend: f32,      // 0 is the analytic terrain, 1 the ProcGenTerrain volume
+    pub sky_horizon_color: [f32; 4],
+    pub sky_zenith_color: [f32; 4],
+
+    // Horizontal rock bands, each layer adds a sawtooth step to the terrain
+    pub erosion_enabled: i32,
+    pub erosion_layers: i32,   // Bands between the origin and max_height
+    pub erosion_hardness: f32, // Distance step at each band edge
+
+    // Interior lighting, blended in by how much rock is above a surface
+    pub cave_darkness: f32, // 0 lights caves like the surface, 1 only with cave_ambient
+    pub cave_ambient: [f32; 4],
+
+    // Snow on upward facing surfaces above the snow line
+    pub snow_height: f32, // Snow line, set by `SnowAccumulation` while it snows
+    pub snow_blend_range: f32, // Distance below the snow line over which snow fades out
+
+    // Sweeps the light color through the spectrum, a demo mode
+    pub rainbow_mode: i32,
+    pub rainbow_period: f32, // Seconds for one trip through the spectrum
+    pub snow_color: [f32; 4],
+
+    // Light passing through thin rock, seen on the side facing away from the sun
+    pub sss_color: [f32; 4],
+    pub sss_strength: f32,
+    pub sss_radius: f32, // Rock thickness over which the transmitted light falls to 1/e
+
+    // High frequency grain over the terrain albedo
+    pub detail_map_scale: f32, // Tiling of the grain texture relative to the base albedo
+    pub detail_map_strength: f32,
+
+    pub gbuffer_debug: i32, // A `GBufferDebug` index, shown instead of the lit scene
+
+    // Exponential haze over terrain and water by hit distance, the sky is left as is
+    pub fog_density: f32,
+    #[serde(skip)]
+    _padding: [f32; 2],
+    pub fog_color: [f32; 4],
+
+    // Preetham daylight model in place of the horizon and zenith gradient, lit by the
+    // light direction
+    pub procedural_sky: i32,
+    pub turbidity: f32, // Haze, 2 for a clear sky up to 10 for a hazy one
+    pub sky_exposure: f32,
+    // Set from the surface alpha mode, sky pixels are written with an alpha of 0. Not
+    // saved with scenes
+    #[serde(skip)]
+    transparent_background: i32,
 }
 
+// Must match the WGSL struct layout, vec4s are 16 byte aligned
+const _: () = assert!(std::mem::size_of::<VoxelSettings>() == 320);
+
 impl Default for VoxelSettings {
     fn default() -> Self {
         // Calculate voxel_size based on voxel_level for consistency
@@ -78,24 +267,136 @@ impl Default for VoxelSettings {
 
             // Light settings - converted to arrays for uniform compatibility
             light_color: [1.0, 0.9, 0.75, 2.0], // vec3f(1.0, 0.9, 0.75) * 2.0
-            light_direction: [0.507746, 0.716817, 0.477878, 0.0], // Normalized in shader
+            light_direction: [0.507746, 0.716817, 0.477878, 0.0],
 
             // Debug flags
             show_normals: 0,             // false
             show_steps: 0,               // false
             visualize_distance_field: 0, // false
-
-            // _padding: [0; 8],
-            _padding: 0,
+            show_shadows: 1,             // true
+
+            // Soft blue-grey sky ambient
+            ambient_color: [0.2, 0.3, 0.5, 1.0],
+            ambient_strength: 0.1,
+
+            sun_size: 0.52,
+            sun_bloom_strength: 0.5,
+            procgen_blend: 0.0,
+            sky_horizon_color: [0.8, 0.9, 1.0, 1.0],
+            sky_zenith_color: [0.353, 0.611, 1.0, 1.0],
+
+            erosion_enabled: 0,
+            erosion_layers: 8,
+            erosion_hardness: 0.3,
+
+            cave_darkness: 0.0,
+            cave_ambient: [0.05, 0.05, 0.07, 1.0],
+            snow_height: 10.0, // Above the terrain, no snow until it falls
+            snow_blend_range: 1.0,
+            rainbow_mode: 0,
+            rainbow_period: 5.0,
+            snow_color: [0.9, 0.92, 0.95, 1.0],
+            sss_color: [1.0, 0.45, 0.3, 1.0],
+            sss_strength: 0.0,
+            sss_radius: 0.3,
+            detail_map_scale: 5.0,
+            detail_map_strength: 0.4,
+            gbuffer_debug: 0,
+            fog_density: 0.001,
+            _padding: [0.0; 2],
+            fog_color: [0.6, 0.7, 0.8, 1.0],
+            procedural_sky: 0,
+            turbidity: 2.5,
+            sky_exposure: 1.0,
+            transparent_background: 0,
         }
     }
 }
 
+// Beyond level 10 voxels get too small for the ray marcher's precision
+const VOXEL_LEVEL_RANGE: std::ops::RangeInclusive<i32> = 1..=10;
+
 impl VoxelSettings {
     pub fn update_voxel_size(&mut self) {
+        self.voxel_level = self
+            .voxel_level
+            .clamp(*VOXEL_LEVEL_RANGE.start(), *VOXEL_LEVEL_RANGE.end());
         self.voxel_size = 2.0f32.powf(-self.voxel_level as f32);
     }
 
+    /// Clamps every field to a range the shaders can handle. Call after building
+    /// settings from outside data and after every edit
+    pub fn validate(&mut self) {
+        self.update_voxel_size();
+        self.steps = self.steps.max(1);
+        self.eps = self.eps.max(f32::MIN_POSITIVE);
+        self.min_dist = self.min_dist.max(0.0);
+        self.max_dist = self.max_dist.max(self.min_dist);
+        for flag in [
+            &mut self.show_normals,
+            &mut self.show_steps,
+            &mut self.visualize_distance_field,
+            &mut self.show_shadows,
+            &mut self.erosion_enabled,
+            &mut self.rainbow_mode,
+            &mut self.procedural_sky,
+        ] {
+            *flag = (*flag != 0) as i32;
+        }
+        self.ambient_strength = self.ambient_strength.clamp(0.0, 1.0);
+        self.sun_size = self.sun_size.clamp(0.0, 90.0);
+        self.sun_bloom_strength = self.sun_bloom_strength.max(0.0);
+        self.procgen_blend = self.procgen_blend.clamp(0.0, 1.0);
+        self.erosion_layers = self.erosion_layers.max(1);
+        self.erosion_hardness = self.erosion_hardness.max(0.0);
+        self.cave_darkness = self.cave_darkness.clamp(0.0, 1.0);
+        // smoothstep needs distinct edges
+        self.snow_blend_range = self.snow_blend_range.max(0.01);
+        self.rainbow_period = self.rainbow_period.max(0.1);
+        self.sss_strength = self.sss_strength.max(0.0);
+        self.sss_radius = self.sss_radius.max(0.01);
+        self.detail_map_scale = self.detail_map_scale.max(0.01);
+        self.detail_map_strength = self.detail_map_strength.clamp(0.0, 1.0);
+        self.fog_density = self.fog_density.max(0.0);
+        // The fitted coefficients only hold over this range
+        self.turbidity = self.turbidity.clamp(2.0, 10.0);
+        self.sky_exposure = self.sky_exposure.max(0.0);
+        self.gbuffer_debug = self
+            .gbuffer_debug
+            .clamp(0, GBUFFER_DEBUG_MODES.len() as i32 - 1);
+        let [x, y, z, _] = self.light_direction;
+        self.set_light_direction([x, y, z]);
+    }
+
+    /// Stores `direction` normalized, a zero vector points the light straight down
+    pub fn set_light_direction(&mut self, direction: [f32; 3]) {
+        let direction = Vector3::from(direction);
+        let direction = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            Vector3::unit_y()
+        };
+        self.light_direction = [direction.x, direction.y, direction.z, 0.0];
+    }
+
+    /// Azimuth turns around the Y axis starting at +X, elevation is above the horizon
+    pub fn set_light_direction_from_angles(&mut self, azimuth_deg: f32, elevation_deg: f32) {
+        let (azimuth, elevation) = (azimuth_deg.to_radians(), elevation_deg.to_radians());
+        self.set_light_direction([
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        ]);
+    }
+
+    /// Inverse of `set_light_direction_from_angles`, azimuth in 0..360
+    pub fn light_angles(&self) -> (f32, f32) {
+        let [x, y, z, _] = self.light_direction;
+        let azimuth = z.atan2(x).to_degrees().rem_euclid(360.0);
+        let elevation = y.clamp(-1.0, 1.0).asin().to_degrees();
+        (azimuth, elevation)
+    }
+
     // Create buffer from settings
     pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -116,23 +417,72 @@ pub struct ImguiState {
     pub last_cursor: Option<MouseCursor>,
 }
 
+/// Mirrored by `CameraUniform` in the shaders. WGSL aligns a vec3 to 16 bytes, so
+/// `position` is followed by exactly one scalar, `_padding`, filling its vec4 slot. A new
+/// scalar can take its place, anything bigger has to go after `prev_view_proj`, and
+/// every WGSL copy must be updated with it
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
     view_proj: [[f32; 4]; 4],
     inv_view_proj: [[f32; 4]; 4],
     view: [[f32; 4]; 4],
-    position: [f32; 3],
-    time: f32,
+    position: [f32; 3], // vec3f, padded to 16 bytes by `_padding`
+    _padding: f32,
+    prev_view_proj: [[f32; 4]; 4],
+}
+
+// Four mat4x4s plus the position vec4
+const _: () = assert!(std::mem::size_of::<CameraUniform>() == 272);
+const _: () = assert!(std::mem::offset_of!(CameraUniform, _padding) % 16 == 12);
+
+/// Bound at group 0 of every scene pipeline, mirrored by `TimeUniform` in the shaders
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TimeUniform {
+    pub elapsed: f32, // Seconds since startup
+    pub delta: f32,   // Seconds since the previous frame
+    pub frame: u32,
+    _padding: u32,
+}
+
+/// Commands `first..first + count` of the indirect draw buffer, one per instance of a mesh
+struct IndirectBatch {
+    model: ModelKey,
+    mesh: usize,
+    first: u32,
+    count: u32,
 }
 
 pub struct WgpuCtx<'window> {
+    window: Arc<Window>, // Also owned by the surface, kept for imgui
     surface: wgpu::Surface<'window>,
     surface_config: wgpu::SurfaceConfiguration,
     adapter: wgpu::Adapter,
     device: Arc<wgpu::Device>,
     queue: Arc<wgpu::Queue>,
+    pipeline_cache: Option<wgpu::PipelineCache>,
+    pipeline_cache_path: Option<PathBuf>,
     render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    voxel_shader: wgpu::ShaderModule, // Last successfully (re)loaded
+    model_pipeline: wgpu::RenderPipeline,
+    model_pipeline_layout: wgpu::PipelineLayout,
+    model_shader: wgpu::ShaderModule,
+    multi_draw_indirect: bool, // Models are culled on the GPU and drawn indirectly
+    antialiasing: AntialiasingMode,
+    msaa_samples: u32,
+    render_format: wgpu::TextureFormat, // Of the scene, normal/depth and post-processing textures
+    msaa_targets: Option<MsaaTargets>,  // Scene pass attachments when msaa_samples > 1
+    indirect_cull_pass: IndirectCullPass,
+    indirect_batches: Vec<IndirectBatch>,
+    indirect_bounds: Vec<CullBounds>, // Parallel to the commands of the indirect draw buffer
+    indirect_buffer: Option<wgpu::Buffer>, // Grown to the largest frame's commands
+    occlusion_cull_pass: OcclusionCullPass,
+    frustum: Frustum,
+    camera_position: Point3<f32>,
+    view: Matrix4<f32>, // Of the last camera update, unprojects read back depth
+    inv_view_proj: Matrix4<f32>, // Of the last camera update, unprojects read back depth
     vertex_buffer: wgpu::Buffer,
     vertex_index_buffer: wgpu::Buffer,
     texture: wgpu::Texture,
@@ -144,46 +494,97 @@ pub struct WgpuCtx<'window> {
     camera_bind_group: wgpu::BindGroup,
     depth_texture: wgpu::Texture,
     depth_texture_view: wgpu::TextureView,
-    models: Vec<Model>,
+    models: HashMap<ModelKey, Model>,
+    next_model_key: u64,
     texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    joint_bind_group_layout: wgpu::BindGroupLayout,
     render_texture: wgpu::Texture,
     render_texture_view: wgpu::TextureView,
+    normal_depth_texture: wgpu::Texture,
+    normal_depth_texture_view: wgpu::TextureView,
+    ssao_effect: SsaoEffect,
+    ssao_settings: SsaoSettings,
+    fog_effect: VolumetricFogEffect,
+    fog_settings: FogSettings,
+    dof_effect: DepthOfFieldEffect,
+    dof_settings: DofSettings,
+    lens_flare_effect: LensFlareEffect,
+    lens_flare_settings: LensFlareSettings,
+    velocity_texture: wgpu::Texture,
+    velocity_texture_view: wgpu::TextureView,
+    prev_view_proj: Option<Matrix4<f32>>, // None until the first camera update
+    motion_blur_effect: MotionBlurEffect,
+    motion_blur_settings: MotionBlurSettings,
     bloom_effect: BloomEffect,
+    bloom_settings: BloomSettings,
+    render_graph: RenderGraph,
     post_process_texture: wgpu::Texture,
     post_process_texture_view: wgpu::TextureView,
+    auto_exposure_effect: AutoExposureEffect,
+    auto_exposure_settings: AutoExposureSettings,
+    chromatic_aberration_effect: ChromaticAberrationEffect,
+    fxaa_effect: FxaaEffect,
     color_correction_effect: ColorCorrectionEffect,
+    color_correction_settings: ColorCorrectionUniform,
+    film_grain_effect: FilmGrainEffect,
     noise0_texture: wgpu::Texture,
     noise1_texture: wgpu::Texture,
     grain_texture: wgpu::Texture,
     dirt_texture: wgpu::Texture,
+    procgen_texture: wgpu::Texture,
     terrain_bind_group_layout: wgpu::BindGroupLayout,
     terrain_bind_group: wgpu::BindGroup,
+    noise1_data: Vec<u8>, // CPU copy for picking against the terrain
+    voxel_edit_buffer: VoxelEditBuffer,
+    chunk_atlas: ChunkAtlas, // Streamed chunk volumes sampled by the voxel shader
     time: Instant,
+    time_uniform: TimeUniform, // Last written to time_buffer
+    time_buffer: wgpu::Buffer,
+    time_bind_group: wgpu::BindGroup,
     hidpi_factor: f64,
     pub imgui: ImguiState,
+    pub debug_draw: DebugDraw,
+    pub perf_stats: PerfStats,
+    render_stats: RenderStats, // Of the frame being drawn until its overlay is built
+    frame_allocator: FrameAllocator, // Per frame uniform writes, flushed at the start of draw
+    pub show_perf_hud: bool,
+    pub show_render_stats: bool,
+    render_scale: f32, // Of the scene and post-processing targets relative to the surface
+    pub voxel_chunks: HashMap<Vector3<i32>, hecs::Entity>, // Streamed chunk entities by chunk coordinate
+    skybox_pass: SkyboxPass,
+    skyboxes: HashMap<String, Option<SkyboxHandle>>, // Failed loads are kept to not retry every frame
     voxel_settings: VoxelSettings,
     voxel_settings_buffer: wgpu::Buffer,
     voxel_settings_bind_group: wgpu::BindGroup,
+    shadow_map_pass: ShadowMapPass,
+    gbuffer_pass: GBufferPass,
+    gbuffer_debug_bound: GBufferDebug, // Channel color correction currently reads
+    voxel_shader_watcher: ShaderWatcher,
+    bloom_shader_watcher: ShaderWatcher,
+    color_correction_shader_watcher: ShaderWatcher,
 }
 
 impl<'window> WgpuCtx<'window> {
     /// Creates a depth texture and its view for depth testing
     fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        width: u32,
+        height: u32,
     ) -> (wgpu::Texture, wgpu::TextureView) {
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Depth Texture"),
             size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
 
@@ -192,25 +593,105 @@ impl<'window> WgpuCtx<'window> {
         (depth_texture, depth_texture_view)
     }
 
+    /// Creates the scene normal/depth target read by the SSAO pass, in the render format
+    fn create_normal_depth_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let normal_depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Normal Depth Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let normal_depth_texture_view =
+            normal_depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (normal_depth_texture, normal_depth_texture_view)
+    }
+
+    /// Creates the per pixel screen space velocity target read by the motion blur pass
+    fn create_velocity_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let velocity_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Velocity Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let velocity_texture_view =
+            velocity_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        (velocity_texture, velocity_texture_view)
+    }
+
     /// Asynchronous constructor for WgpuCtx
-    pub async fn new_async(window: Arc<Window>) -> WgpuCtx<'window> {
-        // Core WGPU setup
-        let instance = wgpu::Instance::default();
+    pub async fn new_async(window: Arc<Window>, config: WgpuCtxConfig) -> WgpuCtx<'window> {
+        // Core WGPU setup, WGPU_BACKEND picks the backend when set
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
         let surface = instance.create_surface(Arc::clone(&window)).unwrap();
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
+                power_preference: config.power_preference,
                 force_fallback_adapter: false,
                 compatible_surface: Some(&surface),
             })
             .await
             .expect("Failed to find an appropriate adapter");
+        let render_format = check_render_texture_support(&adapter, config.render_texture_format);
+
+        let msaa_samples = Self::supported_msaa_samples(
+            &adapter,
+            render_format,
+            config.antialiasing.msaa_samples(),
+        );
+        let antialiasing = if msaa_samples < config.antialiasing.msaa_samples() {
+            AntialiasingMode::None
+        } else {
+            config.antialiasing
+        };
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::FLOAT32_FILTERABLE,
+                    // Pipeline caching is only available on some backends, models fall
+                    // back to direct draws without multi draw indirect and bloom skips
+                    // its GPU timings without timestamp queries. MSAA can be switched
+                    // on later, so its format features are requested where available.
+                    // Without BC compression textures load from PNG instead of KTX2, and
+                    // without filterable 32 bit floats the scene and procgen volume use
+                    // 16 bit ones
+                    required_features: adapter.features()
+                        & (wgpu::Features::FLOAT32_FILTERABLE
+                            | wgpu::Features::PIPELINE_CACHE
+                            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                            | wgpu::Features::TEXTURE_COMPRESSION_BC
+                            | MULTI_DRAW_INDIRECT_FEATURES
+                            | wgpu::Features::TIMESTAMP_QUERY),
                     required_limits: wgpu::Limits::default(),
                     memory_hints: MemoryHints::Performance,
                 },
@@ -222,10 +703,33 @@ impl<'window> WgpuCtx<'window> {
         let device = Arc::new(device);
         let queue = Arc::new(queue);
 
+        // The cache file is keyed on the adapter and driver, a stale blob is never loaded
+        let pipeline_cache_path = wgpu::util::pipeline_cache_key(&adapter.get_info())
+            .map(|key| std::env::temp_dir().join(key));
+        let pipeline_cache = pipeline_cache_path
+            .as_deref()
+            .and_then(|path| Self::create_or_load_pipeline_cache(&device, path));
+
         let size = window.inner_size();
         let width = size.width.max(1);
         let height = size.height.max(1);
-        let surface_config = surface.get_default_config(&adapter, width, height).unwrap();
+        // The scene renders into its own textures, any surface format works for the final
+        // blit, but some Wayland setups report none at all for the chosen adapter
+        let mut surface_config = surface
+            .get_default_config(&adapter, width, height)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Adapter \"{}\" ({:?}) reports no compatible surface format. On Wayland \
+                     try the Vulkan backend with WGPU_BACKEND=vulkan, or run under X11 by \
+                     unsetting WAYLAND_DISPLAY",
+                    adapter.get_info().name,
+                    adapter.get_info().backend,
+                )
+            });
+        surface_config.present_mode =
+            Self::supported_present_mode(&surface, &adapter, config.present_mode);
+        surface_config.alpha_mode =
+            Self::supported_alpha_mode(&surface, &adapter, config.alpha_mode);
         surface.configure(&device, &surface_config);
 
         // Vertex and index buffers for rendering a full-screen quad
@@ -264,47 +768,26 @@ impl<'window> WgpuCtx<'window> {
 
         // Load multiple textures (emulating Shadertoy iChannels)
         // Noise0 texture
-        let noise0_img = RgbaImg::new("./assets/images/textures/rgbnoise.png").unwrap();
-        let noise0_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Noise0 Texture"),
-            size: wgpu::Extent3d {
-                width: noise0_img.width,
-                height: noise0_img.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &noise0_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &noise0_img.bytes,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * noise0_img.width),
-                rows_per_image: Some(noise0_img.height),
-            },
-            wgpu::Extent3d {
-                width: noise0_img.width,
-                height: noise0_img.height,
-                depth_or_array_layers: 1,
-            },
-        );
+        let noise0_texture = load_texture(
+            &device,
+            &queue,
+            "Noise0 Texture",
+            "./assets/images/textures/rgbnoise.png",
+        )
+        .unwrap();
         let noise0_texture_view =
             noise0_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         // Noise1 texture (3D)
-        let noise1_data_full =
-            std::fs::read("./assets/images/textures/graynoise_32x32x32_cube.bin")
-                .expect("Failed to read noise1 binary file");
+        let noise1_data_full = std::fs::read(NOISE1_PATH).unwrap_or_else(|err| {
+            tracing::error!(path = NOISE1_PATH, %err, "Failed to read noise1 binary file");
+            panic!("Failed to read noise1 binary file {}: {}", NOISE1_PATH, err)
+        });
+        tracing::info!(
+            path = NOISE1_PATH,
+            bytes = noise1_data_full.len(),
+            "Loaded noise1"
+        );
         let noise1_data = &noise1_data_full[20..20 + 32 * 32 * 32];
         assert_eq!(
             noise1_data.len(),
@@ -351,79 +834,39 @@ impl<'window> WgpuCtx<'window> {
             noise1_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         // Grain texture
-        let grain_img = RgbaImg::new("./assets/images/textures/stone.png").unwrap();
-        let grain_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Grain Texture"),
-            size: wgpu::Extent3d {
-                width: grain_img.width,
-                height: grain_img.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &grain_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &grain_img.bytes,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * grain_img.width),
-                rows_per_image: Some(grain_img.height),
-            },
-            wgpu::Extent3d {
-                width: grain_img.width,
-                height: grain_img.height,
-                depth_or_array_layers: 1,
-            },
-        );
+        let grain_texture = load_texture(
+            &device,
+            &queue,
+            "Grain Texture",
+            "./assets/images/textures/stone.png",
+        )
+        .unwrap();
         let grain_texture_view = grain_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         // Dirt texture
-        let dirt_img = RgbaImg::new("./assets/images/textures/mud.png").unwrap();
-        let dirt_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Dirt Texture"),
-            size: wgpu::Extent3d {
-                width: dirt_img.width,
-                height: dirt_img.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &dirt_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &dirt_img.bytes,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dirt_img.width),
-                rows_per_image: Some(dirt_img.height),
-            },
-            wgpu::Extent3d {
-                width: dirt_img.width,
-                height: dirt_img.height,
-                depth_or_array_layers: 1,
-            },
-        );
+        let dirt_texture = load_texture(
+            &device,
+            &queue,
+            "Dirt Texture",
+            "./assets/images/textures/mud.png",
+        )
+        .unwrap();
         let dirt_texture_view = dirt_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        // CPU generated SDF volume, blended with the analytic terrain by procgen_blend
+        let procgen_texture = ProcGenTerrain::new(64, 0).upload(&device, &queue);
+        let procgen_texture_view =
+            procgen_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Voxel edits overriding the terrain, see VoxelEditorSystem
+        let voxel_edit_buffer = VoxelEditBuffer::new(&device, &queue);
+
+        // Streamed chunk volumes, empty until the ChunkStreamingSystem sizes the atlas
+        let chunk_atlas = ChunkAtlas::new(&device, &queue, 1, 1, 1.0);
+        let chunk_atlas_view = chunk_atlas
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
         // Terrain bind group layout for multiple textures
         let terrain_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -474,6 +917,46 @@ impl<'window> WgpuCtx<'window> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3, // procgen_texture is 3D
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D3, // chunk_atlas is 3D
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("terrain_bind_group_layout"),
             });
@@ -502,17 +985,50 @@ impl<'window> WgpuCtx<'window> {
                     binding: 4,
                     resource: wgpu::BindingResource::Sampler(&texture_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&procgen_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: voxel_edit_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&chunk_atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: chunk_atlas.table.as_entire_binding(),
+                },
             ],
             label: Some("terrain_bind_group"),
         });
 
-        // Create the bind group layout
-        let voxel_settings_bind_group_layout =
+        // Create the default settings
+        let mut voxel_settings = VoxelSettings {
+            transparent_background: is_transparent(surface_config.alpha_mode) as i32,
+            ..Default::default()
+        };
+        voxel_settings.validate();
+
+        // Create the buffer
+        let voxel_settings_buffer = voxel_settings.create_buffer(&device);
+
+        // Time uniform and bind group, shared by every scene pipeline at group 0
+        let time_uniform = TimeUniform::default();
+        let time_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Time Buffer"),
+            contents: bytemuck::cast_slice(&[time_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let time_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Voxel Settings Bind Group Layout"),
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT, //  | wgpu::ShaderStages::VERTEX  | wgpu::ShaderStages::COMPUTE
+                    visibility: wgpu::ShaderStages::VERTEX
+                        | wgpu::ShaderStages::FRAGMENT
+                        | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -520,27 +1036,21 @@ impl<'window> WgpuCtx<'window> {
                     },
                     count: None,
                 }],
+                label: Some("time_bind_group_layout"),
             });
-
-        // Create the default settings
-        let voxel_settings = VoxelSettings::default();
-
-        // Create the buffer
-        let voxel_settings_buffer = voxel_settings.create_buffer(&device);
-
-        // Create the bind group
-        let voxel_settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Voxel Settings Bind Group"),
-            layout: &voxel_settings_bind_group_layout,
+        let time_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &time_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: voxel_settings_buffer.as_entire_binding(),
+                resource: time_buffer.as_entire_binding(),
             }],
+            label: Some("time_bind_group"),
         });
 
         // Camera uniform and bind group
         let camera_uniform = CameraUniform {
             view_proj: Matrix4::identity().into(),
+            prev_view_proj: Matrix4::identity().into(),
             ..Default::default()
         };
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -571,30 +1081,125 @@ impl<'window> WgpuCtx<'window> {
             label: Some("camera_bind_group"),
         });
 
-        // Render pipeline setup
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[
-                    &camera_bind_group_layout,
-                    &terrain_bind_group_layout,
-                    &voxel_settings_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
-        let render_pipeline = create_pipeline(
-            &device,
-            wgpu::TextureFormat::Rgba32Float,
-            &render_pipeline_layout,
-        );
+        let voxel_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Voxel Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("voxels.wgsl"))),
+        });
 
-        // Depth texture
-        let (depth_texture, depth_texture_view) =
-            Self::create_depth_texture(&device, &surface_config);
+        // Shadow map rendered from the directional light
+        let shadow_map_pass = ShadowMapPass::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            &time_bind_group_layout,
+            &camera_bind_group_layout,
+            &terrain_bind_group_layout,
+            &voxel_settings_buffer,
+            &voxel_shader,
+        );
 
-        // Texture bind group layout for post-processing
-        let texture_bind_group_layout = Arc::new(device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
+        // Create the bind group layout
+        let voxel_settings_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Voxel Settings Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT, //  | wgpu::ShaderStages::VERTEX  | wgpu::ShaderStages::COMPUTE
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        // Create the bind group
+        let voxel_settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Voxel Settings Bind Group"),
+            layout: &voxel_settings_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: voxel_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(shadow_map_pass.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(shadow_map_pass.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: shadow_map_pass.uniform_buffer().as_entire_binding(),
+                },
+            ],
+        });
+
+        // Render pipeline setup
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &time_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &terrain_bind_group_layout,
+                    &voxel_settings_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = create_pipeline(
+            &device,
+            render_format,
+            &render_pipeline_layout,
+            &voxel_shader,
+            msaa_samples,
+            pipeline_cache.as_ref(),
+        );
+        let gbuffer_pass = GBufferPass::new(
+            Arc::clone(&device),
+            &render_pipeline_layout,
+            &voxel_shader,
+            surface_config.width,
+            surface_config.height,
+        );
+
+        // Depth texture
+        let (depth_texture, depth_texture_view) =
+            Self::create_depth_texture(&device, surface_config.width, surface_config.height);
+
+        // Texture bind group layout for post-processing
+        let texture_bind_group_layout = Arc::new(device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
@@ -612,11 +1217,133 @@ impl<'window> WgpuCtx<'window> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // Normal map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    // Metallic (blue) and roughness (green)
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    // Emission
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    // Material constants, the emission color and strength
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             },
         ));
 
+        let debug_draw = DebugDraw::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            &time_bind_group_layout,
+            &camera_bind_group_layout,
+            render_format,
+        );
+
+        let skybox_pass = SkyboxPass::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            &time_bind_group_layout,
+            &camera_bind_group_layout,
+            render_format,
+            msaa_samples,
+        );
+
+        let multi_draw_indirect = device.features().contains(MULTI_DRAW_INDIRECT_FEATURES);
+        let indirect_cull_pass = IndirectCullPass::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            pipeline_cache.as_ref(),
+        );
+        let occlusion_cull_pass = OcclusionCullPass::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            &time_bind_group_layout,
+            &camera_bind_group_layout,
+        );
+
+        // Instanced model rendering, drawn into the same targets as the voxel pass
+        let model_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Model Shader"),
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("model.wgsl"))),
+        });
+        // Joint matrix palette of skinned models
+        let joint_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Joint Palette Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let model_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Model Pipeline Layout"),
+                bind_group_layouts: &[
+                    &time_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &joint_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let model_pipeline = create_model_pipeline(
+            &device,
+            &model_pipeline_layout,
+            &model_shader,
+            render_format,
+            msaa_samples,
+        );
+        let msaa_targets = (msaa_samples > 1).then(|| {
+            MsaaTargets::new(
+                Arc::clone(&device),
+                render_format,
+                msaa_samples,
+                surface_config.width,
+                surface_config.height,
+            )
+        });
+
         // Render texture for intermediate rendering
         let render_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Render Texture"),
@@ -628,29 +1355,121 @@ impl<'window> WgpuCtx<'window> {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
+            format: render_format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
+                | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
         let render_texture_view =
             render_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        // SSAO setup, noise0 is reused as the kernel rotation noise
+        let (normal_depth_texture, normal_depth_texture_view) = Self::create_normal_depth_texture(
+            &device,
+            render_format,
+            surface_config.width,
+            surface_config.height,
+        );
+        let ssao_settings = SsaoSettings::default();
+        let ssao_effect = SsaoEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            SsaoParams {
+                camera_buffer: &camera_buffer,
+                noise_view: noise0_texture_view.clone(),
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+                scene_texture_view: &render_texture_view,
+                normal_depth_view: &normal_depth_texture_view,
+            },
+            pipeline_cache.as_ref(),
+        );
+
+        // Volumetric fog setup, density comes from the terrain's 3D noise
+        let fog_settings = FogSettings::default();
+        let fog_effect = VolumetricFogEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            FogParams {
+                camera_buffer: &camera_buffer,
+                voxel_settings_buffer: &voxel_settings_buffer,
+                time_buffer: &time_buffer,
+                noise_view: noise1_texture_view.clone(),
+                noise_sampler: Arc::clone(&texture_sampler),
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+                scene_texture_view: &render_texture_view,
+                depth_texture_view: &depth_texture_view,
+            },
+            pipeline_cache.as_ref(),
+        );
+
+        // Motion blur setup
+        let (velocity_texture, velocity_texture_view) =
+            Self::create_velocity_texture(&device, surface_config.width, surface_config.height);
+        let motion_blur_settings = MotionBlurSettings::default();
+        let motion_blur_effect = MotionBlurEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            MotionBlurParams {
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+                scene_texture_view: &render_texture_view,
+                velocity_texture_view: &velocity_texture_view,
+            },
+            pipeline_cache.as_ref(),
+        );
+
+        // Depth of field setup
+        let dof_settings = DofSettings::default();
+        let dof_effect = DepthOfFieldEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            DepthOfFieldParams {
+                camera_buffer: &camera_buffer,
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+                scene_texture_view: &render_texture_view,
+                depth_texture_view: &depth_texture_view,
+            },
+            pipeline_cache.as_ref(),
+        );
+
+        // Lens flare setup
+        let lens_flare_settings = LensFlareSettings::default();
+        let lens_flare_effect = LensFlareEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            LensFlareParams {
+                camera_buffer: &camera_buffer,
+                voxel_settings_buffer: &voxel_settings_buffer,
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+                scene_texture_view: &render_texture_view,
+                depth_texture_view: &depth_texture_view,
+            },
+            pipeline_cache.as_ref(),
+        );
+
         // Bloom effect setup
-        let bloom_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Bloom Shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("bloom.wgsl"))),
-        });
         let bloom_effect = BloomEffect::new(
             Arc::clone(&device),
             Arc::clone(&queue),
-            Arc::clone(&texture_bind_group_layout),
-            Arc::clone(&sampler),
-            surface_config.width,
-            surface_config.height,
-            &render_texture_view,
-            &bloom_shader,
+            BloomParams {
+                texture_bind_group_layout: Arc::clone(&texture_bind_group_layout),
+                sampler: Arc::clone(&sampler),
+                max_level: BLOOM_MAX_LEVEL,
+                width: surface_config.width,
+                height: surface_config.height,
+                format: render_format,
+            },
+            pipeline_cache.as_ref(),
         );
 
         // Post-process texture
@@ -664,24 +1483,75 @@ impl<'window> WgpuCtx<'window> {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
+            format: render_format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
+                | wgpu::TextureUsages::STORAGE_BINDING // The bloom composite writes it
+                | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
         let post_process_texture_view =
             post_process_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        // Auto exposure, measured from the bloom prefilter output
+        let auto_exposure_effect = AutoExposureEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            pipeline_cache.as_ref(),
+        );
+
+        let chromatic_aberration_effect = ChromaticAberrationEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            surface_config.width,
+            surface_config.height,
+            render_format,
+            &post_process_texture_view,
+        );
+
         // Color correction effect
         let color_correction_effect = ColorCorrectionEffect::new(
             Arc::clone(&device),
-            Arc::clone(&queue),
             &post_process_texture_view,
+            auto_exposure_effect.exposure_view(),
             Arc::clone(&sampler),
             surface_config.format,
         );
 
+        let fxaa_effect = FxaaEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&sampler),
+            surface_config.width,
+            surface_config.height,
+            render_format,
+            &post_process_texture_view,
+        );
+
+        let film_grain_effect = FilmGrainEffect::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+            surface_config.format,
+        );
+
+        // Post-processing chain, the in place effects run in the order added
+        let mut render_graph = RenderGraph::new();
+        render_graph
+            .add_node("ssao", &["scene", "normal_depth"], &["scene"])
+            .add_node("fog", &["scene", "depth"], &["scene"])
+            .add_node("motion_blur", &["scene", "velocity"], &["scene"])
+            .add_node("dof", &["scene", "depth"], &["scene"])
+            .add_node("lens_flare", &["scene", "depth"], &["scene"])
+            .add_node("bloom", &["scene"], &["post_process", "bloom_prefilter"])
+            .add_node("auto_exposure", &["bloom_prefilter"], &["exposure"])
+            .add_node("chromatic_aberration", &["post_process"], &["post_process"])
+            .add_node(
+                "color_correction",
+                &["post_process", "exposure"],
+                &["surface"],
+            )
+            .add_node("film_grain", &["surface"], &["surface"]);
+        set_fxaa_node(&mut render_graph, antialiasing);
+
         let hidpi_factor = window.scale_factor();
 
         let imgui = {
@@ -693,18 +1563,7 @@ impl<'window> WgpuCtx<'window> {
                 imgui_winit_support::HiDpiMode::Default,
             );
             context.set_ini_filename(None);
-
-            let font_size = (13.0 * hidpi_factor) as f32;
-            context.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
-
-            context.fonts().add_font(&[FontSource::DefaultFontData {
-                config: Some(imgui::FontConfig {
-                    oversample_h: 1,
-                    pixel_snap_h: true,
-                    size_pixels: font_size,
-                    ..Default::default()
-                }),
-            }]);
+            Self::add_ui_font(&mut context, hidpi_factor);
 
             //
             // Set up dear imgui wgpu renderer
@@ -737,13 +1596,41 @@ impl<'window> WgpuCtx<'window> {
             }
         };
 
+        let mut frame_allocator = FrameAllocator::new(&device, Arc::clone(&queue));
+        let color_correction_settings = ColorCorrectionUniform::with_premultiplied_alpha(
+            is_transparent(surface_config.alpha_mode),
+        );
+        color_correction_effect.update_uniform(color_correction_settings, &mut frame_allocator);
+
         WgpuCtx {
+            window,
             surface,
             surface_config,
             adapter,
             device,
             queue,
+            pipeline_cache,
+            pipeline_cache_path,
             render_pipeline,
+            render_pipeline_layout,
+            voxel_shader,
+            model_pipeline,
+            model_pipeline_layout,
+            model_shader,
+            multi_draw_indirect,
+            antialiasing,
+            msaa_samples,
+            render_format,
+            msaa_targets,
+            indirect_cull_pass,
+            indirect_batches: Vec::new(),
+            indirect_bounds: Vec::new(),
+            indirect_buffer: None,
+            occlusion_cull_pass,
+            frustum: Frustum::from_view_projection(Matrix4::identity()),
+            camera_position: Point3::new(0.0, 0.0, 0.0),
+            view: Matrix4::identity(),
+            inv_view_proj: Matrix4::identity(),
             vertex_buffer,
             vertex_index_buffer,
             texture: noise1_texture.clone(), // Primary texture for compatibility
@@ -759,129 +1646,931 @@ impl<'window> WgpuCtx<'window> {
             camera_bind_group,
             depth_texture,
             depth_texture_view,
-            models: Vec::new(),
+            models: HashMap::new(),
+            next_model_key: 0,
             texture_bind_group_layout,
+            joint_bind_group_layout,
             render_texture,
             render_texture_view,
+            normal_depth_texture,
+            normal_depth_texture_view,
+            ssao_effect,
+            ssao_settings,
+            fog_effect,
+            fog_settings,
+            dof_effect,
+            dof_settings,
+            lens_flare_effect,
+            lens_flare_settings,
+            velocity_texture,
+            velocity_texture_view,
+            prev_view_proj: None,
+            motion_blur_effect,
+            motion_blur_settings,
             bloom_effect,
+            bloom_settings: BloomSettings::default(),
+            render_graph,
             post_process_texture,
             post_process_texture_view,
+            auto_exposure_effect,
+            auto_exposure_settings: AutoExposureSettings::default(),
+            chromatic_aberration_effect,
+            fxaa_effect,
             color_correction_effect,
+            color_correction_settings,
+            film_grain_effect,
             noise0_texture,
             noise1_texture,
             grain_texture,
             dirt_texture,
+            procgen_texture,
             terrain_bind_group_layout,
             terrain_bind_group,
+            noise1_data: noise1_data.to_vec(),
+            voxel_edit_buffer,
+            chunk_atlas,
             time: Instant::now(),
+            time_uniform,
+            time_buffer,
+            time_bind_group,
             imgui,
+            debug_draw,
+            perf_stats: PerfStats::default(),
+            render_stats: RenderStats::default(),
+            frame_allocator,
+            show_perf_hud: false,
+            show_render_stats: false,
+            render_scale: 1.0,
+            voxel_chunks: HashMap::new(),
+            skybox_pass,
+            skyboxes: HashMap::new(),
             hidpi_factor,
             voxel_settings,
             voxel_settings_buffer,
             voxel_settings_bind_group,
+            shadow_map_pass,
+            gbuffer_pass,
+            gbuffer_debug_bound: GBufferDebug::Off,
+            voxel_shader_watcher: ShaderWatcher::new(SHADER_DIR.to_owned() + "/voxels.wgsl"),
+            bloom_shader_watcher: ShaderWatcher::new(SHADER_DIR.to_owned() + "/bloom.wgsl"),
+            color_correction_shader_watcher: ShaderWatcher::new(
+                SHADER_DIR.to_owned() + "/color_correction.wgsl",
+            ),
+        }
+    }
+
+    /// Steps the animations of all `AnimationController` entities
+    pub fn update_animations(&mut self, world: &mut World, dt: Duration) {
+        update_animation_system(world, &mut self.models, &self.queue, dt);
+    }
+
+    /// Creates a pipeline cache seeded from `path` if it exists. Returns None
+    /// when the device doesn't support pipeline caching
+    pub fn create_or_load_pipeline_cache(
+        device: &wgpu::Device,
+        path: &Path,
+    ) -> Option<wgpu::PipelineCache> {
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return None;
+        }
+        // A missing file only means nothing was cached yet
+        let data = match fs::read(path) {
+            Ok(data) => Some(data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                tracing::warn!(path = %path.display(), %err, "Failed to read pipeline cache");
+                None
+            }
+        };
+        // SAFETY: the data was written by `save_pipeline_cache` for the same adapter,
+        // and `fallback` makes wgpu start empty if it is rejected anyway
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        Some(cache)
+    }
+
+    /// Writes the pipeline cache back to disk so the next launch skips shader compilation
+    pub fn save_pipeline_cache(&self) {
+        let (Some(cache), Some(path)) = (&self.pipeline_cache, &self.pipeline_cache_path) else {
+            return;
+        };
+        let Some(data) = cache.get_data() else {
+            return;
+        };
+        // Write to a temporary file first so a crash never leaves a truncated cache
+        let temp_path = path.with_extension("tmp");
+        if let Err(err) = fs::write(&temp_path, &data).and_then(|_| fs::rename(&temp_path, path)) {
+            tracing::warn!(path = %path.display(), %err, "Failed to save pipeline cache");
+        }
+    }
+
+    pub fn load_model<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        load_with_bvh: bool,
+    ) -> Result<ModelKey, ModelLoadError> {
+        let mut model = Model::load(&self.device, &self.queue, path, load_with_bvh)?;
+        model.create_bind_groups(&self.device, &self.texture_bind_group_layout);
+        model.create_joint_bind_group(&self.device, &self.joint_bind_group_layout);
+        model.upload_textures(&self.device, &self.queue);
+        // Keys are never reused, so instances of an unloaded model just stop drawing
+        let key = ModelKey(self.next_model_key);
+        self.next_model_key += 1;
+        self.models.insert(key, model);
+        Ok(key)
+    }
+
+    /// Writes one `DrawIndexedIndirectArgs` per instance of every loaded model's meshes,
+    /// uploading all instances except the occluded ones. The batches to draw them and
+    /// their bounds for `IndirectCullPass` are kept until the next call
+    pub fn build_indirect_draw_buffer(&mut self, world: &World) -> wgpu::Buffer {
+        let mut instances: ModelInstances = HashMap::new();
+        let occluded = self.occlusion_cull_pass.occluded();
+        for (_, (transform, instance, bounds)) in world
+            .query::<(&Transform, &ModelInstance, Option<&BoundingBox>)>()
+            .iter()
+            .filter(|(entity, _)| !occluded.contains(entity))
+        {
+            instances
+                .entry(instance.model)
+                .or_default()
+                .push((calculate_model_matrix(transform), bounds.copied()));
+        }
+        let mut model_keys: Vec<ModelKey> = instances.keys().copied().collect();
+        model_keys.sort_unstable();
+
+        self.indirect_batches.clear();
+        self.indirect_bounds.clear();
+        let mut commands = Vec::new();
+        for model_key in model_keys {
+            let Some(model) = self.models.get_mut(&model_key) else {
+                continue;
+            };
+            let instances = &instances[&model_key];
+            let matrices: Vec<[[f32; 4]; 4]> = instances
+                .iter()
+                .map(|(matrix, _)| (*matrix).into())
+                .collect();
+            model.write_instances(&self.device, &self.queue, &matrices);
+            let bounds: Vec<CullBounds> = instances
+                .iter()
+                .map(|(matrix, bounds)| {
+                    bounds
+                        .unwrap_or(model.bounding_box)
+                        .transformed(matrix)
+                        .into()
+                })
+                .collect();
+
+            for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+                self.indirect_batches.push(IndirectBatch {
+                    model: model_key,
+                    mesh: mesh_index,
+                    first: commands.len() as u32,
+                    count: instances.len() as u32,
+                });
+                for instance in 0..instances.len() as u32 {
+                    commands.push(wgpu::util::DrawIndexedIndirectArgs {
+                        index_count: mesh.num_elements,
+                        instance_count: 1,
+                        first_index: 0,
+                        base_vertex: 0,
+                        first_instance: instance,
+                    });
+                }
+                self.indirect_bounds.extend_from_slice(&bounds);
+            }
+        }
+
+        let contents: Vec<u8> = commands
+            .iter()
+            .flat_map(|command| command.as_bytes())
+            .copied()
+            .collect();
+        let size = (contents.len() as wgpu::BufferAddress).max(INDIRECT_ARGS_SIZE);
+        if self
+            .indirect_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.indirect_buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Indirect Draw Buffer"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::INDIRECT
+                    | wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let buffer = self.indirect_buffer.clone().unwrap();
+        self.queue.write_buffer(&buffer, 0, &contents);
+        buffer
+    }
+
+    /// Frees a model's GPU resources, returns false if the key wasn't loaded
+    pub fn unload_model(&mut self, key: ModelKey) -> bool {
+        self.models.remove(&key).is_some()
+    }
+
+    /// Replaces the ProcGenTerrain volume the voxel shader blends in
+    pub fn set_procgen_terrain(&mut self, terrain: &ProcGenTerrain) {
+        self.procgen_texture = terrain.upload(&self.device, &self.queue);
+        self.rebuild_terrain_bind_group();
+    }
+
+    /// Recreates the chunk atlas unless it already has `slots` per side of `resolution`
+    /// voxels, true if it did and the chunks written so far are gone
+    pub fn resize_chunk_atlas(&mut self, slots: i32, resolution: u32, chunk_size: f32) -> bool {
+        if self.chunk_atlas.fits(slots, resolution, chunk_size) {
+            return false;
+        }
+        self.chunk_atlas.texture.destroy();
+        self.chunk_atlas =
+            ChunkAtlas::new(&self.device, &self.queue, slots, resolution, chunk_size);
+        self.rebuild_terrain_bind_group();
+        true
+    }
+
+    /// Copies a streamed chunk's uploaded volume into the atlas the voxel shader samples
+    pub fn write_chunk_volume(&self, position: Vector3<i32>, volume: &wgpu::Texture) {
+        self.chunk_atlas
+            .write_chunk(&self.device, &self.queue, position, volume);
+    }
+
+    fn rebuild_terrain_bind_group(&mut self) {
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let views = [
+            view(&self.noise0_texture),
+            view(&self.noise1_texture),
+            view(&self.grain_texture),
+            view(&self.dirt_texture),
+        ];
+        let procgen_texture_view = view(&self.procgen_texture);
+        let chunk_atlas_view = view(&self.chunk_atlas.texture);
+        self.terrain_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.terrain_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&views[2]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&views[3]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.texture_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&procgen_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.voxel_edit_buffer.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&chunk_atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.chunk_atlas.table.as_entire_binding(),
+                },
+            ],
+            label: Some("terrain_bind_group"),
+        });
+        self.bind_group = self.terrain_bind_group.clone();
+    }
+
+    /// Uploads the edit of `cell`, false if it's too far from the other edits
+    pub fn write_voxel_edit(&mut self, grid: &VoxelGrid, cell: Vector3<i32>) -> bool {
+        self.voxel_edit_buffer.write_cell(&self.queue, grid, cell)
+    }
+
+    /// Distance to the analytic terrain at `position`, without voxel edits
+    pub fn terrain_distance(&self, position: Point3<f32>) -> f32 {
+        analytic_terrain_distance(&self.noise1_data, &self.voxel_settings, position)
+    }
+
+    pub fn voxel_size(&self) -> f32 {
+        self.voxel_settings.voxel_size
+    }
+
+    /// Uploads a volume as its own texture, without binding it to the terrain
+    pub fn upload_procgen_volume(&self, terrain: &ProcGenTerrain) -> wgpu::Texture {
+        terrain.upload(&self.device, &self.queue)
+    }
+
+    /// Loads a cube map from six face images in +X, -X, +Y, -Y, +Z, -Z order
+    pub fn load_skybox(&mut self, paths: [&Path; 6]) -> Option<SkyboxHandle> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            match RgbaImg::new(path.to_str().unwrap()) {
+                Some(face) => faces.push(face),
+                None => {
+                    tracing::error!(path = %path.display(), "Failed to load skybox face");
+                    return None;
+                }
+            }
+        }
+        self.skybox_pass.load(&faces.try_into().ok()?)
+    }
+
+    /// Handle of the skybox in the `Skybox` component's directory, loaded on first use
+    fn skybox_handle(&mut self, texture_path: &str) -> Option<SkyboxHandle> {
+        if let Some(handle) = self.skyboxes.get(texture_path) {
+            return *handle;
+        }
+        let directory = Path::new(texture_path);
+        let paths = SKYBOX_FACES.map(|face| directory.join(face));
+        let handle = self.load_skybox(paths.each_ref().map(PathBuf::as_path));
+        self.skyboxes.insert(texture_path.to_owned(), handle);
+        handle
+    }
+
+    pub fn update_camera_uniform(
+        &mut self,
+        view_proj: Matrix4<f32>,
+        inv_view_proj: Matrix4<f32>,
+        view: Matrix4<f32>,
+        position: [f32; 3],
+    ) {
+        let camera_uniform = CameraUniform {
+            view_proj: view_proj.into(),
+            inv_view_proj: inv_view_proj.into(),
+            view: view.into(),
+            position,
+            _padding: 0.0,
+            prev_view_proj: self.prev_view_proj.unwrap_or(view_proj).into(),
+        };
+        self.prev_view_proj = Some(view_proj);
+        self.frame_allocator.write(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[camera_uniform]),
+        );
+
+        self.frustum = Frustum::from_view_projection(view_proj);
+        self.camera_position = Point3::from(position);
+        self.view = view;
+        self.inv_view_proj = inv_view_proj;
+
+        // Keep the shadow volume centered on the camera
+        self.shadow_map_pass
+            .update_light(self.voxel_settings.light_direction, Point3::from(position));
+    }
+    /// Moves the sun, the shadow map follows on the next camera update
+    pub fn set_sun(&mut self, light_direction: [f32; 4], light_color: [f32; 4]) {
+        let [x, y, z, _] = light_direction;
+        self.voxel_settings.set_light_direction([x, y, z]);
+        self.voxel_settings.light_color = light_color;
+        self.frame_allocator.write(
+            &self.voxel_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.voxel_settings]),
+        );
+    }
+
+    pub fn voxel_settings(&self) -> &VoxelSettings {
+        &self.voxel_settings
+    }
+
+    pub fn set_voxel_settings(&mut self, mut voxel_settings: VoxelSettings) {
+        voxel_settings.transparent_background = self.voxel_settings.transparent_background;
+        voxel_settings.validate();
+        self.voxel_settings = voxel_settings;
+        self.frame_allocator.write(
+            &self.voxel_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.voxel_settings]),
+        );
+    }
+
+    pub fn set_snow_height(&mut self, snow_height: f32) {
+        self.voxel_settings.snow_height = snow_height;
+        self.frame_allocator.write(
+            &self.voxel_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.voxel_settings]),
+        );
+    }
+
+    pub fn set_water_height(&mut self, water_height: f32) {
+        self.voxel_settings.water_height = water_height;
+        self.frame_allocator.write(
+            &self.voxel_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.voxel_settings]),
+        );
+    }
+
+    /// Information about the GPU and driver in use, for bug reports
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    /// Recompiles any shader whose source changed on disk. Returns true if a
+    /// pipeline was replaced, on errors the old pipelines are kept
+    pub fn try_reload_shaders(&mut self) -> bool {
+        let mut reloaded = false;
+
+        if let Some(source) = self.voxel_shader_watcher.poll() {
+            let result = with_validation(&self.device, || {
+                let shader = self
+                    .device
+                    .create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("Voxel Shader"),
+                        source: ShaderSource::Wgsl(source.into()),
+                    });
+                let render_pipeline = create_pipeline(
+                    &self.device,
+                    self.render_format,
+                    &self.render_pipeline_layout,
+                    &shader,
+                    self.msaa_samples,
+                    None,
+                );
+                let shadow_pipeline = self.shadow_map_pass.create_pipeline(&self.device, &shader);
+                let gbuffer_pipeline = self.gbuffer_pass.create_pipeline(&self.device, &shader);
+                (shader, render_pipeline, shadow_pipeline, gbuffer_pipeline)
+            });
+            match result {
+                Ok((shader, render_pipeline, shadow_pipeline, gbuffer_pipeline)) => {
+                    self.voxel_shader = shader;
+                    self.render_pipeline = render_pipeline;
+                    self.shadow_map_pass.set_pipeline(shadow_pipeline);
+                    self.gbuffer_pass.set_pipeline(gbuffer_pipeline);
+                    reloaded = true;
+                }
+                Err(err) => tracing::error!(
+                    path = %self.voxel_shader_watcher.path().display(),
+                    %err,
+                    "Failed to reload shader"
+                ),
+            }
+        }
+
+        if let Some(source) = self.bloom_shader_watcher.poll() {
+            match self.bloom_effect.reload_shader(&source) {
+                Ok(()) => reloaded = true,
+                Err(err) => tracing::error!(
+                    path = %self.bloom_shader_watcher.path().display(),
+                    %err,
+                    "Failed to reload shader"
+                ),
+            }
+        }
+
+        if let Some(source) = self.color_correction_shader_watcher.poll() {
+            match self.color_correction_effect.reload_shader(&source) {
+                Ok(()) => reloaded = true,
+                Err(err) => tracing::error!(
+                    path = %self.color_correction_shader_watcher.path().display(),
+                    %err,
+                    "Failed to reload shader"
+                ),
+            }
+        }
+
+        reloaded
+    }
+
+    /// Synchronous constructor that blocks on async initialization
+    pub fn new(window: Arc<Window>, config: WgpuCtxConfig) -> WgpuCtx<'window> {
+        pollster::block_on(WgpuCtx::new_async(window, config))
+    }
+
+    /// Returns `requested` if the surface supports it, otherwise Fifo which is always available
+    fn supported_present_mode(
+        surface: &wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        requested: wgpu::PresentMode,
+    ) -> wgpu::PresentMode {
+        if surface
+            .get_capabilities(adapter)
+            .present_modes
+            .contains(&requested)
+        {
+            requested
+        } else {
+            tracing::warn!(
+                ?requested,
+                chosen = ?wgpu::PresentMode::Fifo,
+                "Present mode is not supported"
+            );
+            wgpu::PresentMode::Fifo
+        }
+    }
+
+    /// Returns `requested` if the surface supports it, otherwise Auto which picks an
+    /// opaque mode
+    fn supported_alpha_mode(
+        surface: &wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        requested: wgpu::CompositeAlphaMode,
+    ) -> wgpu::CompositeAlphaMode {
+        if requested == wgpu::CompositeAlphaMode::Auto
+            || surface
+                .get_capabilities(adapter)
+                .alpha_modes
+                .contains(&requested)
+        {
+            requested
+        } else {
+            tracing::warn!(
+                ?requested,
+                chosen = ?wgpu::CompositeAlphaMode::Auto,
+                "Surface alpha mode is not supported"
+            );
+            wgpu::CompositeAlphaMode::Auto
+        }
+    }
+
+    /// Returns `requested` if the scene pass can be multisampled that many times, otherwise 1
+    fn supported_msaa_samples(
+        adapter: &wgpu::Adapter,
+        render_format: wgpu::TextureFormat,
+        requested: u32,
+    ) -> u32 {
+        if requested <= 1 || MsaaTargets::supported(adapter, render_format, requested) {
+            requested.max(1)
+        } else {
+            tracing::warn!(requested, chosen = 1, "MSAA sample count is not supported");
+            1
+        }
+    }
+
+    /// Switches the antialiasing, rebuilding the scene pass pipelines and targets when
+    /// the MSAA sample count changes. MSAA falls back to None where it's unsupported
+    pub fn set_antialiasing_mode(&mut self, mode: AntialiasingMode) {
+        let msaa_samples =
+            Self::supported_msaa_samples(&self.adapter, self.render_format, mode.msaa_samples());
+        self.antialiasing = if msaa_samples < mode.msaa_samples() {
+            AntialiasingMode::None
+        } else {
+            mode
+        };
+        set_fxaa_node(&mut self.render_graph, self.antialiasing);
+        if msaa_samples == self.msaa_samples {
+            return;
         }
+
+        self.msaa_samples = msaa_samples;
+        self.render_pipeline = create_pipeline(
+            &self.device,
+            self.render_format,
+            &self.render_pipeline_layout,
+            &self.voxel_shader,
+            msaa_samples,
+            self.pipeline_cache.as_ref(),
+        );
+        self.model_pipeline = create_model_pipeline(
+            &self.device,
+            &self.model_pipeline_layout,
+            &self.model_shader,
+            self.render_format,
+            msaa_samples,
+        );
+        self.skybox_pass.set_sample_count(msaa_samples);
+        let (width, height) = self.render_size();
+        self.msaa_targets = (msaa_samples > 1).then(|| {
+            MsaaTargets::new(
+                Arc::clone(&self.device),
+                self.render_format,
+                msaa_samples,
+                width,
+                height,
+            )
+        });
     }
 
-    pub fn load_model<P: AsRef<Path>>(&mut self, path: P) -> Option<usize> {
-        if let Some(mut model) = Model::load(&self.device, &self.queue, path) {
-            model.create_bind_groups(&self.device, &self.texture_bind_group_layout);
-            model.upload_textures(&self.queue);
-            let index = self.models.len();
-            self.models.push(model);
-            Some(index)
-        } else {
-            None
+    /// Rebuilds the UI font for a new scale factor, the GPU targets only depend on the
+    /// pixel size and are left alone
+    pub fn resize_hidpi_only(&mut self, hidpi_factor: f64) {
+        if hidpi_factor == self.hidpi_factor {
+            return;
         }
+        self.hidpi_factor = hidpi_factor;
+        let imgui = &mut self.imgui;
+        imgui.context.fonts().clear();
+        Self::add_ui_font(&mut imgui.context, hidpi_factor);
+        imgui
+            .renderer
+            .reload_font_texture(&mut imgui.context, &self.device, &self.queue);
     }
 
-    pub fn update_camera_uniform(
-        &mut self,
-        view_proj: Matrix4<f32>,
-        inv_view_proj: Matrix4<f32>,
-        view: Matrix4<f32>,
-        position: [f32; 3],
-    ) {
-        let camera_uniform = CameraUniform {
-            view_proj: view_proj.into(),
-            inv_view_proj: inv_view_proj.into(),
-            view: view.into(),
-            position,
-            time: self.time.elapsed().as_secs_f32(),
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.surface_config.present_mode =
+            Self::supported_present_mode(&self.surface, &self.adapter, present_mode);
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
+    /// Rasterizes the UI font at the window's scale factor, scaled back down to logical size
+    fn add_ui_font(context: &mut imgui::Context, hidpi_factor: f64) {
+        let font_size = (13.0 * hidpi_factor) as f32;
+        context.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
+
+        context.fonts().add_font(&[FontSource::DefaultFontData {
+            config: Some(imgui::FontConfig {
+                oversample_h: 1,
+                pixel_snap_h: true,
+                size_pixels: font_size,
+                ..Default::default()
+            }),
+        }]);
+    }
+
+    /// Resizes the rendering surfaces and updates related resources. Does nothing
+    /// when the size didn't change unless `force` is set, moving the window between
+    /// monitors reports the same size with a new scale factor
+    pub fn resize(&mut self, new_size: (u32, u32), force: bool) {
+        let (width, height) = (new_size.0.max(1), new_size.1.max(1));
+        if (width, height) == (self.surface_config.width, self.surface_config.height) && !force {
+            return;
+        }
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        self.surface.configure(&self.device, &self.surface_config);
+        self.resize_render_targets();
+    }
+
+    /// Size of the scene and post-processing targets, the surface scaled by the render scale
+    pub fn render_size(&self) -> (u32, u32) {
+        let scale = |size: u32| ((size as f32 * self.render_scale).round() as u32).max(1);
+        (
+            scale(self.surface_config.width),
+            scale(self.surface_config.height),
+        )
+    }
+
+    /// Renders the scene at `scale` times the surface size, color correction upscales
+    /// it to the surface. Targets are only recreated when their pixel size changes
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let previous_size = self.render_size();
+        self.render_scale = scale;
+        if self.render_size() != previous_size {
+            self.resize_render_targets();
+        }
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Copies the depth under a surface pixel out of the last drawn frame. Waits for
+    /// the GPU, so keep it to single clicks rather than every frame
+    fn read_raw_depth(&self, x: u32, y: u32) -> f32 {
+        let (width, height) = self.render_size();
+        let origin = wgpu::Origin3d {
+            x: ((x as f32 * self.render_scale) as u32).min(width - 1),
+            y: ((y as f32 * self.render_scale) as u32).min(height - 1),
+            z: 0,
         };
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[camera_uniform]),
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Readback Buffer"),
+            size: std::mem::size_of::<f32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Depth Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.depth_texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::DepthOnly,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &staging_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        self.device.poll(wgpu::Maintain::Wait);
+        let depth = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+        staging_buffer.unmap();
+        depth
+    }
+
+    /// World position of the scene surface under a surface pixel, None where only the
+    /// sky was drawn
+    pub fn world_position_at_pixel(&self, x: u32, y: u32) -> Option<Point3<f32>> {
+        let depth = self.read_raw_depth(x, y);
+        if depth >= 1.0 {
+            return None;
+        }
+        // The depth buffer holds OpenGL depth remapped to 0..1, see view_depth in voxels.wgsl
+        let ndc = Vector4::new(
+            (x as f32 + 0.5) / self.surface_config.width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / self.surface_config.height as f32 * 2.0,
+            depth * 2.0 - 1.0,
+            1.0,
         );
+        let world = self.inv_view_proj * ndc;
+        Some(Point3::from_homogeneous(world))
     }
-    /// Synchronous constructor that blocks on async initialization
-    pub fn new(window: Arc<Window>) -> WgpuCtx<'window> {
-        pollster::block_on(WgpuCtx::new_async(window))
+
+    /// Linear view space depth under a surface pixel, infinite where only the sky was drawn
+    pub fn read_depth_at_pixel(&self, x: u32, y: u32) -> f32 {
+        self.world_position_at_pixel(x, y)
+            .map_or(f32::INFINITY, |position| {
+                -(self.view * position.to_homogeneous()).z
+            })
     }
 
-    /// Resizes the rendering surfaces and updates related resources
-    pub fn resize(&mut self, new_size: (u32, u32)) {
-        let (width, height) = new_size;
-        self.surface_config.width = width.max(1);
-        self.surface_config.height = height.max(1);
-        self.surface.configure(&self.device, &self.surface_config);
+    /// CPU wall time of the last frame in milliseconds, 0 before the first one
+    pub fn last_frame_ms(&self) -> f32 {
+        self.perf_stats.frame_times.back().copied().unwrap_or(0.0)
+    }
+
+    /// Render targets, noise and model textures, the MSAA targets only keep their views
+    fn owned_texture_bytes(&self) -> u64 {
+        let targets = [
+            &self.texture,
+            &self.depth_texture,
+            &self.render_texture,
+            &self.normal_depth_texture,
+            &self.velocity_texture,
+            &self.post_process_texture,
+            &self.noise0_texture,
+            &self.noise1_texture,
+            &self.grain_texture,
+            &self.dirt_texture,
+            &self.procgen_texture,
+            &self.chunk_atlas.texture,
+        ];
+        let materials = self
+            .models
+            .values()
+            .flat_map(|model| &model.materials)
+            .flat_map(|material| {
+                [
+                    &material.texture,
+                    &material.normal_texture,
+                    &material.metallic_roughness_texture,
+                    &material.emission_texture,
+                ]
+            })
+            .flatten();
+        targets
+            .into_iter()
+            .chain(materials)
+            .map(texture_bytes)
+            .sum::<u64>()
+            + self.gbuffer_pass.texture_bytes()
+    }
 
+    fn resize_render_targets(&mut self) {
+        let (width, height) = self.render_size();
         let (depth_texture, depth_texture_view) =
-            Self::create_depth_texture(&self.device, &self.surface_config);
+            Self::create_depth_texture(&self.device, width, height);
         self.depth_texture = depth_texture;
         self.depth_texture_view = depth_texture_view;
+        if let Some(msaa) = &mut self.msaa_targets {
+            msaa.resize(width, height);
+        }
 
         self.render_texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Render Texture"),
             size: wgpu::Extent3d {
-                width: self.surface_config.width,
-                height: self.surface_config.height,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
+            format: self.render_format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
+                | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
         self.render_texture_view = self
             .render_texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        let (normal_depth_texture, normal_depth_texture_view) =
+            Self::create_normal_depth_texture(&self.device, self.render_format, width, height);
+        self.normal_depth_texture = normal_depth_texture;
+        self.normal_depth_texture_view = normal_depth_texture_view;
+
         self.post_process_texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Post Process Texture"),
             size: wgpu::Extent3d {
-                width: self.surface_config.width,
-                height: self.surface_config.height,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
+            format: self.render_format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                 | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::STORAGE_BINDING,
+                | wgpu::TextureUsages::STORAGE_BINDING // The bloom composite writes it
+                | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
         self.post_process_texture_view = self
             .post_process_texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        self.bloom_effect.resize(
-            self.surface_config.width,
-            self.surface_config.height,
+        self.ssao_effect.resize(
+            width,
+            height,
+            &self.render_texture_view,
+            &self.normal_depth_texture_view,
+        );
+        self.fog_effect.resize(
+            width,
+            height,
             &self.render_texture_view,
+            &self.depth_texture_view,
         );
+        let (velocity_texture, velocity_texture_view) =
+            Self::create_velocity_texture(&self.device, width, height);
+        self.velocity_texture = velocity_texture;
+        self.velocity_texture_view = velocity_texture_view;
+        self.motion_blur_effect.resize(
+            width,
+            height,
+            &self.render_texture_view,
+            &self.velocity_texture_view,
+        );
+        self.dof_effect.resize(
+            width,
+            height,
+            &self.render_texture_view,
+            &self.depth_texture_view,
+        );
+        self.lens_flare_effect.resize(
+            width,
+            height,
+            &self.render_texture_view,
+            &self.depth_texture_view,
+        );
+        self.bloom_effect.resize(width, height);
+        self.chromatic_aberration_effect
+            .resize(width, height, &self.post_process_texture_view);
+        self.fxaa_effect
+            .resize(width, height, &self.post_process_texture_view);
         self.color_correction_effect
             .resize(&self.post_process_texture_view);
+        self.gbuffer_pass.resize(width, height);
+        self.gbuffer_debug_bound = GBufferDebug::Off;
     }
 
     /// Renders the scene with post-processing effects
-    pub fn draw(&mut self, world: &World, window: &Window) {
+    pub fn draw(&mut self, world: &mut World, ui_state: &mut UiState) {
+        let UiState {
+            time_of_day,
+            water_surface,
+            snow_accumulation,
+            dynamic_resolution,
+            scene_files,
+            target_frame_time,
+            low_power_mode,
+        } = ui_state;
+        self.try_reload_shaders();
+        self.render_stats = RenderStats {
+            texture_bytes: self.owned_texture_bytes(),
+            bloom_mip_bytes: self.bloom_effect.mip_bytes(),
+            ..Default::default()
+        };
+
         let surface_texture = self
             .surface
             .get_current_texture()
@@ -892,26 +2581,114 @@ impl<'window> WgpuCtx<'window> {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let elapsed = self.time.elapsed().as_secs_f32();
+        self.time_uniform = TimeUniform {
+            elapsed,
+            delta: elapsed - self.time_uniform.elapsed,
+            frame: self.time_uniform.frame.wrapping_add(1),
+            _padding: 0,
+        };
+        self.frame_allocator.write(
+            &self.time_buffer,
+            0,
+            bytemuck::cast_slice(&[self.time_uniform]),
+        );
+        self.frame_allocator.flush(&mut encoder);
+        self.occlusion_cull_pass.poll();
+
+        // Render the terrain depth from the light
+        if self.voxel_settings.show_shadows != 0 {
+            self.shadow_map_pass.render(
+                &mut encoder,
+                &self.time_bind_group,
+                &self.camera_bind_group,
+                &self.terrain_bind_group,
+            );
+        }
+
+        // Only the debug view reads the G-buffer so far, it's skipped otherwise. Color
+        // correction is pointed at the shown channel instead of the post-processed scene
+        let gbuffer_debug = GBufferDebug::from_index(self.voxel_settings.gbuffer_debug);
+        if gbuffer_debug != GBufferDebug::Off {
+            self.gbuffer_pass.render(
+                &mut encoder,
+                &self.time_bind_group,
+                &self.camera_bind_group,
+                &self.terrain_bind_group,
+                &self.voxel_settings_bind_group,
+            );
+        }
+        if gbuffer_debug != self.gbuffer_debug_bound {
+            let view = self
+                .gbuffer_pass
+                .debug_view(gbuffer_debug)
+                .unwrap_or(&self.post_process_texture_view);
+            self.color_correction_effect.resize(view);
+            self.gbuffer_debug_bound = gbuffer_debug;
+        }
+
+        let skybox_path = world
+            .query::<&Skybox>()
+            .iter()
+            .next()
+            .map(|(_, skybox)| skybox.texture_path.clone());
+        let skybox = skybox_path.and_then(|path| self.skybox_handle(&path));
+
+        // Cull the model instances on the GPU before the scene pass draws them
+        let indirect_buffer = self.multi_draw_indirect.then(|| {
+            let buffer = self.build_indirect_draw_buffer(world);
+            self.indirect_cull_pass.cull(
+                &mut encoder,
+                &buffer,
+                &self.indirect_bounds,
+                &self.frustum,
+            );
+            buffer
+        });
 
-        // Render the scene to an intermediate texture
+        // Render the scene to an intermediate texture. With MSAA the multisampled
+        // attachments resolve into the same textures, so post-processing is unchanged
         {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Scene Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.render_texture_view,
+            let targets = [
+                &self.render_texture_view,
+                &self.normal_depth_texture_view,
+                &self.velocity_texture_view,
+            ];
+            let clear_color = if is_transparent(self.surface_config.alpha_mode) {
+                wgpu::Color::TRANSPARENT
+            } else {
+                wgpu::Color::BLACK
+            };
+            let attachment = |i: usize, clear: wgpu::Color| match &self.msaa_targets {
+                Some(msaa) => wgpu::RenderPassColorAttachment {
+                    view: &msaa.color_views()[i],
+                    resolve_target: Some(targets[i]),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear),
+                        store: wgpu::StoreOp::Discard,
+                    },
+                },
+                None => wgpu::RenderPassColorAttachment {
+                    view: targets[i],
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(clear),
                         store: wgpu::StoreOp::Store,
                     },
-                })],
+                },
+            };
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Scene Render Pass"),
+                color_attachments: &[
+                    Some(attachment(0, clear_color)),
+                    Some(attachment(1, wgpu::Color::TRANSPARENT)),
+                    Some(attachment(2, wgpu::Color::TRANSPARENT)),
+                ],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture_view,
+                    view: self
+                        .msaa_targets
+                        .as_ref()
+                        .map_or(&self.depth_texture_view, |msaa| msaa.depth_view()),
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -923,33 +2700,192 @@ impl<'window> WgpuCtx<'window> {
             });
 
             rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_bind_group(0, &self.camera_bind_group, &[]);
-            rpass.set_bind_group(1, &self.terrain_bind_group, &[]);
-            rpass.set_bind_group(2, &self.voxel_settings_bind_group, &[]);
+            rpass.set_bind_group(0, &self.time_bind_group, &[]);
+            rpass.set_bind_group(1, &self.camera_bind_group, &[]);
+            rpass.set_bind_group(2, &self.terrain_bind_group, &[]);
+            rpass.set_bind_group(3, &self.voxel_settings_bind_group, &[]);
             rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             rpass.set_index_buffer(
                 self.vertex_index_buffer.slice(..),
                 wgpu::IndexFormat::Uint16,
             );
             rpass.draw_indexed(0..INDICES_SQUARE.len() as u32, 0, 0..1);
+            self.render_stats.record_draw(
+                INDICES_SQUARE.len() as u32,
+                1,
+                self.vertex_buffer.size(),
+            );
+
+            if let Some(skybox) = skybox {
+                self.skybox_pass.render(
+                    &mut rpass,
+                    &self.time_bind_group,
+                    &self.camera_bind_group,
+                    skybox,
+                    &mut self.render_stats,
+                );
+            }
+
+            // Models, GPU culled with one multi draw per mesh when supported, otherwise
+            // one CPU culled batch per model with instances in the world
+            rpass.set_pipeline(&self.model_pipeline);
+            rpass.set_bind_group(0, &self.time_bind_group, &[]);
+            rpass.set_bind_group(1, &self.camera_bind_group, &[]);
+            if let Some(indirect_buffer) = &indirect_buffer {
+                for batch in &self.indirect_batches {
+                    let Some(model) = self.models.get(&batch.model) else {
+                        continue;
+                    };
+                    let mesh = &model.meshes[batch.mesh];
+                    let Some(bind_group) = mesh
+                        .material_index
+                        .and_then(|index| model.materials.get(index))
+                        .and_then(|material| material.bind_group.as_ref())
+                    else {
+                        continue;
+                    };
+                    rpass.set_bind_group(2, bind_group, &[]);
+                    if let Some(joint_bind_group) = &model.joint_bind_group {
+                        rpass.set_bind_group(3, joint_bind_group, &[]);
+                    }
+                    rpass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    rpass.set_vertex_buffer(1, model.instance_buffer.as_ref().unwrap().slice(..));
+                    rpass.set_vertex_buffer(2, mesh.skin_buffer.slice(..));
+                    rpass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    // Culled instances were given an instance count of zero
+                    rpass.multi_draw_indexed_indirect(
+                        indirect_buffer,
+                        batch.first as wgpu::BufferAddress * INDIRECT_ARGS_SIZE,
+                        batch.count,
+                    );
+                    // One instance per command, culled ones included
+                    self.render_stats.record_draw(
+                        mesh.num_elements,
+                        batch.count,
+                        mesh.vertex_buffer.size() + mesh.skin_buffer.size(),
+                    );
+                }
+            } else {
+                let mut model_keys: Vec<ModelKey> = world
+                    .query::<&ModelInstance>()
+                    .iter()
+                    .map(|(_, instance)| instance.model)
+                    .collect();
+                model_keys.sort_unstable();
+                model_keys.dedup();
+
+                let mut ctx = ModelPassContext {
+                    frustum: &self.frustum,
+                    occluded: self.occlusion_cull_pass.occluded(),
+                    device: &self.device,
+                    queue: &self.queue,
+                    stats: &mut self.render_stats,
+                };
+                for model_key in model_keys {
+                    if let Some(model) = self.models.get_mut(&model_key) {
+                        render_model_instances(world, model_key, model, &mut rpass, &mut ctx);
+                    }
+                }
+            }
         }
 
-        // Apply post-processing effects
-        self.bloom_effect
-            .render(&mut encoder, &self.render_texture_view);
-        self.bloom_effect.apply(
+        if let Some(msaa) = &self.msaa_targets {
+            msaa.resolve_depth(&mut encoder, &self.depth_texture_view);
+        }
+
+        // Test the bounds of the frustum visible models against this frame's depth,
+        // the occluded ones are skipped once the results are read back
+        let occlusion_objects: Vec<(Entity, BoundingBox)> = world
+            .query::<(&Transform, &ModelInstance, Option<&BoundingBox>)>()
+            .iter()
+            .filter_map(|(entity, (transform, instance, bounds))| {
+                let model = self.models.get(&instance.model)?;
+                let matrix = calculate_model_matrix(transform);
+                let bounds = bounds.unwrap_or(&model.bounding_box).transformed(&matrix);
+                self.frustum
+                    .contains_aabb(&bounds)
+                    .then_some((entity, bounds))
+            })
+            .collect();
+        self.occlusion_cull_pass.record(
+            &mut encoder,
+            &self.depth_texture_view,
+            &self.time_bind_group,
+            &self.camera_bind_group,
+            self.camera_position,
+            &occlusion_objects,
+        );
+
+        self.debug_draw.render(
             &mut encoder,
-            &self.post_process_texture_view,
             &self.render_texture_view,
+            &self.time_bind_group,
+            &self.camera_bind_group,
         );
-        self.color_correction_effect
-            .update_uniform(ColorCorrectionUniform {
-                brightness: 1.0,
-                contrast: 1.0,
-                saturation: 1.0,
-            });
-        self.color_correction_effect
-            .apply(&mut encoder, &surface_texture_view);
+
+        // Apply post-processing effects
+        self.film_grain_effect.next_frame();
+        self.auto_exposure_effect.next_frame();
+        let nodes: [(&str, &dyn RenderNode); 11] = [
+            ("ssao", &self.ssao_effect),
+            ("fog", &self.fog_effect),
+            ("motion_blur", &self.motion_blur_effect),
+            ("dof", &self.dof_effect),
+            ("lens_flare", &self.lens_flare_effect),
+            ("bloom", &self.bloom_effect),
+            ("auto_exposure", &self.auto_exposure_effect),
+            ("chromatic_aberration", &self.chromatic_aberration_effect),
+            ("fxaa", &self.fxaa_effect),
+            ("color_correction", &self.color_correction_effect),
+            ("film_grain", &self.film_grain_effect),
+        ];
+        let resources = [
+            (
+                "scene",
+                RenderTarget {
+                    texture: &self.render_texture,
+                    view: &self.render_texture_view,
+                },
+            ),
+            (
+                "normal_depth",
+                RenderTarget {
+                    texture: &self.normal_depth_texture,
+                    view: &self.normal_depth_texture_view,
+                },
+            ),
+            (
+                "depth",
+                RenderTarget {
+                    texture: &self.depth_texture,
+                    view: &self.depth_texture_view,
+                },
+            ),
+            (
+                "velocity",
+                RenderTarget {
+                    texture: &self.velocity_texture,
+                    view: &self.velocity_texture_view,
+                },
+            ),
+            (
+                "post_process",
+                RenderTarget {
+                    texture: &self.post_process_texture,
+                    view: &self.post_process_texture_view,
+                },
+            ),
+            ("bloom_prefilter", self.bloom_effect.prefilter_target()),
+            ("exposure", self.auto_exposure_effect.exposure_target()),
+            (
+                "surface",
+                RenderTarget {
+                    texture: &surface_texture.texture,
+                    view: &surface_texture_view,
+                },
+            ),
+        ];
+        self.render_graph.execute(&mut encoder, &nodes, &resources);
 
         // Setup UI first
         // Update time delta
@@ -963,14 +2899,26 @@ impl<'window> WgpuCtx<'window> {
         // Prepare frame
         self.imgui
             .platform
-            .prepare_frame(self.imgui.context.io_mut(), window)
+            .prepare_frame(self.imgui.context.io_mut(), &self.window)
             .expect("Failed to prepare ImGui frame");
+        let adapter_info = self.adapter_info();
+        let limits = self.device.limits();
+        let render_size = self.render_size();
         let ui = self.imgui.context.frame();
 
+        // Applied after presenting, the surface can't be reconfigured mid-frame
+        let mut requested_present_mode = None;
+        let mut requested_antialiasing = None;
+
         // Build your UI here
         {
             let mut modified = false;
-            let window = ui.window("Voxel Settings");
+            // The ### suffix keeps the window id stable while the FPS changes
+            let title = format!(
+                "Voxel Settings ({:.0} FPS)###Voxel Settings",
+                ui.io().framerate
+            );
+            let window = ui.window(title);
             window
                 .size([300.0, 200.0], Condition::FirstUseEver)
                 .build(|| {
@@ -978,6 +2926,620 @@ impl<'window> WgpuCtx<'window> {
                         self.voxel_settings.update_voxel_size();
                         modified = true;
                     }
+                    if ui.slider(
+                        "ProcGen Blend",
+                        0.0,
+                        1.0,
+                        &mut self.voxel_settings.procgen_blend,
+                    ) {
+                        modified = true;
+                    }
+                    let mut show_shadows = self.voxel_settings.show_shadows != 0;
+                    if ui.checkbox("Shadows", &mut show_shadows) {
+                        self.voxel_settings.show_shadows = show_shadows as i32;
+                        modified = true;
+                    }
+                    let mut ambient_color = [
+                        self.voxel_settings.ambient_color[0],
+                        self.voxel_settings.ambient_color[1],
+                        self.voxel_settings.ambient_color[2],
+                    ];
+                    if ui.color_edit3("Ambient Color", &mut ambient_color) {
+                        self.voxel_settings.ambient_color[..3].copy_from_slice(&ambient_color);
+                        modified = true;
+                    }
+                    if Drag::new("Ambient Strength")
+                        .range(0.0, 1.0)
+                        .speed(0.005)
+                        .build(ui, &mut self.voxel_settings.ambient_strength)
+                    {
+                        modified = true;
+                    }
+                    let mut gbuffer_debug = self.voxel_settings.gbuffer_debug as usize;
+                    if ui.combo_simple_string(
+                        "G-Buffer View",
+                        &mut gbuffer_debug,
+                        &GBUFFER_DEBUG_MODES.map(GBufferDebug::name),
+                    ) {
+                        self.voxel_settings.gbuffer_debug = gbuffer_debug as i32;
+                        modified = true;
+                    }
+
+                    if ui.collapsing_header("Terrain", TreeNodeFlags::empty()) {
+                        let mut erosion = self.voxel_settings.erosion_enabled != 0;
+                        if ui.checkbox("Erosion Layers", &mut erosion) {
+                            self.voxel_settings.erosion_enabled = erosion as i32;
+                            modified = true;
+                        }
+                        modified |= ui.slider(
+                            "Layers",
+                            1,
+                            32,
+                            &mut self.voxel_settings.erosion_layers,
+                        );
+                        modified |= ui.slider(
+                            "Hardness",
+                            0.0,
+                            1.0,
+                            &mut self.voxel_settings.erosion_hardness,
+                        );
+                        modified |= ui.slider(
+                            "Cave Darkness",
+                            0.0,
+                            1.0,
+                            &mut self.voxel_settings.cave_darkness,
+                        );
+                        let mut cave_ambient = [
+                            self.voxel_settings.cave_ambient[0],
+                            self.voxel_settings.cave_ambient[1],
+                            self.voxel_settings.cave_ambient[2],
+                        ];
+                        if ui.color_edit3("Cave Ambient", &mut cave_ambient) {
+                            self.voxel_settings.cave_ambient[..3].copy_from_slice(&cave_ambient);
+                            modified = true;
+                        }
+                    }
+
+                    if ui.collapsing_header("Snow", TreeNodeFlags::empty()) {
+                        if ui.checkbox("Snowfall", &mut snow_accumulation.snowfall)
+                            && snow_accumulation.snowfall
+                        {
+                            snow_accumulation.restart();
+                        }
+                        ui.slider("Snow Speed", 0.0, 1.0, &mut snow_accumulation.snow_speed);
+                        modified |= ui.slider(
+                            "Snow Height",
+                            -5.0,
+                            10.0,
+                            &mut self.voxel_settings.snow_height,
+                        );
+                        modified |= ui.slider(
+                            "Snow Blend Range",
+                            0.01,
+                            4.0,
+                            &mut self.voxel_settings.snow_blend_range,
+                        );
+                        let mut snow_color = [
+                            self.voxel_settings.snow_color[0],
+                            self.voxel_settings.snow_color[1],
+                            self.voxel_settings.snow_color[2],
+                        ];
+                        if ui.color_edit3("Snow Color", &mut snow_color) {
+                            self.voxel_settings.snow_color[..3].copy_from_slice(&snow_color);
+                            modified = true;
+                        }
+                    }
+
+                    if ui.collapsing_header("Material", TreeNodeFlags::empty()) {
+                        if Drag::new("Subsurface Strength")
+                            .range(0.0, 4.0)
+                            .speed(0.01)
+                            .build(ui, &mut self.voxel_settings.sss_strength)
+                        {
+                            modified = true;
+                        }
+                        if Drag::new("Subsurface Radius")
+                            .range(0.01, 2.0)
+                            .speed(0.005)
+                            .build(ui, &mut self.voxel_settings.sss_radius)
+                        {
+                            modified = true;
+                        }
+                        let mut sss_color = [
+                            self.voxel_settings.sss_color[0],
+                            self.voxel_settings.sss_color[1],
+                            self.voxel_settings.sss_color[2],
+                        ];
+                        if ui.color_edit3("Subsurface Color", &mut sss_color) {
+                            self.voxel_settings.sss_color[..3].copy_from_slice(&sss_color);
+                            modified = true;
+                        }
+                        if Drag::new("Detail Scale")
+                            .range(0.01, 20.0)
+                            .speed(0.05)
+                            .build(ui, &mut self.voxel_settings.detail_map_scale)
+                        {
+                            modified = true;
+                        }
+                        if Drag::new("Detail Strength")
+                            .range(0.0, 1.0)
+                            .speed(0.005)
+                            .build(ui, &mut self.voxel_settings.detail_map_strength)
+                        {
+                            modified = true;
+                        }
+                    }
+
+                    if ui.collapsing_header("Sky", TreeNodeFlags::empty()) {
+                        let mut procedural_sky = self.voxel_settings.procedural_sky != 0;
+                        if ui.checkbox("Procedural Sky", &mut procedural_sky) {
+                            self.voxel_settings.procedural_sky = procedural_sky as i32;
+                            modified = true;
+                        }
+                        if Drag::new("Turbidity")
+                            .range(2.0, 10.0)
+                            .speed(0.01)
+                            .build(ui, &mut self.voxel_settings.turbidity)
+                        {
+                            modified = true;
+                        }
+                        if Drag::new("Sky Exposure")
+                            .range(0.0, 10.0)
+                            .speed(0.01)
+                            .build(ui, &mut self.voxel_settings.sky_exposure)
+                        {
+                            modified = true;
+                        }
+                        let mut horizon_color = [
+                            self.voxel_settings.sky_horizon_color[0],
+                            self.voxel_settings.sky_horizon_color[1],
+                            self.voxel_settings.sky_horizon_color[2],
+                        ];
+                        if ui.color_edit3("Horizon Color", &mut horizon_color) {
+                            self.voxel_settings.sky_horizon_color[..3]
+                                .copy_from_slice(&horizon_color);
+                            modified = true;
+                        }
+                        let mut zenith_color = [
+                            self.voxel_settings.sky_zenith_color[0],
+                            self.voxel_settings.sky_zenith_color[1],
+                            self.voxel_settings.sky_zenith_color[2],
+                        ];
+                        if ui.color_edit3("Zenith Color", &mut zenith_color) {
+                            self.voxel_settings.sky_zenith_color[..3]
+                                .copy_from_slice(&zenith_color);
+                            modified = true;
+                        }
+                        // Moving the sun by hand stops the day cycle from overriding it
+                        let (mut azimuth, mut elevation) = self.voxel_settings.light_angles();
+                        let azimuth_changed =
+                            ui.slider("Sun Azimuth", 0.0, 360.0, &mut azimuth);
+                        let elevation_changed =
+                            ui.slider("Sun Elevation", 0.0, 90.0, &mut elevation);
+                        if azimuth_changed || elevation_changed {
+                            self.voxel_settings
+                                .set_light_direction_from_angles(azimuth, elevation);
+                            time_of_day.enabled = false;
+                            modified = true;
+                        }
+                        if Drag::new("Sun Size")
+                            .range(0.0, 10.0)
+                            .speed(0.01)
+                            .build(ui, &mut self.voxel_settings.sun_size)
+                        {
+                            modified = true;
+                        }
+                        if Drag::new("Sun Bloom")
+                            .range(0.0, 5.0)
+                            .speed(0.01)
+                            .build(ui, &mut self.voxel_settings.sun_bloom_strength)
+                        {
+                            modified = true;
+                        }
+                        let mut rainbow_mode = self.voxel_settings.rainbow_mode != 0;
+                        if ui.checkbox("Rainbow Light", &mut rainbow_mode) {
+                            self.voxel_settings.rainbow_mode = rainbow_mode as i32;
+                            modified = true;
+                        }
+                        if Drag::new("Rainbow Period (s)")
+                            .range(0.1, 60.0)
+                            .speed(0.05)
+                            .build(ui, &mut self.voxel_settings.rainbow_period)
+                        {
+                            modified = true;
+                        }
+                        if Drag::new("Fog Density")
+                            .range(0.0, 0.1)
+                            .speed(0.0001)
+                            .display_format("%.4f")
+                            .build(ui, &mut self.voxel_settings.fog_density)
+                        {
+                            modified = true;
+                        }
+                        let mut fog_color = [
+                            self.voxel_settings.fog_color[0],
+                            self.voxel_settings.fog_color[1],
+                            self.voxel_settings.fog_color[2],
+                        ];
+                        if ui.color_edit3("Distance Fog Color", &mut fog_color) {
+                            self.voxel_settings.fog_color[..3].copy_from_slice(&fog_color);
+                            modified = true;
+                        }
+                    }
+
+                    let mut present_mode_index = PRESENT_MODES
+                        .iter()
+                        .position(|mode| *mode == self.surface_config.present_mode)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string(
+                        "Present Mode",
+                        &mut present_mode_index,
+                        &["Fifo", "Mailbox", "Immediate"],
+                    ) {
+                        requested_present_mode = Some(PRESENT_MODES[present_mode_index]);
+                    }
+
+                    let mut antialiasing_index = ANTIALIASING_MODES
+                        .iter()
+                        .position(|mode| *mode == self.antialiasing)
+                        .unwrap_or(0);
+                    if ui.combo_simple_string(
+                        "Antialiasing",
+                        &mut antialiasing_index,
+                        &["None", "MSAA 4x", "FXAA"],
+                    ) {
+                        requested_antialiasing = Some(ANTIALIASING_MODES[antialiasing_index]);
+                    }
+                    if self.antialiasing == AntialiasingMode::Fxaa {
+                        ui.text("FXAA also blurs text and UI drawn into the scene");
+                    }
+
+                    let mut max_fps = if target_frame_time.is_zero() {
+                        0
+                    } else {
+                        (1.0 / target_frame_time.as_secs_f32()).round() as u32
+                    };
+                    if ui.slider("Max FPS (0 = off)", 0, 240, &mut max_fps) {
+                        *target_frame_time = if max_fps == 0 {
+                            Duration::ZERO
+                        } else {
+                            Duration::from_secs(1) / max_fps
+                        };
+                    }
+                    ui.checkbox("Low Power When Unfocused", low_power_mode);
+
+                    for (_, camera) in world.query_mut::<&mut Camera>() {
+                        let mut orthographic =
+                            matches!(camera.projection, CameraProjection::Orthographic { .. });
+                        if ui.checkbox("Orthographic", &mut orthographic) {
+                            camera.projection = if orthographic {
+                                CameraProjection::Orthographic {
+                                    width: ORTHOGRAPHIC_HEIGHT * camera.aspect,
+                                    height: ORTHOGRAPHIC_HEIGHT,
+                                }
+                            } else {
+                                CameraProjection::Perspective {
+                                    fov: Rad(std::f32::consts::FRAC_PI_4),
+                                }
+                            };
+                        }
+                    }
+
+                    if ui.collapsing_header("Camera Controls", TreeNodeFlags::empty()) {
+                        for (_, controller) in world.query_mut::<&mut CameraController>() {
+                            ui.text(format!("Sprint: {:?}", controller.sprint_key));
+                            ui.text(format!("Descend: {:?}", controller.descend_key));
+                            Drag::new("Sprint Multiplier")
+                                .range(1.0, 10.0)
+                                .speed(0.05)
+                                .build(ui, &mut controller.sprint_multiplier);
+                        }
+                    }
+
+                    if ui.collapsing_header("Camera Path", TreeNodeFlags::empty()) {
+                        for (_, (transform, animation)) in
+                            world.query_mut::<(&Transform, &mut CameraAnimation)>()
+                        {
+                            ui.text(format!(
+                                "{} keyframes, {:.1} / {:.1} s",
+                                animation.keyframes.len(),
+                                animation.time,
+                                animation.duration()
+                            ));
+                            let label = if animation.playing { "Pause" } else { "Play" };
+                            if ui.button(label) {
+                                animation.playing = !animation.playing;
+                                // Restart a path that already played to the end
+                                if animation.playing && animation.time >= animation.duration() {
+                                    animation.time = 0.0;
+                                }
+                            }
+                            ui.same_line();
+                            if ui.button("Record Keyframe") {
+                                animation.record(transform);
+                            }
+                            ui.same_line();
+                            if ui.button("Clear") {
+                                *animation = CameraAnimation::default();
+                            }
+                            ui.checkbox("Loop", &mut animation.loop_);
+                        }
+                    }
+
+                    if ui.collapsing_header("Animation", TreeNodeFlags::empty()) {
+                        for (entity, (instance, controller)) in
+                            world.query_mut::<(&ModelInstance, &mut AnimationController)>()
+                        {
+                            let Some(model) = self.models.get(&instance.model) else {
+                                continue;
+                            };
+                            if model.animations.is_empty() {
+                                continue;
+                            }
+                            let clip_names: Vec<&str> = model
+                                .animations
+                                .iter()
+                                .map(|clip| clip.name.as_str())
+                                .collect();
+                            let _id = ui.push_id_usize(entity.id() as usize);
+                            if ui.combo_simple_string(
+                                "Clip",
+                                &mut controller.clip_index,
+                                &clip_names,
+                            ) {
+                                controller.time = 0.0;
+                            }
+                            ui.same_line();
+                            ui.checkbox("Playing", &mut controller.playing);
+                        }
+                    }
+
+                    if ui.collapsing_header("Scene", TreeNodeFlags::empty()) {
+                        ui.text(format!("File: {}", scene_files.path.display()));
+                        ui.text("Ctrl+S saves, Ctrl+O loads");
+                        ui.text("Recent Files");
+                        if scene_files.recent().is_empty() {
+                            ui.text_disabled("None");
+                        }
+                        let mut picked = None;
+                        for path in scene_files.recent() {
+                            if ui.selectable(path.display().to_string()) {
+                                picked = Some(path.clone());
+                            }
+                        }
+                        if picked.is_some() {
+                            scene_files.requested_load = picked;
+                        }
+                    }
+
+                    if ui.collapsing_header("Time Of Day", TreeNodeFlags::empty()) {
+                        ui.checkbox("Day Cycle", &mut time_of_day.enabled);
+                        ui.slider("Time", 0.0, 24.0, &mut time_of_day.time_of_day);
+                        Drag::new("Speed")
+                            .range(0.0, 24.0)
+                            .speed(0.01)
+                            .build(ui, &mut time_of_day.speed);
+                    }
+
+                    if ui.collapsing_header("Dynamic Resolution", TreeNodeFlags::empty()) {
+                        ui.checkbox("Enabled##Dynamic Resolution", &mut dynamic_resolution.enabled);
+                        ui.slider(
+                            "Target Frame Time (ms)",
+                            4.0,
+                            50.0,
+                            &mut dynamic_resolution.target_frame_ms,
+                        );
+                        ui.slider("Min Scale", 0.25, 1.0, &mut dynamic_resolution.min_scale);
+                        ui.slider("Max Scale", 0.25, 1.0, &mut dynamic_resolution.max_scale);
+                        ui.text(format!("Render size: {}x{}", render_size.0, render_size.1));
+                    }
+
+                    if ui.collapsing_header("Water", TreeNodeFlags::empty()) {
+                        ui.checkbox("Pause Waves", &mut water_surface.paused);
+                        ui.slider("Wave Amplitude", 0.0, 2.0, &mut water_surface.amplitude);
+                        Drag::new("Wave Frequency")
+                            .range(0.0, 10.0)
+                            .speed(0.01)
+                            .build(ui, &mut water_surface.frequency);
+                    }
+
+                    if ui.collapsing_header("SSAO", TreeNodeFlags::empty()) {
+                        let mut ssao_modified = false;
+                        ssao_modified |=
+                            ui.slider("Radius", 0.05, 4.0, &mut self.ssao_settings.radius);
+                        ssao_modified |= ui.slider("Bias", 0.0, 0.2, &mut self.ssao_settings.bias);
+                        ssao_modified |=
+                            ui.slider("Samples", 1, 64, &mut self.ssao_settings.samples);
+                        ssao_modified |=
+                            ui.slider("Strength", 0.0, 4.0, &mut self.ssao_settings.strength);
+                        if ssao_modified {
+                            self.ssao_effect.update_uniform(self.ssao_settings);
+                        }
+                    }
+
+                    if ui.collapsing_header("Volumetric Fog", TreeNodeFlags::empty()) {
+                        let fog = &mut self.fog_settings;
+                        let mut fog_modified = false;
+                        fog_modified |= Drag::new("Density")
+                            .range(0.0, 1.0)
+                            .speed(0.001)
+                            .build(ui, &mut fog.density);
+                        fog_modified |= ui.slider("Anisotropy", -0.95, 0.95, &mut fog.anisotropy);
+                        fog_modified |=
+                            ui.slider("Start Distance", 0.0, 50.0, &mut fog.start_distance);
+                        let mut fog_color = [fog.color[0], fog.color[1], fog.color[2]];
+                        if ui.color_edit3("Fog Color", &mut fog_color) {
+                            fog.color[..3].copy_from_slice(&fog_color);
+                            fog_modified = true;
+                        }
+                        if fog_modified {
+                            self.fog_effect.update_uniform(*fog);
+                        }
+                    }
+
+                    if ui.collapsing_header("Motion Blur", TreeNodeFlags::empty()) {
+                        let motion_blur = &mut self.motion_blur_settings;
+                        let mut motion_blur_modified = false;
+                        motion_blur_modified |=
+                            ui.slider("Shutter Angle", 0.0, 360.0, &mut motion_blur.shutter_angle);
+                        motion_blur_modified |=
+                            ui.slider("Samples", 2, 32, &mut motion_blur.samples);
+                        if motion_blur_modified {
+                            self.motion_blur_effect.update_uniform(*motion_blur);
+                        }
+                    }
+
+                    if ui.collapsing_header("Depth Of Field", TreeNodeFlags::empty()) {
+                        let dof = &mut self.dof_settings;
+                        let mut dof_modified = false;
+                        dof_modified |=
+                            ui.slider("Focal Distance", 0.1, 200.0, &mut dof.focal_distance);
+                        dof_modified |= ui.slider("Focal Range", 0.0, 50.0, &mut dof.focal_range);
+                        dof_modified |= ui.slider("Aperture", 0.0, 4.0, &mut dof.aperture);
+                        if dof_modified {
+                            self.dof_effect.update_uniform(*dof);
+                        }
+                    }
+
+                    if ui.collapsing_header("Lens Flare", TreeNodeFlags::empty()) {
+                        let lens_flare = &mut self.lens_flare_settings;
+                        let mut lens_flare_modified = false;
+                        lens_flare_modified |=
+                            ui.slider("Intensity", 0.0, 2.0, &mut lens_flare.intensity);
+                        lens_flare_modified |= ui.slider(
+                            "Visible Threshold",
+                            0.0,
+                            0.95,
+                            &mut lens_flare.visible_threshold,
+                        );
+                        lens_flare_modified |=
+                            ui.slider("Ghosts", 0, 12, &mut lens_flare.num_ghosts);
+                        if lens_flare_modified {
+                            self.lens_flare_effect.update_uniform(*lens_flare);
+                        }
+                    }
+
+                    if ui.collapsing_header("Bloom", TreeNodeFlags::empty()) {
+                        let bloom = &mut self.bloom_settings;
+                        let mut bloom_modified = false;
+                        bloom_modified |=
+                            ui.slider("Min Brightness", 0.0, 4.0, &mut bloom.min_brightness);
+                        bloom_modified |=
+                            ui.slider("Max Brightness", 0.0, 4.0, &mut bloom.max_brightness);
+                        bloom_modified |=
+                            ui.slider("Blur Radius", 0.0, 4.0, &mut bloom.blur_radius);
+                        let mut blur_type = bloom.blur_type as usize;
+                        if ui.combo_simple_string(
+                            "Blur",
+                            &mut blur_type,
+                            &["Gaussian", "Box", "Tent"],
+                        ) {
+                            bloom.blur_type = blur_type as u32;
+                            bloom_modified = true;
+                        }
+                        if bloom_modified {
+                            bloom.max_brightness = bloom.max_brightness.max(bloom.min_brightness);
+                            self.bloom_effect.set_settings(*bloom);
+                        }
+                        let timings = self.bloom_effect.last_timings();
+                        if timings.total() > 0.0 {
+                            ui.text(format!("GPU Time: {:.3} ms", timings.total()));
+                            ui.text(format!(
+                                "Prefilter {:.3}, Downsample {:.3}, Blur {:.3} + {:.3}, Composite {:.3}",
+                                timings.prefilter,
+                                timings.downsample,
+                                timings.horizontal_blur,
+                                timings.vertical_blur,
+                                timings.composite
+                            ));
+                        }
+                    }
+
+                    if ui.collapsing_header("Auto Exposure", TreeNodeFlags::empty()) {
+                        let exposure = &mut self.auto_exposure_settings;
+                        let mut exposure_modified = false;
+                        let mut enabled = exposure.enabled != 0;
+                        if ui.checkbox("Enabled##Auto Exposure", &mut enabled) {
+                            exposure.enabled = enabled as u32;
+                            exposure_modified = true;
+                        }
+                        exposure_modified |=
+                            ui.slider("Min Exposure", 0.01, 1.0, &mut exposure.min_exposure);
+                        exposure_modified |=
+                            ui.slider("Max Exposure", 1.0, 16.0, &mut exposure.max_exposure);
+                        exposure_modified |= ui.slider(
+                            "Adaptation Speed",
+                            0.0,
+                            10.0,
+                            &mut exposure.adaptation_speed,
+                        );
+                        if exposure_modified {
+                            self.auto_exposure_effect.update_uniform(*exposure);
+                        }
+                    }
+
+                    if ui.collapsing_header("Color Correction", TreeNodeFlags::empty()) {
+                        let cc = &mut self.color_correction_settings;
+                        let mut cc_modified = false;
+                        cc_modified |= Drag::new("Brightness")
+                            .range(0.0, 4.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.brightness);
+                        cc_modified |= Drag::new("Contrast")
+                            .range(0.0, 4.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.contrast);
+                        cc_modified |= Drag::new("Saturation")
+                            .range(0.0, 2.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.saturation);
+                        cc_modified |= Drag::new("Gamma")
+                            .range(0.1, 4.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.gamma);
+                        cc_modified |= Drag::new("Vignette Strength")
+                            .range(0.0, 1.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.vignette_strength);
+                        cc_modified |= Drag::new("Vignette Radius")
+                            .range(0.0, 2.0)
+                            .speed(0.01)
+                            .build(ui, &mut cc.vignette_radius);
+                        if cc_modified {
+                            self.color_correction_effect
+                                .update_uniform(*cc, &mut self.frame_allocator);
+                        }
+                    }
+
+                    if ui.collapsing_header("Chromatic Aberration", TreeNodeFlags::empty()) {
+                        let mut aberration = self.chromatic_aberration_effect.settings();
+                        if ui.slider("Aberration Strength", 0.0, 0.05, &mut aberration.strength) {
+                            self.chromatic_aberration_effect.update_uniform(aberration);
+                        }
+                    }
+
+                    if ui.collapsing_header("Film Grain", TreeNodeFlags::empty()) {
+                        let mut grain = self.film_grain_effect.settings();
+                        if ui.slider("Grain Strength", 0.0, 1.0, &mut grain.strength) {
+                            self.film_grain_effect.update_uniform(grain);
+                        }
+                    }
+
+                    if ui.collapsing_header("Device Info", TreeNodeFlags::empty()) {
+                        ui.text(format!("Adapter: {}", adapter_info.name));
+                        ui.text(format!("Vendor: {:#06x}", adapter_info.vendor));
+                        ui.text(format!("Backend: {:?}", adapter_info.backend));
+                        ui.text(format!("Driver: {}", adapter_info.driver_info));
+                        ui.text(format!("Render texture: {:?}", self.render_format));
+                        ui.text(format!(
+                            "Max texture dimension 2D: {}",
+                            limits.max_texture_dimension_2d
+                        ));
+                        ui.text(format!(
+                            "Max compute workgroups per dimension: {}",
+                            limits.max_compute_workgroups_per_dimension
+                        ));
+                    }
                     // // Add buttons to test mouse capture
                     // if ui.button("Test Button") {
                     //     println!("ImGui button clicked!");
@@ -998,8 +3560,60 @@ impl<'window> WgpuCtx<'window> {
             // // Show demo window (useful while developing)
             // ui.show_demo_window(&mut imgui.demo_open);
 
+            if self.show_perf_hud {
+                self.perf_stats.update_gpu_memory(&self.device);
+                let stats = &self.perf_stats;
+                let render_scale = self.render_scale;
+                let title = format!("{:.0} FPS###Performance", stats.fps());
+                ui.window(title)
+                    .size([300.0, 160.0], Condition::FirstUseEver)
+                    .opened(&mut self.show_perf_hud)
+                    .build(|| {
+                        let frame_times: Vec<f32> = stats.frame_times.iter().copied().collect();
+                        ui.plot_lines("##Frame Times", &frame_times)
+                            .graph_size([0.0, 60.0])
+                            .scale_min(0.0)
+                            .build();
+                        ui.text(format!(
+                            "Frame time: {:.2} min, {:.2} avg, {:.2} max ms",
+                            stats.min_frame_time(),
+                            stats.average_frame_time(),
+                            stats.max_frame_time()
+                        ));
+                        ui.text(format!("Render scale: {:.0}%", render_scale * 100.0));
+                        if stats.gpu_memory_used > 0 {
+                            ui.text(format!(
+                                "GPU memory: {:.1} MiB",
+                                stats.gpu_memory_used as f64 / (1024.0 * 1024.0)
+                            ));
+                        } else {
+                            ui.text("GPU memory: unavailable");
+                        }
+                    });
+            }
+
+            if self.show_render_stats {
+                let stats = self.render_stats;
+                let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+                ui.window("Render Stats")
+                    .position([ui.io().display_size[0] - 10.0, 10.0], Condition::Always)
+                    .position_pivot([1.0, 0.0])
+                    .always_auto_resize(true)
+                    .title_bar(false)
+                    .movable(false)
+                    .resizable(false)
+                    .build(|| {
+                        ui.text(format!("Draw calls: {}", stats.draw_calls));
+                        ui.text(format!("Triangles: {}", stats.triangles));
+                        ui.text(format!("Vertex data: {:.1} MiB", mib(stats.vertex_bytes)));
+                        ui.text(format!("Textures: {:.1} MiB", mib(stats.texture_bytes)));
+                        ui.text(format!("Bloom mips: {:.1} MiB", mib(stats.bloom_mip_bytes)));
+                    });
+            }
+
             if modified {
-                self.queue.write_buffer(
+                self.voxel_settings.validate();
+                self.frame_allocator.write(
                     &self.voxel_settings_buffer,
                     0,
                     bytemuck::cast_slice(&[self.voxel_settings]),
@@ -1010,7 +3624,7 @@ impl<'window> WgpuCtx<'window> {
         // Update cursor if changed
         if self.imgui.last_cursor != ui.mouse_cursor() {
             self.imgui.last_cursor = ui.mouse_cursor();
-            self.imgui.platform.prepare_render(ui, window);
+            self.imgui.platform.prepare_render(ui, &self.window);
         }
 
         // Render ImGui UI on top of the scene
@@ -1039,33 +3653,147 @@ impl<'window> WgpuCtx<'window> {
 
         self.queue.submit(Some(encoder.finish()));
         surface_texture.present();
+
+        if let Some(present_mode) = requested_present_mode {
+            self.set_present_mode(present_mode);
+        }
+        if let Some(mode) = requested_antialiasing {
+            self.set_antialiasing_mode(mode);
+        }
+    }
+}
+
+fn is_transparent(alpha_mode: wgpu::CompositeAlphaMode) -> bool {
+    matches!(
+        alpha_mode,
+        wgpu::CompositeAlphaMode::PreMultiplied | wgpu::CompositeAlphaMode::PostMultiplied
+    )
+}
+
+/// Picks the format of the scene, normal/depth and post-processing targets, which are
+/// rendered to and sampled with filtering, the post-processing one is also written from
+/// compute. Rgba32Float falls back to Rgba16Float where it can't be, anything else fails
+/// early with a readable message instead of an opaque device or validation error
+fn check_render_texture_support(
+    adapter: &wgpu::Adapter,
+    requested: RenderTextureFormat,
+) -> wgpu::TextureFormat {
+    let name = adapter.get_info().name;
+    let required_usages =
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::STORAGE_BINDING;
+    let usable = |format: wgpu::TextureFormat| {
+        adapter
+            .get_texture_format_features(format)
+            .allowed_usages
+            .contains(required_usages)
+    };
+    let float32_filterable = adapter
+        .features()
+        .contains(wgpu::Features::FLOAT32_FILTERABLE);
+
+    let mut format = requested.texture_format();
+    if requested == RenderTextureFormat::F32 && !(usable(format) && float32_filterable) {
+        format = RenderTextureFormat::F16.texture_format();
+        tracing::warn!(
+            adapter = %name,
+            requested = ?requested.texture_format(),
+            chosen = ?format,
+            "Render texture format is not supported"
+        );
     }
+    if !usable(format) {
+        panic!(
+            "Adapter \"{}\" can't render to {:?} textures (supported usages: {:?})",
+            name,
+            format,
+            adapter.get_texture_format_features(format).allowed_usages
+        );
+    }
+    format
 }
 
 fn create_pipeline(
     device: &wgpu::Device,
     swap_chain_format: wgpu::TextureFormat,
     pipeline_layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    sample_count: u32,
+    cache: Option<&wgpu::PipelineCache>,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("voxels.wgsl"))),
-    });
-
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: None,
         layout: Some(pipeline_layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: Some("vs_main"),
             buffers: &[create_vertex_buffer_layout()],
             compilation_options: Default::default(),
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            // Second target carries normal and hit distance for SSAO, third the velocity
+            targets: &[
+                Some(swap_chain_format.into()),
+                Some(swap_chain_format.into()),
+                Some(wgpu::TextureFormat::Rgba16Float.into()),
+            ],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache,
+    })
+}
+
+fn create_model_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    render_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Model Pipeline"),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[
+                create_vertex_buffer_layout(),
+                create_instance_vertex_buffer_layout(),
+                create_skin_vertex_buffer_layout(),
+            ],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
             entry_point: Some("fs_main"),
             compilation_options: Default::default(),
-            targets: &[Some(swap_chain_format.into())],
+            targets: &[
+                Some(render_format.into()),
+                Some(render_format.into()),
+                Some(wgpu::TextureFormat::Rgba16Float.into()),
+            ],
         }),
         primitive: wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleList,
@@ -1083,7 +3811,10 @@ fn create_pipeline(
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
-        multisample: wgpu::MultisampleState::default(),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
         multiview: None,
         cache: None,
     })
@@ -21,12 +21,13 @@ pub fn setup_camera_entity(world: &mut World, window_size: Option<(u32, u32)>) -
             ..Default::default()
         },
         CameraController::default(),
+        CameraAnimation::default(),
     ))
 }
 
 pub fn spawn_model_entity(
     world: &mut World,
-    model_index: usize,
+    model: ModelKey,
     position: Point3<f32>,
 ) -> hecs::Entity {
     world.spawn((
@@ -34,6 +35,6 @@ pub fn spawn_model_entity(
             position,
             ..Default::default()
         },
-        ModelInstance { model: model_index },
+        ModelInstance { model },
     ))
 }